@@ -1,3 +1,3 @@
-bash makeAndRun.sh | grep "A: " > log.out
+cargo run -- "tests/blargg-gb/cpu_instrs/cpu_instrs.gb" --trace log.out --trace-doctor
 
-python3 compareTrace.py | less
+cargo run --bin trace-diff -- expected/gameboy-doctor.log log.out | less
@@ -0,0 +1,123 @@
+//! Compares two gameboy-doctor style trace logs and reports the first instruction where
+//! they differ, e.g. `trace-diff expected.log rustboy.log --context 10`. Anything after a
+//! `;` (like the labels rustboy adds when it has a .sym file) is ignored.
+//!
+//! Exits with 0 if the traces match, 1 if they differ and 2 on bad arguments.
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process;
+
+const DEFAULT_CONTEXT: usize = 5;
+
+/// Fields (like `A:01`) whose values differ between the two lines
+fn field_diffs(expected: &str, actual: &str) -> Vec<String> {
+    let mut res = vec![];
+    for (e, a) in expected.split_whitespace().zip(actual.split_whitespace()) {
+        if e != a {
+            let name = e.split(':').next().unwrap_or(e);
+            let expected_value = e.splitn(2, ':').nth(1).unwrap_or(e);
+            let actual_value = a.splitn(2, ':').nth(1).unwrap_or(a);
+            res.push(format!("{}: expected {}, got {}", name, expected_value, actual_value));
+        }
+    }
+    return res;
+}
+
+/// The part of a line that gets compared
+fn state(line: &str) -> &str {
+    return line.split(';').next().unwrap_or("").trim();
+}
+
+fn open(path: &str) -> std::io::Lines<BufReader<fs::File>> {
+    let file = fs::File::open(path).unwrap_or_else(|e| {
+        eprintln!("Couldn't open {}: {}", path, e);
+        process::exit(2);
+    });
+    return BufReader::new(file).lines();
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: trace-diff <expected.log> <actual.log> [--context N]");
+    process::exit(2);
+}
+
+fn main() {
+    let mut files = vec![];
+    let mut context = DEFAULT_CONTEXT;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context = args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()),
+            "--help" | "-h" => usage(),
+            _ => files.push(arg)
+        }
+    }
+    if files.len() != 2 {
+        usage();
+    }
+
+    let mut expected = open(&files[0]);
+    let mut actual = open(&files[1]);
+    let mut history = VecDeque::with_capacity(context);
+    let mut line = 0;
+
+    loop {
+        line += 1;
+        let e = expected.next().map(|l| l.unwrap_or_default());
+        let a = actual.next().map(|l| l.unwrap_or_default());
+
+        let (e, a) = match (e, a) {
+            (None, None) => {
+                println!("Traces match ({} instructions)", line - 1);
+                process::exit(0);
+            },
+            (Some(_), None) => {
+                println!("{} ends at line {}, {} keeps going", files[1], line, files[0]);
+                process::exit(1);
+            },
+            (None, Some(_)) => {
+                println!("{} ends at line {}, {} keeps going", files[0], line, files[1]);
+                process::exit(1);
+            },
+            (Some(e), Some(a)) => (e, a)
+        };
+
+        if state(&e) == state(&a) {
+            if context > 0 {
+                if history.len() == context {
+                    history.pop_front();
+                }
+                history.push_back(e);
+            }
+            continue;
+        }
+
+        println!("First difference at line {}\n", line);
+        for (i, previous) in history.iter().enumerate() {
+            println!("  {:>8}  {}", line - history.len() + i, previous);
+        }
+        println!("- {:>8}  {}", line, e);
+        println!("+ {:>8}  {}\n", line, a);
+        for diff in field_diffs(state(&e), state(&a)) {
+            println!("  {}", diff);
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_diffs, state};
+
+    #[test]
+    fn test_field_diffs() {
+        let expected = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02";
+        let actual = "A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:00,C3,13,02";
+        assert_eq!(vec!["F: expected B0, got 80", "PC: expected 0100, got 0101"], field_diffs(expected, actual));
+        assert!(field_diffs(expected, expected).is_empty());
+        assert_eq!(expected, state(&format!("{} ; Main.loop+2", expected)));
+    }
+}
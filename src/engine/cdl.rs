@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::Path;
+
+/// Flags for each ROM byte, a byte can have several
+pub const OPCODE: u8 = 0x01;
+/// immediate value or address following an opcode
+pub const OPERAND: u8 = 0x02;
+/// read by an instruction like `ld a, [hl]`
+pub const DATA: u8 = 0x04;
+/// copied to OAM by DMA
+pub const DMA: u8 = 0x08;
+
+/// Code/data log: one byte of flags per byte of the ROM file, so it's bank-aware and
+/// the `.cdl` file on disk is the same size as the ROM
+pub struct CodeDataLog {
+    pub flags: Vec<u8>
+}
+
+impl CodeDataLog {
+    pub fn make_code_data_log(rom_size: usize) -> CodeDataLog {
+        return CodeDataLog {
+            flags: vec![0; rom_size]
+        };
+    }
+
+    /// Starts from the log at `path` if there is one, so runs add up
+    pub fn load(path: &Path, rom_size: usize) -> std::io::Result<CodeDataLog> {
+        let mut res = CodeDataLog::make_code_data_log(rom_size);
+        if path.exists() {
+            res.merge(&fs::read(path)?)?;
+        }
+        return Ok(res);
+    }
+
+    /// Adds the flags from another log of the same ROM
+    pub fn merge(&mut self, other: &[u8]) -> std::io::Result<()> {
+        if other.len() != self.flags.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("code/data log is {} bytes but the rom is {}", other.len(), self.flags.len())));
+        }
+        for (flags, other) in self.flags.iter_mut().zip(other.iter()) {
+            *flags |= other;
+        }
+        return Ok(());
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        return fs::write(path, &self.flags);
+    }
+
+    /// Marks the ROM byte at `addr` with `rom_bank` mapped at 0x4000-0x7FFF, anything outside of ROM is ignored
+    pub fn log(&mut self, addr: u16, rom_bank: u16, flag: u8) {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            0x4000..=0x7FFF => rom_bank as usize * 0x4000 + (addr as usize - 0x4000),
+            _ => return
+        };
+        if self.flags.is_empty() {
+            return;
+        }
+        // banks past the end of the ROM wrap around like they do on the cartridge
+        let offset = offset % self.flags.len();
+        self.flags[offset] |= flag;
+    }
+
+    /// How many bytes have any of `flags` set
+    pub fn count(&self, flags: u8) -> usize {
+        return self.flags.iter().filter(|f| **f & flags != 0).count();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::cdl::{CodeDataLog, OPCODE, OPERAND, DATA};
+
+    #[test]
+    fn test_log_and_merge() {
+        let mut cdl = CodeDataLog::make_code_data_log(0x10000);
+        cdl.log(0x0100, 3, OPCODE);
+        cdl.log(0x4001, 3, OPERAND);
+        cdl.log(0x4001, 2, DATA);
+        cdl.log(0xC000, 3, DATA);
+        assert_eq!(OPCODE, cdl.flags[0x0100]);
+        assert_eq!(OPERAND, cdl.flags[0xC001]);
+        assert_eq!(DATA, cdl.flags[0x8001]);
+        assert_eq!(3, cdl.count(OPCODE | OPERAND | DATA));
+
+        let mut other = vec![0; 0x10000];
+        other[0x0100] = DATA;
+        cdl.merge(&other).unwrap();
+        assert_eq!(OPCODE | DATA, cdl.flags[0x0100]);
+        assert!(cdl.merge(&[0; 0x8000]).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::Path;
+
+use crate::engine::memory::Memory;
+use crate::engine::hooks::HookId;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheatCode {
+    /// ROM reads of `addr` see `value`, only when the ROM has `compare` there if it's given
+    GameGenie { addr: u16, value: u8, compare: Option<u8> },
+    /// `value` gets written to `addr` every VBlank
+    GameShark { addr: u16, value: u8 }
+}
+
+impl CheatCode {
+    /// `ABC-DEF-GHI` or `ABC-DEF` Game Genie codes, `01VVAAAA` GameShark codes
+    pub fn parse(text: &str) -> Result<CheatCode, String> {
+        let digits: String = text.chars().filter(|c| *c != '-').collect();
+        let nibbles: Vec<u8> = digits.chars().map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(format!("{} isn't a cheat code", text))?;
+
+        return match (nibbles.len(), text.contains('-')) {
+            (8, false) => {
+                // 01 VV LL HH, the type byte picks the WRAM bank on the GBC
+                let byte = |i: usize| (nibbles[i] << 4) | nibbles[i + 1];
+                Ok(CheatCode::GameShark {
+                    value: byte(2),
+                    addr: ((byte(6) as u16) << 8) | byte(4) as u16
+                })
+            },
+            (6, true) | (9, true) => {
+                // AB is the value, FCDE the address with F inverted
+                let value = (nibbles[0] << 4) | nibbles[1];
+                let addr = (((nibbles[5] ^ 0xF) as u16) << 12) | ((nibbles[2] as u16) << 8)
+                    | ((nibbles[3] as u16) << 4) | nibbles[4] as u16;
+                if addr >= 0x8000 {
+                    return Err(format!("{} patches {:04X}, which isn't ROM", text, addr));
+                }
+                // GI is the compare value xored with BA and rotated left 2, H isn't used
+                let compare = if nibbles.len() == 9 {
+                    let scrambled = (nibbles[6] << 4) | nibbles[8];
+                    Some(scrambled.rotate_right(2) ^ 0xBA)
+                } else {
+                    None
+                };
+                Ok(CheatCode::GameGenie { addr: addr, value: value, compare: compare })
+            },
+            _ => Err(format!("{} isn't a Game Genie (ABC-DEF-GHI) or GameShark (01VVAAAA) code", text))
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub description: String,
+    /// a cheat can need several codes, `+` separated in the file
+    pub codes: Vec<CheatCode>,
+    pub enabled: bool
+}
+
+/// The cheats for a ROM, in RetroArch's `.cht` format:
+///
+/// ```text
+/// cheats = 1
+/// cheat0_desc = "Infinite lives"
+/// cheat0_code = "010338C1"
+/// cheat0_enable = true
+/// ```
+#[derive(Debug, Default)]
+pub struct Cheats {
+    pub cheats: Vec<Cheat>,
+    /// memory hooks doing the enabled Game Genie patches
+    pub(crate) hook_ids: Vec<HookId>,
+    /// `GPU::frames` when the GameShark codes were last written
+    pub(crate) last_frame: u64
+}
+
+impl Cheats {
+    pub fn make_cheats() -> Cheats {
+        return Cheats::default();
+    }
+
+    pub fn load(path: &Path) -> Result<Cheats, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        return Cheats::parse(&text);
+    }
+
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut res = Cheats::make_cheats();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim().trim_matches('"')),
+                _ => continue
+            };
+            if !key.starts_with("cheat") || key == "cheats" {
+                continue;
+            }
+            let mut key = key["cheat".len()..].splitn(2, '_');
+            let (index, field) = match (key.next().and_then(|i| i.parse::<usize>().ok()), key.next()) {
+                (Some(index), Some(field)) => (index, field),
+                _ => continue
+            };
+            while res.cheats.len() <= index {
+                res.cheats.push(Cheat { description: String::new(), codes: vec![], enabled: false });
+            }
+            let cheat = &mut res.cheats[index];
+            match field {
+                "desc" => cheat.description = value.to_string(),
+                "enable" => cheat.enabled = value == "true",
+                "code" => {
+                    cheat.codes = value.split('+').map(|code| CheatCode::parse(code.trim()))
+                        .collect::<Result<Vec<CheatCode>, String>>()
+                        .map_err(|e| format!("cheat{}: {}", index, e))?;
+                },
+                _ => {}
+            }
+        }
+        return Ok(res);
+    }
+
+    /// Enabled Game Genie patches
+    pub fn rom_patches(&self) -> Vec<CheatCode> {
+        return self.enabled_codes().filter(|code| match code {
+            CheatCode::GameGenie { .. } => true,
+            _ => false
+        }).collect();
+    }
+
+    pub fn has_ram_writes(&self) -> bool {
+        return self.enabled_codes().any(|code| match code {
+            CheatCode::GameShark { .. } => true,
+            _ => false
+        });
+    }
+
+    /// Does the enabled GameShark writes, the engine calls this every VBlank
+    pub fn write_ram(&self, memory: &mut dyn Memory) {
+        for code in self.enabled_codes() {
+            if let CheatCode::GameShark { addr, value } = code {
+                memory.set(addr, value);
+            }
+        }
+    }
+
+    fn enabled_codes<'a>(&'a self) -> impl Iterator<Item = CheatCode> + 'a {
+        return self.cheats.iter().filter(|cheat| cheat.enabled).flat_map(|cheat| cheat.codes.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::cheats::{Cheats, CheatCode};
+
+    #[test]
+    fn test_parse_codes() {
+        assert_eq!(Ok(CheatCode::GameShark { addr: 0xC138, value: 0x03 }), CheatCode::parse("010338C1"));
+        assert_eq!(Ok(CheatCode::GameGenie { addr: 0x4A17, value: 0x00, compare: Some(0xC8) }), CheatCode::parse("00A-17B-C49"));
+        assert_eq!(Ok(CheatCode::GameGenie { addr: 0x1234, value: 0x3E, compare: None }), CheatCode::parse("3E2-34E"));
+        assert!(CheatCode::parse("010338").is_err());
+        assert!(CheatCode::parse("ZZA-17B-C49").is_err());
+        // F inverted is 8, which is VRAM
+        assert!(CheatCode::parse("3E2-347").is_err());
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let cheats = Cheats::parse("cheats = 2\n\n\
+            cheat0_desc = \"Infinite lives\"\n\
+            cheat0_code = \"010338C1\"\n\
+            cheat0_enable = true\n\
+            cheat1_desc = \"Jump higher\"\n\
+            cheat1_code = \"3E2-34E+00A-17B-C49\"\n\
+            cheat1_enable = false\n").unwrap();
+
+        assert_eq!(2, cheats.cheats.len());
+        assert_eq!("Infinite lives", cheats.cheats[0].description);
+        assert_eq!(2, cheats.cheats[1].codes.len());
+        assert!(cheats.has_ram_writes());
+        assert!(cheats.rom_patches().is_empty());
+
+        assert!(Cheats::parse("cheat0_code = \"nope\"").is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::fmt;
 
 use crate::engine::memory::Memory;
+use crate::engine::state::{StateWriter, StateReader};
 
 #[derive(Debug)]
 pub struct Clock {
@@ -13,6 +14,17 @@ impl Clock {
         return Clock {time: 0, div_clock:0}
     }
 
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.u32(self.time);
+        out.u32(self.div_clock);
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.time = input.u32()?;
+        self.div_clock = input.u32()?;
+        return Ok(());
+    }
+
     pub fn tick(&mut self, memory: &mut Box<dyn Memory>, ticks: u32) {
         self.div_clock += ticks;
 
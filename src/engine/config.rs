@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+use sdl2::keyboard::Keycode;
+
+use crate::engine::engine::KeyNames;
+use crate::engine::controller::{PadInput, DEFAULT_DEADZONE, parse_pad_input};
+use crate::engine::input::DEFAULT_AUTOFIRE_RATE;
+use crate::engine::gpu::{Palette, PALETTES, parse_palette};
+
+/// Window scale when the config doesn't say, 800x720
+pub const DEFAULT_SCALE: u32 = 5;
+
+/// Something a key can be bound to in `Engine::run`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Button(KeyNames),
+    Quit,
+    Screenshot,
+    Pause,
+    FrameAdvance,
+    FastForward,
+    Turbo,
+    Rewind,
+    /// turn cheat n (from 0) on or off
+    Cheat(usize),
+    /// press A or B over and over while held
+    Autofire(KeyNames),
+    /// start or stop recording a macro
+    RecordMacro,
+    /// play macro n (from 0)
+    Macro(usize)
+}
+
+impl Action {
+    /// Whether a held key keeps doing it
+    pub fn repeats(&self) -> bool {
+        return matches!(self, Action::Button(_) | Action::FrameAdvance | Action::Autofire(_));
+    }
+}
+
+/// Section and name of every action in the config file, with its default keys and
+/// controller inputs
+const ACTIONS: [(&str, &str, Action, &str, &str); 31] = [
+    ("buttons", "a", Action::Button(KeyNames::A), "F", "b"),
+    ("buttons", "b", Action::Button(KeyNames::B), "D", "a"),
+    ("buttons", "start", Action::Button(KeyNames::START), "R", "start"),
+    ("buttons", "select", Action::Button(KeyNames::SELECT), "E", "back"),
+    ("buttons", "up", Action::Button(KeyNames::UP), "Up", "dpup, lefty-"),
+    ("buttons", "down", Action::Button(KeyNames::DOWN), "Down", "dpdown, lefty+"),
+    ("buttons", "left", Action::Button(KeyNames::LEFT), "Left", "dpleft, leftx-"),
+    ("buttons", "right", Action::Button(KeyNames::RIGHT), "Right", "dpright, leftx+"),
+    ("hotkeys", "quit", Action::Quit, "Escape", ""),
+    ("hotkeys", "screenshot", Action::Screenshot, "Space", ""),
+    ("hotkeys", "pause", Action::Pause, "P", ""),
+    ("hotkeys", "frame_advance", Action::FrameAdvance, "N", ""),
+    ("hotkeys", "fast_forward", Action::FastForward, "Tab", "righttrigger"),
+    ("hotkeys", "turbo", Action::Turbo, "T", ""),
+    ("hotkeys", "rewind", Action::Rewind, "Backspace", "lefttrigger"),
+    ("hotkeys", "cheat1", Action::Cheat(0), "F1", ""),
+    ("hotkeys", "cheat2", Action::Cheat(1), "F2", ""),
+    ("hotkeys", "cheat3", Action::Cheat(2), "F3", ""),
+    ("hotkeys", "cheat4", Action::Cheat(3), "F4", ""),
+    ("hotkeys", "cheat5", Action::Cheat(4), "F5", ""),
+    ("hotkeys", "cheat6", Action::Cheat(5), "F6", ""),
+    ("hotkeys", "cheat7", Action::Cheat(6), "F7", ""),
+    ("hotkeys", "cheat8", Action::Cheat(7), "F8", ""),
+    ("hotkeys", "cheat9", Action::Cheat(8), "F9", ""),
+    ("hotkeys", "autofire_a", Action::Autofire(KeyNames::A), "G", ""),
+    ("hotkeys", "autofire_b", Action::Autofire(KeyNames::B), "S", ""),
+    ("hotkeys", "record_macro", Action::RecordMacro, "M", ""),
+    ("hotkeys", "macro1", Action::Macro(0), "1", ""),
+    ("hotkeys", "macro2", Action::Macro(1), "2", ""),
+    ("hotkeys", "macro3", Action::Macro(2), "3", ""),
+    ("hotkeys", "macro4", Action::Macro(3), "4", "")
+];
+
+/// Key and controller bindings, from an INI file like
+///
+/// ```text
+/// [buttons]
+/// a = F, Space
+/// start = Return
+///
+/// [hotkeys]
+/// screenshot = F12
+/// rewind =
+///
+/// [controller]
+/// a = a
+/// b = x
+/// turbo = rightshoulder
+/// mappings = gamecontrollerdb.txt
+///
+/// [autofire]
+/// a = 20
+///
+/// [display]
+/// scale = 4
+/// palette = green
+/// screenshots = ~/Pictures
+/// ```
+///
+/// Actions the file doesn't mention keep their defaults, an empty value unbinds one.
+/// Keys are letters, digits or `Keycode` names from `NAMED_KEYS` (`F5`, `Return`, `LShift`, `KpPlus`...).
+/// `[controller]` binds any action by name to controller inputs for `parse_pad_input`, and can
+/// point at an SDL `gamecontrollerdb.txt` for controllers SDL doesn't know. `[autofire]` sets
+/// how many times a second autofire presses A and B, and `[display]` the window and where
+/// screenshots go, with `~/` for the home directory.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// keys for each entry in `ACTIONS`
+    keys: Vec<Vec<Keycode>>,
+    bindings: HashMap<Keycode, Action>,
+    /// controller inputs for each entry in `ACTIONS`
+    pads: Vec<Vec<PadInput>>,
+    pad_bindings: HashMap<PadInput, Action>,
+    /// extra controller mappings to give SDL
+    pub controller_mappings: Option<PathBuf>,
+    /// stick travel before it counts as a direction
+    pub deadzone: i16,
+    /// autofire presses a second for A and B
+    pub autofire_rates: [u32; 2],
+    /// window size in Game Boy pixels
+    pub scale: u32,
+    pub palette: Palette,
+    /// where the screenshot hotkey saves
+    pub screenshot_dir: PathBuf
+}
+
+impl Config {
+    pub fn make_config() -> Config {
+        let keys = ACTIONS.iter().map(|(_, _, _, key, _)| vec![parse_key(key).unwrap()]).collect();
+        let pads = ACTIONS.iter().map(|(_, _, _, _, inputs)| parse_list(inputs, parse_pad_input).unwrap()).collect();
+        return Config::with_bindings(keys, pads).unwrap();
+    }
+
+    fn with_bindings(keys: Vec<Vec<Keycode>>, pads: Vec<Vec<PadInput>>) -> Result<Config, String> {
+        return Ok(Config {
+            bindings: bind(&keys)?,
+            pad_bindings: bind(&pads)?,
+            keys: keys,
+            pads: pads,
+            controller_mappings: None,
+            deadzone: DEFAULT_DEADZONE,
+            autofire_rates: [DEFAULT_AUTOFIRE_RATE, DEFAULT_AUTOFIRE_RATE],
+            scale: DEFAULT_SCALE,
+            palette: PALETTES[0].1,
+            screenshot_dir: PathBuf::from("screenshots")
+        });
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        let mut config = Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        // relative to the config file
+        config.controller_mappings = config.controller_mappings
+            .map(|mappings| path.parent().map_or(mappings.clone(), |dir| dir.join(&mappings)));
+        return Ok(config);
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let defaults = Config::make_config();
+        let mut keys = defaults.keys;
+        let mut pads = defaults.pads;
+        let mut controller_mappings = None;
+        let mut deadzone = DEFAULT_DEADZONE;
+        let mut autofire_rates = [DEFAULT_AUTOFIRE_RATE, DEFAULT_AUTOFIRE_RATE];
+        let mut scale = DEFAULT_SCALE;
+        let mut palette = PALETTES[0].1;
+        let mut screenshot_dir = None;
+        let mut section = String::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_lowercase();
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.trim().to_lowercase(), value.trim()),
+                _ => return Err(format!("line {}: expected name = keys", line_number + 1))
+            };
+            let error = |message: String| format!("line {}: {}", line_number + 1, message);
+            if section == "controller" {
+                match name.as_str() {
+                    "mappings" => controller_mappings = Some(PathBuf::from(value)),
+                    "deadzone" => deadzone = value.parse().ok().filter(|d: &i16| *d >= 0)
+                        .ok_or(error(format!("bad deadzone {}", value)))?,
+                    _ => {
+                        let index = ACTIONS.iter().position(|(_, n, _, _, _)| *n == name)
+                            .ok_or(error(format!("no action {}", name)))?;
+                        pads[index] = parse_list(value, parse_pad_input).map_err(|input| error(format!("unknown controller input {}", input)))?;
+                    }
+                }
+                continue;
+            }
+            if section == "display" {
+                match name.as_str() {
+                    "scale" => scale = parse_scale(value).map_err(error)?,
+                    "palette" => palette = parse_palette(value).map_err(error)?,
+                    "screenshots" => screenshot_dir = Some(expand_home(value)),
+                    _ => return Err(error(format!("no {} in [display]", name)))
+                }
+                continue;
+            }
+            if section == "autofire" {
+                let slot = match name.as_str() {
+                    "a" => 0,
+                    "b" => 1,
+                    _ => return Err(error(format!("no {} in [autofire]", name)))
+                };
+                autofire_rates[slot] = value.parse().ok().filter(|rate| (1..=30).contains(rate))
+                    .ok_or(error(format!("autofire rate {} isn't 1 to 30 presses a second", value)))?;
+                continue;
+            }
+            let index = ACTIONS.iter().position(|(s, n, _, _, _)| *s == section && *n == name)
+                .ok_or(error(format!("no {} in [{}]", name, section)))?;
+            keys[index] = parse_list(value, parse_key).map_err(|key| error(format!("unknown key {}", key)))?;
+        }
+        let mut config = Config::with_bindings(keys, pads)?;
+        config.controller_mappings = controller_mappings;
+        config.deadzone = deadzone;
+        config.autofire_rates = autofire_rates;
+        config.scale = scale;
+        config.palette = palette;
+        config.screenshot_dir = screenshot_dir.unwrap_or(config.screenshot_dir);
+        return Ok(config);
+    }
+
+    /// `$XDG_CONFIG_HOME/rustboy/config.ini`, or `~/.config/rustboy/config.ini`
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+            .or(env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        return Some(base.join("rustboy").join("config.ini"));
+    }
+
+    pub fn action(&self, key: Keycode) -> Option<Action> {
+        return self.bindings.get(&key).cloned();
+    }
+
+    pub fn pad_action(&self, input: PadInput) -> Option<Action> {
+        return self.pad_bindings.get(&input).cloned();
+    }
+
+    /// Keys bound to `action`
+    pub fn keys(&self, action: Action) -> Vec<Keycode> {
+        return ACTIONS.iter().position(|(_, _, a, _, _)| *a == action).map_or(vec![], |i| self.keys[i].clone());
+    }
+
+    /// Controller inputs bound to `action`
+    pub fn pad_inputs(&self, action: Action) -> Vec<PadInput> {
+        return ACTIONS.iter().position(|(_, _, a, _, _)| *a == action).map_or(vec![], |i| self.pads[i].clone());
+    }
+}
+
+/// `value` as a path, with a leading `~/` meaning the home directory
+fn expand_home(value: &str) -> PathBuf {
+    if let (Some(rest), Some(home)) = (value.strip_prefix("~/"), env::var_os("HOME")) {
+        return Path::new(&home).join(rest);
+    }
+    return PathBuf::from(value);
+}
+
+/// A window scale from 1 to 20
+pub fn parse_scale(value: &str) -> Result<u32, String> {
+    return value.parse().ok().filter(|scale| (1..=20).contains(scale))
+        .ok_or(format!("scale {} isn't 1 to 20", value));
+}
+
+/// Each entry in `ACTIONS` for its inputs, errors if one input has two actions
+fn bind<T: Copy + Eq + Hash + Debug>(inputs: &[Vec<T>]) -> Result<HashMap<T, Action>, String> {
+    let mut bindings = HashMap::new();
+    for (i, action_inputs) in inputs.iter().enumerate() {
+        for input in action_inputs.iter() {
+            if let Some(other) = bindings.insert(*input, ACTIONS[i].2) {
+                let other_name = ACTIONS.iter().find(|(_, _, action, _, _)| *action == other).map_or("", |a| a.1);
+                return Err(format!("{:?} is bound to both {} and {}", input, other_name, ACTIONS[i].1));
+            }
+        }
+    }
+    return Ok(bindings);
+}
+
+/// Comma separated names, or the first one `parse` doesn't know
+fn parse_list<T>(value: &str, parse: fn(&str) -> Option<T>) -> Result<Vec<T>, String> {
+    return value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty())
+        .map(|name| parse(name).ok_or(name.to_string()))
+        .collect();
+}
+
+/// Keys other than letters and digits that can be bound, by their `Keycode` name
+const NAMED_KEYS: [Keycode; 61] = [
+    Keycode::Backspace, Keycode::Tab, Keycode::Return, Keycode::Escape, Keycode::Space,
+    Keycode::Up, Keycode::Down, Keycode::Left, Keycode::Right, Keycode::F1, Keycode::F2,
+    Keycode::F3, Keycode::F4, Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8, Keycode::F9,
+    Keycode::F10, Keycode::F11, Keycode::F12, Keycode::LShift, Keycode::RShift, Keycode::LCtrl,
+    Keycode::RCtrl, Keycode::LAlt, Keycode::RAlt, Keycode::CapsLock, Keycode::Comma,
+    Keycode::Period, Keycode::Slash, Keycode::Semicolon, Keycode::Quote, Keycode::Minus,
+    Keycode::Equals, Keycode::LeftBracket, Keycode::RightBracket, Keycode::Backslash,
+    Keycode::Backquote, Keycode::Insert, Keycode::Delete, Keycode::Home, Keycode::End,
+    Keycode::PageUp, Keycode::PageDown, Keycode::Kp0, Keycode::Kp1, Keycode::Kp2, Keycode::Kp3,
+    Keycode::Kp4, Keycode::Kp5, Keycode::Kp6, Keycode::Kp7, Keycode::Kp8, Keycode::Kp9,
+    Keycode::KpPlus, Keycode::KpMinus, Keycode::KpMultiply, Keycode::KpDivide, Keycode::KpEnter,
+    Keycode::KpPeriod
+];
+
+/// A `Keycode` from its name in any case: a letter, a digit or one of `NAMED_KEYS`
+pub fn parse_key(name: &str) -> Option<Keycode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // letters and digits are their lower case ASCII code
+        if c.is_ascii_alphanumeric() {
+            return Keycode::from_i32(c.to_ascii_lowercase() as i32);
+        }
+    }
+    let wanted = name.to_lowercase();
+    return NAMED_KEYS.iter().cloned().find(|key| format!("{:?}", key).to_lowercase() == wanted);
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::controller::{Axis, Button};
+    use sdl2::keyboard::Keycode;
+    use crate::engine::config::{Config, Action, parse_key};
+    use crate::engine::controller::PadInput;
+    use crate::engine::gpu::PALETTES;
+    use std::env;
+    use std::path::{Path, PathBuf};
+    use crate::engine::engine::KeyNames;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(Some(Keycode::A), parse_key("a"));
+        assert_eq!(Some(Keycode::A), parse_key("A"));
+        assert_eq!(Some(Keycode::Num1), parse_key("1"));
+        assert_eq!(Some(Keycode::F12), parse_key("F12"));
+        assert_eq!(Some(Keycode::LShift), parse_key("lshift"));
+        assert_eq!(Some(Keycode::Return), parse_key("Return"));
+        assert_eq!(None, parse_key("Nope"));
+        assert_eq!(None, parse_key("+"));
+    }
+
+    #[test]
+    fn test_bindings() {
+        let config = Config::make_config();
+        assert_eq!(Some(Action::Button(KeyNames::A)), config.action(Keycode::F));
+        assert_eq!(Some(Action::Cheat(8)), config.action(Keycode::F9));
+
+        let config = Config::parse("# mine\n[buttons]\na = X, Z\nstart = Return\n\n[hotkeys]\nrewind =\n").unwrap();
+        assert_eq!(Some(Action::Button(KeyNames::A)), config.action(Keycode::Z));
+        assert_eq!(None, config.action(Keycode::F));
+        assert_eq!(None, config.action(Keycode::Backspace));
+        assert_eq!(vec![Keycode::X, Keycode::Z], config.keys(Action::Button(KeyNames::A)));
+        assert_eq!(Some(Action::Pause), config.action(Keycode::P));
+
+        assert_eq!(Some("Space is bound to both b and screenshot".to_string()), Config::parse("[buttons]\nb = Space").err());
+        assert!(Config::parse("[buttons]\nturbo = Y").is_err());
+        assert!(Config::parse("[buttons]\na = Nope").is_err());
+    }
+
+    #[test]
+    fn test_controller_bindings() {
+        let config = Config::make_config();
+        assert_eq!(Some(Action::Button(KeyNames::A)), config.pad_action(PadInput::Button(Button::B)));
+        assert_eq!(Some(Action::Button(KeyNames::LEFT)), config.pad_action(PadInput::Axis(Axis::LeftX, false)));
+        assert_eq!(None, config.controller_mappings);
+
+        let config = Config::parse("[controller]\nb = x, y\nturbo = RightShoulder\nup = dpup\ndeadzone = 8000\nmappings = pads.txt\n").unwrap();
+        assert_eq!(vec![PadInput::Button(Button::X), PadInput::Button(Button::Y)], config.pad_inputs(Action::Button(KeyNames::B)));
+        assert_eq!(Some(Action::Turbo), config.pad_action(PadInput::Button(Button::RightShoulder)));
+        assert_eq!(None, config.pad_action(PadInput::Axis(Axis::LeftY, false)));
+        assert_eq!(8000, config.deadzone);
+        // keys are untouched
+        assert_eq!(Some(Action::Button(KeyNames::B)), config.action(Keycode::D));
+
+        assert!(Config::parse("[controller]\na = start").is_err());
+        assert!(Config::parse("[controller]\na = leftx").is_err());
+        assert!(Config::parse("[controller]\nfly = a").is_err());
+        assert!(Config::parse("[controller]\ndeadzone = -1").is_err());
+    }
+
+    #[test]
+    fn test_autofire_rates() {
+        let config = Config::parse("[autofire]\nb = 30\n[hotkeys]\nautofire_a = Q\n").unwrap();
+        assert_eq!([15, 30], config.autofire_rates);
+        assert_eq!(Some(Action::Autofire(KeyNames::A)), config.action(Keycode::Q));
+        assert_eq!(Some(Action::Macro(0)), config.action(Keycode::Num1));
+        assert!(Config::parse("[autofire]\na = 60").is_err());
+        assert!(Config::parse("[autofire]\nstart = 10").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let config = Config::parse("[display]\nscale = 3\npalette = pocket\nscreenshots = shots\n").unwrap();
+        assert_eq!(3, config.scale);
+        assert_eq!(PALETTES[2].1, config.palette);
+        assert_eq!(PathBuf::from("shots"), config.screenshot_dir);
+        if let Some(home) = env::var_os("HOME") {
+            let config = Config::parse("[display]\nscreenshots = ~/Pictures\n").unwrap();
+            assert_eq!(Path::new(&home).join("Pictures"), config.screenshot_dir);
+        }
+        assert_eq!(5, Config::make_config().scale);
+        assert!(Config::parse("[display]\nscale = 0").is_err());
+        assert!(Config::parse("[display]\npalette = nope").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use sdl2::controller::{Axis, Button};
+
+/// Stick or trigger travel, out of 32767, before it counts as pressed
+pub const DEFAULT_DEADZONE: i16 = 16_000;
+
+/// A game controller button, or a stick or trigger pushed one way, that can be bound to an
+/// `Action` in the `[controller]` section of the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadInput {
+    Button(Button),
+    /// the axis pushed past the deadzone, true for the positive direction (right or down)
+    Axis(Axis, bool)
+}
+
+/// Buttons by their name in SDL's mapping strings
+const BUTTONS: [(&str, Button); 15] = [
+    ("a", Button::A), ("b", Button::B), ("x", Button::X), ("y", Button::Y),
+    ("back", Button::Back), ("guide", Button::Guide), ("start", Button::Start),
+    ("leftstick", Button::LeftStick), ("rightstick", Button::RightStick),
+    ("leftshoulder", Button::LeftShoulder), ("rightshoulder", Button::RightShoulder),
+    ("dpup", Button::DPadUp), ("dpdown", Button::DPadDown),
+    ("dpleft", Button::DPadLeft), ("dpright", Button::DPadRight)
+];
+
+const AXES: [(&str, Axis); 6] = [
+    ("leftx", Axis::LeftX), ("lefty", Axis::LeftY), ("rightx", Axis::RightX),
+    ("righty", Axis::RightY), ("lefttrigger", Axis::TriggerLeft), ("righttrigger", Axis::TriggerRight)
+];
+
+/// A `PadInput` from its name in any case: a button like `dpup` or `start`, an axis with the
+/// direction after it like `leftx-` or `lefty+`, or a trigger like `righttrigger`
+pub fn parse_pad_input(name: &str) -> Option<PadInput> {
+    let name = name.to_lowercase();
+    if let Some((_, button)) = BUTTONS.iter().find(|(n, _)| *n == name) {
+        return Some(PadInput::Button(*button));
+    }
+    let (axis_name, positive) = match name.chars().last() {
+        Some('+') => (&name[..name.len() - 1], true),
+        Some('-') => (&name[..name.len() - 1], false),
+        // triggers only go one way
+        _ if name.ends_with("trigger") => (&name[..], true),
+        _ => return None
+    };
+    return AXES.iter().find(|(n, _)| *n == axis_name).map(|(_, axis)| PadInput::Axis(*axis, positive));
+}
+
+/// Inputs held down on every connected controller, by the controller's instance id.
+///
+/// Turns SDL's controller events into presses and releases of `PadInput`s, so a stick moving
+/// from left to right releases left before pressing right and unplugging a controller lets go
+/// of everything it was holding.
+pub struct PadState {
+    held: HashSet<(u32, PadInput)>,
+    pub deadzone: i16
+}
+
+impl PadState {
+    pub fn make_pad_state(deadzone: i16) -> PadState {
+        return PadState { held: HashSet::new(), deadzone: deadzone };
+    }
+
+    /// A button went down or up, returns the change if it is one
+    pub fn button(&mut self, which: u32, button: Button, down: bool) -> Option<(PadInput, bool)> {
+        return self.set(which, PadInput::Button(button), down);
+    }
+
+    /// An axis moved to `value`, returns the directions released and pressed
+    pub fn axis(&mut self, which: u32, axis: Axis, value: i16) -> Vec<(PadInput, bool)> {
+        let negative = self.set(which, PadInput::Axis(axis, false), value < -self.deadzone);
+        let positive = self.set(which, PadInput::Axis(axis, true), value > self.deadzone);
+        // releases first, so the other direction's press wins when both map to one button
+        let mut res: Vec<(PadInput, bool)> = negative.into_iter().chain(positive).collect();
+        res.sort_by_key(|(_, down)| *down);
+        return res;
+    }
+
+    /// The controller was unplugged, returns everything it was holding
+    pub fn remove(&mut self, which: u32) -> Vec<PadInput> {
+        let inputs: Vec<PadInput> = self.held.iter().filter(|(w, _)| *w == which).map(|(_, input)| *input).collect();
+        self.held.retain(|(w, _)| *w != which);
+        return inputs;
+    }
+
+    fn set(&mut self, which: u32, input: PadInput, down: bool) -> Option<(PadInput, bool)> {
+        let changed = if down { self.held.insert((which, input)) } else { self.held.remove(&(which, input)) };
+        return if changed { Some((input, down)) } else { None };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::controller::{Axis, Button};
+    use crate::engine::controller::{PadInput, PadState, parse_pad_input};
+
+    #[test]
+    fn test_parse_pad_input() {
+        assert_eq!(Some(PadInput::Button(Button::DPadUp)), parse_pad_input("dpup"));
+        assert_eq!(Some(PadInput::Button(Button::Start)), parse_pad_input("Start"));
+        assert_eq!(Some(PadInput::Axis(Axis::LeftX, false)), parse_pad_input("leftx-"));
+        assert_eq!(Some(PadInput::Axis(Axis::RightY, true)), parse_pad_input("righty+"));
+        assert_eq!(Some(PadInput::Axis(Axis::TriggerLeft, true)), parse_pad_input("lefttrigger"));
+        assert_eq!(None, parse_pad_input("leftx"));
+        assert_eq!(None, parse_pad_input("start+"));
+        assert_eq!(None, parse_pad_input("z"));
+    }
+
+    #[test]
+    fn test_pad_state() {
+        let mut pads = PadState::make_pad_state(16_000);
+        assert_eq!(Some((PadInput::Button(Button::A), true)), pads.button(0, Button::A, true));
+        assert_eq!(None, pads.button(0, Button::A, true));
+
+        let left = PadInput::Axis(Axis::LeftX, false);
+        let right = PadInput::Axis(Axis::LeftX, true);
+        assert!(pads.axis(0, Axis::LeftX, 8_000).is_empty());
+        assert_eq!(vec![(left, true)], pads.axis(0, Axis::LeftX, -20_000));
+        assert!(pads.axis(0, Axis::LeftX, -30_000).is_empty());
+        assert_eq!(vec![(left, false), (right, true)], pads.axis(0, Axis::LeftX, 32_767));
+
+        // another controller doesn't get released with the first one
+        pads.button(1, Button::B, true);
+        let mut released = pads.remove(0);
+        released.sort_by_key(|input| format!("{:?}", input));
+        assert_eq!(vec![right, PadInput::Button(Button::A)], released);
+        assert_eq!(Some((PadInput::Button(Button::A), true)), pads.button(0, Button::A, true));
+        assert_eq!(vec![PadInput::Button(Button::B)], pads.remove(1));
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+use crate::engine::engine::{Engine, EngineEvent};
+use crate::engine::opcodes;
+use crate::engine::opcodes::Op;
+use crate::engine::registers::RegisterNames;
+use crate::engine::symbols::Symbols;
+use crate::engine::cheats::{Cheat, CheatCode};
+use crate::engine::search::{RamSearch, Filter, Width};
+
+/// How many executed PCs are kept to show before the current one in `dis`
+const HISTORY_LEN: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    /// ROM bank the address has to be in, any bank if None
+    pub bank: Option<u16>,
+    pub addr: u16
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind
+}
+
+/// Command line debugger, lives in `Engine::debugger` and is checked before every instruction.
+///
+/// Addresses and values are hex (`$`, `0x` or nothing in front), counts are decimal.
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// stop once this many more instructions are about to run
+    step_count: Option<u64>,
+    /// `next` over a call, stop at this PC once SP is back to at least the second value
+    step_over: Option<(u16, u16)>,
+    /// set by a watchpoint during an instruction, we stop before the next one
+    watch_hit: Option<String>,
+    /// stop once `GPU::frames` reaches this
+    frame_target: Option<u64>,
+    pub search: Option<RamSearch>,
+    history: VecDeque<u16>,
+    last_command: String,
+    input: Box<dyn BufRead>
+}
+
+impl Debugger {
+    /// Debugger reading commands from stdin, stopped before the first instruction
+    pub fn make_debugger() -> Debugger {
+        return Debugger::with_input(Box::new(io::BufReader::new(io::stdin())));
+    }
+
+    pub fn with_input(input: Box<dyn BufRead>) -> Debugger {
+        return Debugger {
+            breakpoints: vec![],
+            watchpoints: vec![],
+            step_count: Some(1),
+            step_over: None,
+            watch_hit: None,
+            frame_target: None,
+            search: None,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            last_command: String::new(),
+            input: input
+        };
+    }
+
+    /// Called for every CPU read and write
+    pub fn check_access(&mut self, addr: u16, val: u8, write: bool) {
+        for (i, watch) in self.watchpoints.iter().enumerate() {
+            let kind_matches = match watch.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true
+            };
+            if kind_matches && addr >= watch.start && addr <= watch.end {
+                let action = if write { "write" } else { "read" };
+                self.watch_hit = Some(format!("Watchpoint {}: {} {:02X} at {:04X}", i + 1, action, val, addr));
+            }
+        }
+    }
+
+    /// Why we should stop before the instruction at PC, if we should
+    fn stop_reason(&mut self, eng: &Engine) -> Option<String> {
+        let pc = eng.registers.pc;
+        let mut reason = self.watch_hit.take();
+
+        if let Some(n) = self.step_count {
+            if n <= 1 {
+                self.step_count = None;
+                reason = reason.or(Some(String::new()));
+            } else {
+                self.step_count = Some(n - 1);
+            }
+        }
+
+        if let Some((ret, sp)) = self.step_over {
+            if pc == ret && eng.registers.sp >= sp {
+                self.step_over = None;
+                reason = reason.or(Some(String::new()));
+            }
+        }
+
+        if let Some(frame) = self.frame_target {
+            if eng.gpu.frames >= frame {
+                self.frame_target = None;
+                reason = reason.or(Some(String::new()));
+            }
+        }
+
+        let bank = eng.bank_of(pc);
+        for (i, bp) in self.breakpoints.iter().enumerate() {
+            if bp.addr == pc && (bp.bank.is_none() || bp.bank == bank) {
+                reason = Some(format!("Breakpoint {} at {}", i + 1, format_addr(bp.bank, bp.addr)));
+            }
+        }
+        return reason;
+    }
+
+    /// Checks breakpoints and steps, runs the REPL until the user resumes if we stopped
+    pub fn before_instruction(&mut self, eng: &mut Engine) {
+        if let Some(reason) = self.stop_reason(eng) {
+            if !reason.is_empty() {
+                println!("{}", reason);
+            }
+            self.stop_running();
+            self.print_current(eng);
+            self.repl(eng);
+        }
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(eng.registers.pc);
+    }
+
+    fn stop_running(&mut self) {
+        self.step_count = None;
+        self.step_over = None;
+        self.frame_target = None;
+    }
+
+    fn print_current(&self, eng: &Engine) {
+        let pc = eng.registers.pc;
+        println!("{}: {}", eng.describe_addr(pc), eng.disassemble(pc).0);
+    }
+
+    fn repl(&mut self, eng: &mut Engine) {
+        loop {
+            print!("rustboy> ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line).unwrap_or(0) == 0 {
+                // end of input, let the game run on
+                println!();
+                return;
+            }
+            let mut line = line.trim().to_string();
+            if line.is_empty() {
+                line = self.last_command.clone();
+            }
+            self.last_command = line.clone();
+
+            match self.run_command(eng, &line) {
+                Ok(true) => return,
+                Ok(false) => {},
+                Err(msg) => println!("{}", msg)
+            }
+        }
+    }
+
+    /// Runs one command, returns true if emulation should resume
+    pub fn run_command(&mut self, eng: &mut Engine, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return Ok(false);
+        }
+
+        match args[0] {
+            "s" | "step" => {
+                self.step_count = Some(parse_count(args.get(1), 1)?);
+                return Ok(true);
+            },
+            "n" | "next" => {
+                let pc = eng.registers.pc;
+                let instr = opcodes::decode(&*eng.memory, pc);
+                if instr.op == Op::Call || instr.op == Op::Rst {
+                    self.step_over = Some((pc.wrapping_add(instr.length as u16), eng.registers.sp));
+                } else {
+                    self.step_count = Some(1);
+                }
+                return Ok(true);
+            },
+            "fr" | "frame" => {
+                self.frame_target = Some(eng.gpu.frames + parse_count(args.get(1), 1)?);
+                return Ok(true);
+            },
+            "c" | "continue" => return Ok(true),
+            "q" | "quit" => {
+                eng.events.push(EngineEvent::Quit);
+                return Ok(true);
+            },
+            "b" | "break" => {
+                let (bank, addr) = parse_location(args.get(1).ok_or("break <addr>, <bank>:<addr> or <label>")?, &eng.symbols)?;
+                self.breakpoints.push(Breakpoint { bank: bank, addr: addr });
+                println!("Breakpoint {} at {}", self.breakpoints.len(), eng.describe_addr(addr));
+            },
+            "watch" | "rwatch" | "awatch" => {
+                let (start, end) = parse_range(args.get(1).ok_or("watch <addr> or watch <start>-<end>")?, &eng.symbols)?;
+                let kind = match args[0] {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access
+                };
+                self.watchpoints.push(Watchpoint { start: start, end: end, kind: kind });
+                println!("Watchpoint {} on {:04X}-{:04X} ({:?})", self.watchpoints.len(), start, end, kind);
+            },
+            "d" | "delete" => {
+                if args.get(1) == Some(&"watch") {
+                    let n = parse_count(args.get(2), 0)? as usize;
+                    if n == 0 || n > self.watchpoints.len() {
+                        return Err(format!("No watchpoint {}", n));
+                    }
+                    self.watchpoints.remove(n - 1);
+                } else {
+                    let n = parse_count(args.get(1), 0)? as usize;
+                    if n == 0 || n > self.breakpoints.len() {
+                        return Err(format!("No breakpoint {}", n));
+                    }
+                    self.breakpoints.remove(n - 1);
+                }
+            },
+            "i" | "info" => {
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    println!("Breakpoint {} at {}", i + 1, format_addr(bp.bank, bp.addr));
+                }
+                for (i, watch) in self.watchpoints.iter().enumerate() {
+                    println!("Watchpoint {} on {:04X}-{:04X} ({:?})", i + 1, watch.start, watch.end, watch.kind);
+                }
+            },
+            "r" | "regs" => {
+                let r = &eng.registers;
+                println!("AF={:02X}{:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} PC={:04X}",
+                    r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc);
+                println!("Z={} N={} H={} C={} IME={} {:?}",
+                    (r.f >> 7) & 1, (r.f >> 6) & 1, (r.f >> 5) & 1, (r.f >> 4) & 1, eng.interrupts.ime as u8, eng.interrupts.state);
+            },
+            "set" => {
+                let name = args.get(1).ok_or("set <register> <value>")?;
+                let value = parse_address(args.get(2).ok_or("set <register> <value>")?, &eng.symbols)?;
+                let register = parse_register(name).ok_or(format!("Unknown register {}", name))?;
+                eng.registers.set_register(&register, value);
+            },
+            "x" => {
+                let addr = parse_address(args.get(1).ok_or("x <addr> [count]")?, &eng.symbols)?;
+                let count = parse_count(args.get(2), 16)?;
+                for row in (0..count).step_by(16) {
+                    let start = addr.wrapping_add(row as u16);
+                    let bytes: Vec<String> = (row..std::cmp::min(row + 16, count))
+                        .map(|i| format!("{:02X}", eng.memory.get(addr.wrapping_add(i as u16))))
+                        .collect();
+                    println!("{:04X}: {}", start, bytes.join(" "));
+                }
+            },
+            "poke" => {
+                let addr = parse_address(args.get(1).ok_or("poke <addr> <byte>...")?, &eng.symbols)?;
+                for (i, byte) in args[2..].iter().enumerate() {
+                    eng.memory.set(addr.wrapping_add(i as u16), parse_number(byte)? as u8);
+                }
+            },
+            "dis" => {
+                let count = parse_count(args.get(1), 8)?;
+                for pc in self.history.iter() {
+                    println!("  {}: {}", eng.describe_addr(*pc), eng.disassemble(*pc).0);
+                }
+                let mut pc = eng.registers.pc;
+                for i in 0..count {
+                    let (text, length) = eng.disassemble(pc);
+                    println!("{} {}: {}", if i == 0 { ">" } else { " " }, eng.describe_addr(pc), text);
+                    pc = pc.wrapping_add(length);
+                }
+            },
+            "bt" | "backtrace" => {
+                for (i, ret) in backtrace(eng).iter().enumerate() {
+                    println!("#{} {}", i, eng.describe_addr(*ret));
+                }
+            },
+            "cheat" => match args.get(1) {
+                None => {
+                    for (i, cheat) in eng.cheats.cheats.iter().enumerate() {
+                        println!("Cheat {} [{}] {}", i + 1, if cheat.enabled { "on" } else { "off" }, cheat.description);
+                    }
+                },
+                Some(&"add") => {
+                    let code = CheatCode::parse(args.get(2).ok_or("cheat add <code> [description]")?)?;
+                    eng.add_cheat(Cheat { description: args[3..].join(" "), codes: vec![code], enabled: true });
+                    println!("Cheat {} on", eng.cheats.cheats.len());
+                },
+                Some(n) => {
+                    let n = parse_count(Some(n), 0)?;
+                    let enabled = eng.toggle_cheat((n as usize).wrapping_sub(1)).ok_or(format!("No cheat {}", n))?;
+                    println!("Cheat {} {}", n, if enabled { "on" } else { "off" });
+                }
+            },
+            "search" => match args.get(1) {
+                Some(&"start") => {
+                    let width = match args.get(2) {
+                        None | Some(&"8") => Width::Byte,
+                        Some(&"16") => Width::Word,
+                        Some(other) => return Err(format!("Can't search {} bit values, only 8 or 16", other))
+                    };
+                    let search = RamSearch::make_ram_search(&*eng.memory, width);
+                    println!("Searching {} addresses", search.candidates.len());
+                    self.search = Some(search);
+                },
+                Some(&"list") => {
+                    let search = self.search.as_ref().ok_or("No search, try search start")?;
+                    let count = parse_count(args.get(2), 20)? as usize;
+                    for (addr, value) in search.candidates.iter().take(count) {
+                        println!("{}: {:02X} ({})", eng.describe_addr(*addr), value, value);
+                    }
+                    if search.candidates.len() > count {
+                        println!("... {} more", search.candidates.len() - count);
+                    }
+                },
+                Some(&"freeze") => {
+                    let search = self.search.as_ref().ok_or("No search, try search start")?;
+                    let addr = parse_address(args.get(2).ok_or("search freeze <addr> [value]")?, &eng.symbols)?;
+                    let value = match args.get(3) {
+                        Some(value) => parse_number(value)?,
+                        None => search.candidates.iter().find(|(a, _)| *a == addr).map(|(_, v)| *v)
+                            .ok_or(format!("{:04X} isn't in the search results, give a value", addr))?
+                    };
+                    let codes = search.freeze(addr, value);
+                    eng.add_cheat(Cheat { description: format!("Freeze {:04X} at {:X}", addr, value), codes: codes, enabled: true });
+                    println!("Cheat {} on", eng.cheats.cheats.len());
+                },
+                Some(filter) => {
+                    let filter = Filter::parse(filter)?;
+                    let search = self.search.as_mut().ok_or("No search, try search start")?;
+                    println!("{} left", search.filter(&*eng.memory, filter));
+                },
+                None => return Err("search start [8|16], search eq|changed|inc|dec|<value>, search list, search freeze <addr>".to_string())
+            },
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("Unknown command {}, try help", args[0]))
+        }
+        return Ok(false);
+    }
+}
+
+const HELP: &str = "\
+s, step [n]          run n instructions
+n, next              step over calls
+c, continue          run until a breakpoint or watchpoint
+b, break <addr>      break at an address or label, <bank>:<addr> for a specific ROM bank
+watch <addr>         stop after writes, <start>-<end> for a range
+rwatch, awatch       same for reads, or reads and writes
+d, delete <n>        remove a breakpoint, delete watch <n> for a watchpoint
+i, info              list breakpoints and watchpoints
+r, regs              show registers
+set <reg> <value>    change a register (a, f, b, ..., af, bc, de, hl, sp, pc)
+x <addr> [count]     dump memory
+poke <addr> <byte>.. write memory
+dis [count]          disassemble around PC
+bt, backtrace        return addresses found on the stack
+cheat [n]            list cheats, or turn cheat n on or off
+cheat add <code>     add a Game Genie or GameShark code
+fr, frame [n]        run until n more frames have been drawn
+search start [8|16]  start a RAM search of 8 or 16 bit values
+search <filter>      keep results that are eq, changed, inc, dec or equal a value
+search list [n]      show the first n results
+search freeze <addr> add a GameShark cheat holding a result at its value
+q, quit              stop emulation
+An empty line repeats the last command.";
+
+/// Pretty much how far back any game nests calls
+const BACKTRACE_DEPTH: u16 = 32;
+
+/// Walks up the stack looking for words that point right after a CALL or RST
+pub fn backtrace(eng: &Engine) -> Vec<u16> {
+    let mut res = vec![eng.registers.pc];
+    for i in 0..BACKTRACE_DEPTH {
+        let sp = eng.registers.sp.wrapping_add(i * 2);
+        if sp < eng.registers.sp {
+            break; // wrapped around the top of memory
+        }
+        let ret = eng.memory.get(sp) as u16 + ((eng.memory.get(sp.wrapping_add(1)) as u16) << 8);
+        let call = opcodes::decode(&*eng.memory, ret.wrapping_sub(3));
+        let rst = opcodes::decode(&*eng.memory, ret.wrapping_sub(1));
+        if (call.op == Op::Call && call.length == 3) || rst.op == Op::Rst {
+            res.push(ret);
+        }
+    }
+    return res;
+}
+
+pub fn format_addr(bank: Option<u16>, addr: u16) -> String {
+    return match bank {
+        Some(bank) => format!("{:02X}:{:04X}", bank, addr),
+        None => format!("{:04X}", addr)
+    };
+}
+
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    return u16::from_str_radix(digits, 16).map_err(|_| format!("Bad number {}", text));
+}
+
+fn parse_count(text: Option<&&str>, default: u64) -> Result<u64, String> {
+    return match text {
+        Some(text) => text.parse().map_err(|_| format!("Bad count {}", text)),
+        None => Ok(default)
+    };
+}
+
+/// A label or a number, labels win since names like `Add` are valid hex too
+pub fn parse_address(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    if let Some((_, addr)) = symbols.lookup(text) {
+        return Ok(addr);
+    }
+    return parse_number(text);
+}
+
+/// `addr`, `bank:addr` or a label, which gets its bank if it's in ROM
+pub fn parse_location(text: &str, symbols: &Symbols) -> Result<(Option<u16>, u16), String> {
+    if let Some((bank, addr)) = symbols.lookup(text) {
+        return Ok((if addr < 0x8000 { Some(bank) } else { None }, addr));
+    }
+    return match text.find(':') {
+        Some(i) => Ok((Some(parse_number(&text[..i])?), parse_number(&text[i + 1..])?)),
+        None => Ok((None, parse_number(text)?))
+    };
+}
+
+/// `addr` or `start-end`
+fn parse_range(text: &str, symbols: &Symbols) -> Result<(u16, u16), String> {
+    return match text.find('-') {
+        Some(i) => Ok((parse_address(&text[..i], symbols)?, parse_address(&text[i + 1..], symbols)?)),
+        None => {
+            let addr = parse_address(text, symbols)?;
+            Ok((addr, addr))
+        }
+    };
+}
+
+fn parse_register(name: &str) -> Option<RegisterNames> {
+    return match name.to_lowercase().as_str() {
+        "a" => Some(RegisterNames::A),
+        "f" => Some(RegisterNames::F),
+        "b" => Some(RegisterNames::B),
+        "c" => Some(RegisterNames::C),
+        "d" => Some(RegisterNames::D),
+        "e" => Some(RegisterNames::E),
+        "h" => Some(RegisterNames::H),
+        "l" => Some(RegisterNames::L),
+        "af" => Some(RegisterNames::AF),
+        "bc" => Some(RegisterNames::BC),
+        "de" => Some(RegisterNames::DE),
+        "hl" => Some(RegisterNames::HL),
+        "sp" => Some(RegisterNames::SP),
+        "pc" => Some(RegisterNames::PC),
+        _ => None
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::engine::make_engine;
+    use crate::engine::engine::Engine;
+    use crate::engine::debugger::{Debugger, backtrace, parse_location};
+    use crate::engine::symbols::Symbols;
+    use crate::engine::cheats::CheatCode;
+
+    /// ld a, $12; ld [$c000], a; call $0200; nop... with ret at $0200
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x109].copy_from_slice(&[0x3E, 0x12, 0xEA, 0x00, 0xC0, 0xCD, 0x00, 0x02, 0x00]);
+        rom[0x200] = 0xC9;
+        return rom;
+    }
+
+    fn run_with(commands: &str, instructions: u64) -> Engine {
+        let mut eng = make_engine(test_rom());
+        eng.debugger = Some(Debugger::with_input(Box::new(Cursor::new(commands.to_string()))));
+        eng.run_limited(instructions);
+        return eng;
+    }
+
+    #[test]
+    fn test_parse_location() {
+        let symbols = Symbols::parse("00:0150 Add\n03:4010 Music.play\n00:c000 wX\n");
+        assert_eq!(Ok((None, 0x150)), parse_location("$150", &symbols));
+        assert_eq!(Ok((Some(2), 0x4567)), parse_location("02:4567", &symbols));
+        assert_eq!(Ok((Some(0), 0x150)), parse_location("Add", &symbols));
+        assert_eq!(Ok((Some(3), 0x4010)), parse_location("Music.play", &symbols));
+        assert_eq!(Ok((None, 0xC000)), parse_location("wX", &symbols));
+        assert!(parse_location("zz", &symbols).is_err());
+    }
+
+    #[test]
+    fn test_breakpoint_and_set() {
+        // stops before the first instruction, then breaks at the call and changes A there
+        let eng = run_with("break 105\ncontinue\nset a 34\nc\n", 3);
+        assert_eq!(0x34, eng.registers.a);
+        assert_eq!(0x200, eng.registers.pc);
+    }
+
+    #[test]
+    fn test_next_steps_over_call() {
+        // stops again once the call returned, B is set there before the nop runs
+        let eng = run_with("b 105\nc\nnext\nset b 99\n", 5);
+        assert_eq!(0x99, eng.registers.b);
+        assert_eq!(0x109, eng.registers.pc);
+    }
+
+    #[test]
+    fn test_cheats() {
+        let eng = run_with("cheat add 010338C1 Infinite lives\ncheat 1\ncheat add 3E2-34E\ncheat\nc\n", 1);
+        assert_eq!(2, eng.cheats.cheats.len());
+        assert_eq!("Infinite lives", eng.cheats.cheats[0].description);
+        assert!(!eng.cheats.cheats[0].enabled);
+        assert!(eng.cheats.cheats[1].enabled);
+    }
+
+    #[test]
+    fn test_ram_search() {
+        let eng = run_with("poke c100 5\npoke c101 5\nsearch start\npoke c100 7\nsearch inc\nsearch 7\nsearch freeze c100\nc\n", 1);
+        assert_eq!(vec![(0xC100, 7)], eng.debugger.as_ref().unwrap().search.as_ref().unwrap().candidates);
+        assert_eq!(vec![CheatCode::GameShark { addr: 0xC100, value: 7 }], eng.cheats.cheats[0].codes);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let eng = run_with("watch c000\nc\nset c 77\n", 3);
+        assert_eq!(0x77, eng.registers.c);
+        assert_eq!(0x12, eng.memory.get(0xC000));
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let eng = run_with("c\n", 3);
+        assert_eq!(0x200, eng.registers.pc);
+        assert_eq!(vec![0x200, 0x108], backtrace(&eng));
+    }
+}
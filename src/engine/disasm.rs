@@ -0,0 +1,277 @@
+use std::io::{self, Write};
+
+use crate::engine::memory::Memory;
+use crate::engine::opcodes::{self, Op, Operand};
+use crate::engine::symbols::Symbols;
+
+const BANK_SIZE: usize = 0x4000;
+/// Where execution starts after the boot rom, and the VBlank, STAT, timer, serial and joypad interrupts
+pub const ENTRY_POINTS: [u16; 6] = [0x0100, 0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+/// Runs of the same byte at least this long become `ds`, so padding doesn't turn into pages of `rst $38`
+const MIN_FILL: usize = 16;
+/// Bytes per `db` line
+const DATA_PER_LINE: usize = 8;
+
+/// The ROM as seen by the CPU with `bank` mapped at 0x4000-0x7FFF
+struct BankView<'a> {
+    rom: &'a [u8],
+    bank: u16
+}
+
+impl<'a> Memory for BankView<'a> {
+    fn set(&mut self, _loc: u16, _val: u8) {}
+
+    fn get(&self, loc: u16) -> u8 {
+        let offset = match loc {
+            0x0000..=0x3FFF => loc as usize,
+            0x4000..=0x7FFF => self.bank as usize * BANK_SIZE + (loc as usize - 0x4000),
+            _ => return 0xFF
+        };
+        return *self.rom.get(offset).unwrap_or(&0xFF);
+    }
+
+    fn load(&mut self, _data: Vec<u8>) {}
+
+    fn save(&self) -> Vec<u8> {
+        return vec![];
+    }
+
+    fn rom_bank(&self) -> u16 {
+        return self.bank;
+    }
+}
+
+/// Number of 16KiB banks, from the header's ROM size byte (0x148) but never more than the file holds
+pub fn rom_banks(rom: &[u8]) -> usize {
+    let in_file = std::cmp::max(1, (rom.len() + BANK_SIZE - 1) / BANK_SIZE);
+    return match rom.get(0x148) {
+        Some(size) if *size <= 8 => std::cmp::min(2 << size, in_file),
+        _ => in_file
+    };
+}
+
+/// Game title from the header
+pub fn title(rom: &[u8]) -> String {
+    let end = std::cmp::min(rom.len(), 0x144);
+    let bytes = rom.get(0x134..end).unwrap_or(&[]);
+    return bytes.iter().take_while(|b| **b != 0).map(|b| *b as char).filter(|c| c.is_ascii_graphic() || *c == ' ').collect();
+}
+
+/// Index into the ROM file of `addr` with `bank` mapped, None outside of ROM or past its end
+fn rom_offset(rom: &[u8], addr: u16, bank: u16) -> Option<usize> {
+    let offset = match addr {
+        0x0000..=0x3FFF => addr as usize,
+        0x4000..=0x7FFF => bank as usize * BANK_SIZE + (addr as usize - 0x4000),
+        _ => return None
+    };
+    return if offset < rom.len() { Some(offset) } else { None };
+}
+
+/// Marks every ROM byte that starts an instruction reachable from `ENTRY_POINTS`.
+///
+/// Jumps into 0x4000-0x7FFF go to whichever bank the code itself is in, or, from bank 0,
+/// to the last bank selected with `ld a, n` / `ld [$2000-$3FFF], a`, otherwise bank 1.
+/// Jumps through `hl` and code in RAM aren't followed.
+pub fn reachable(rom: &[u8]) -> Vec<bool> {
+    let mut starts = vec![false; rom.len()];
+    let banks = rom_banks(rom) as u16;
+    let mut todo: Vec<(u16, u16)> = ENTRY_POINTS.iter().rev().map(|addr| (*addr, 1)).collect();
+
+    while let Some((mut pc, mut mapped)) = todo.pop() {
+        let mut last_a: Option<u8> = None;
+        loop {
+            let view = BankView { rom: rom, bank: mapped };
+            let offset = match rom_offset(rom, pc, mapped) {
+                Some(offset) => offset,
+                None => break
+            };
+            let instr = opcodes::decode(&view, pc);
+            let next = pc.wrapping_add(instr.length as u16);
+            // ran off the end of the bank or into something we've already followed
+            if starts[offset] || instr.op == Op::Illegal || (next.wrapping_sub(1) ^ pc) & 0xC000 != 0 {
+                break;
+            }
+            starts[offset] = true;
+
+            let imm = opcodes::immediate(&view, pc, instr);
+            let conditional = match instr.dst {
+                Operand::CondNZ | Operand::CondZ | Operand::CondNC | Operand::CondC => true,
+                _ => false
+            };
+            match instr.op {
+                Op::Jp if instr.dst == Operand::HL => break,
+                Op::Jp | Op::Call => todo.push((imm, mapped)),
+                Op::Jr => todo.push((next.wrapping_add(imm as u8 as i8 as u16), mapped)),
+                Op::Rst => if let Operand::Vector(vector) = instr.dst {
+                    todo.push((vector, mapped));
+                },
+                Op::Ld if instr.dst == Operand::IndA16 && instr.src == Operand::A && (0x2000..0x4000).contains(&imm) => {
+                    if let Some(bank) = last_a {
+                        let bank = std::cmp::max(1, bank as u16);
+                        if bank < banks {
+                            mapped = bank;
+                        }
+                    }
+                },
+                _ => {}
+            }
+            let ends = match instr.op {
+                Op::Jp | Op::Jr | Op::Ret => !conditional,
+                Op::Reti => true,
+                _ => false
+            };
+            if ends {
+                break;
+            }
+
+            last_a = if instr.op == Op::Ld && instr.dst == Operand::A && instr.src == Operand::D8 { Some(imm as u8) } else { None };
+            pc = next;
+        }
+    }
+    return starts;
+}
+
+fn write_line(out: &mut dyn Write, text: &str, bank: usize, addr: u16, bytes: &[u8]) -> io::Result<()> {
+    let raw: Vec<String> = bytes.iter().take(DATA_PER_LINE).map(|b| format!("{:02X}", b)).collect();
+    return writeln!(out, "    {:<28} ; {:02X}:{:04X}  {}", text, bank, addr, raw.join(" "));
+}
+
+/// Writes the whole ROM as RGBDS assembly, one section per bank, with the bank, address
+/// and raw bytes of every line in a comment. With `reachable_only` only code reached from
+/// `ENTRY_POINTS` is disassembled and everything else is left as data.
+pub fn write_disassembly(out: &mut dyn Write, rom: &[u8], symbols: &Symbols, reachable_only: bool) -> io::Result<()> {
+    let banks = rom_banks(rom);
+    let code = if reachable_only { Some(reachable(rom)) } else { None };
+
+    let title = title(rom);
+    if !title.is_empty() {
+        writeln!(out, "; {}", title)?;
+    }
+    writeln!(out, "; {} banks, cartridge type ${:02X}", banks, rom.get(0x147).unwrap_or(&0))?;
+    for (_, addr, name) in symbols.iter().filter(|(_, addr, _)| *addr >= 0x8000) {
+        writeln!(out, "DEF {} EQU ${:04X}", name, addr)?;
+    }
+
+    for bank in 0..banks {
+        let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+        // what gets passed to symbols for 0x4000-0x7FFF, bank 0 doesn't care
+        let label_bank = std::cmp::max(1, bank as u16);
+        let view = BankView { rom: rom, bank: label_bank };
+        let end = std::cmp::min(BANK_SIZE, rom.len() - bank * BANK_SIZE);
+
+        writeln!(out)?;
+        if bank == 0 {
+            writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]")?;
+        } else {
+            writeln!(out, "SECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:03X}]", bank, bank)?;
+        }
+
+        let label_at = |i: usize| symbols.label(base + i as u16, label_bank);
+        let is_code = |i: usize| code.as_ref().map_or(true, |starts| starts[bank * BANK_SIZE + i]);
+        // something that has to start its own line
+        let boundary = |i: usize| label_at(i).is_some() || code.as_ref().map_or(false, |starts| starts[bank * BANK_SIZE + i]);
+        let bank_bytes = &rom[bank * BANK_SIZE..bank * BANK_SIZE + end];
+        // how many times the byte at i repeats, without running into a label or code
+        let run_at = |i: usize| 1 + (i + 1..end).take_while(|n| bank_bytes[*n] == bank_bytes[i] && !boundary(*n)).count();
+
+        let mut i = 0;
+        while i < end {
+            let addr = base + i as u16;
+            let bytes = &bank_bytes[i..];
+            if let Some(label) = label_at(i) {
+                writeln!(out, "{}:", label)?;
+            }
+
+            let run = run_at(i);
+            if run >= MIN_FILL && !(reachable_only && is_code(i)) {
+                write_line(out, &format!("ds {}, ${:02x}", run, bytes[0]), bank, addr, &bytes[..run])?;
+                i += run;
+                continue;
+            }
+
+            if is_code(i) {
+                let instr = opcodes::decode(&view, addr);
+                let length = instr.length as usize;
+                // the assembler writes `stop` as 10 00
+                let fits = length <= bytes.len() && (1..length).all(|n| !boundary(i + n))
+                    && (instr.op != Op::Stop || bytes[1] == 0);
+                if fits {
+                    let (text, _) = opcodes::disassemble_with_symbols(&view, addr, symbols, label_bank);
+                    write_line(out, &text, bank, addr, &bytes[..length])?;
+                    i += length;
+                    continue;
+                }
+            }
+
+            // without a code map this is just a byte that didn't decode
+            let most = if reachable_only { DATA_PER_LINE } else { 1 };
+            let mut length = 1;
+            while length < most && length < bytes.len() && !boundary(i + length) && run_at(i + length) < MIN_FILL {
+                length += 1;
+            }
+            let values: Vec<String> = bytes[..length].iter().map(|b| format!("${:02x}", b)).collect();
+            write_line(out, &format!("db {}", values.join(", ")), bank, addr, &bytes[..length])?;
+            i += length;
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::disasm::{reachable, rom_banks, title, write_disassembly};
+    use crate::engine::symbols::Symbols;
+
+    fn make_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0xFF; banks * 0x4000];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);   // nop, jp $0150
+        rom[0x150..0x15A].copy_from_slice(&[
+            0x3E, 0x02,             // ld a, 2
+            0xEA, 0x00, 0x20,       // ld [$2000], a
+            0xCD, 0x00, 0x40,       // call $4000 (in bank 2)
+            0x18, 0xFE              // jr $0158
+        ]);
+        rom[0x8000..0x8004].copy_from_slice(&[0xCB, 0x37, 0xC9, 0x12]); // swap a, ret, data
+        for vector in (0x40..=0x60).step_by(8) {
+            rom[vector] = 0xD9;                                         // reti
+        }
+        return rom;
+    }
+
+    #[test]
+    fn test_header() {
+        let rom = make_rom(4);
+        assert_eq!(4, rom_banks(&rom));
+        assert_eq!(2, rom_banks(&rom[..0x8000]));
+        assert_eq!("TEST", title(&rom));
+    }
+
+    #[test]
+    fn test_reachable() {
+        let rom = make_rom(4);
+        let starts = reachable(&rom);
+        let found: Vec<usize> = (0..rom.len()).filter(|i| starts[*i]).collect();
+        assert_eq!(vec![0x40, 0x48, 0x50, 0x58, 0x60, 0x100, 0x101, 0x150, 0x152, 0x155, 0x158, 0x8000, 0x8002], found);
+    }
+
+    #[test]
+    fn test_write_disassembly() {
+        let rom = make_rom(4);
+        let symbols = Symbols::parse("00:0150 Start\n02:4000 Swap\n00:c000 wCount\n");
+        let mut out = vec![];
+        write_disassembly(&mut out, &rom, &symbols, true).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("DEF wCount EQU $C000\n"));
+        assert!(text.contains("SECTION \"ROM Bank $002\", ROMX[$4000], BANK[$002]\n"));
+        assert!(text.contains("    jp Start                     ; 00:0101  C3 50 01\n"));
+        assert!(text.contains("Start:\n    ld a, $02"));
+        assert!(text.contains("    call $4000                   ; 00:0155  CD 00 40\n"));
+        assert!(text.contains("Swap:\n    swap a                       ; 02:4000  CB 37\n    ret"));
+        assert!(text.contains("    db $12                       ; 02:4003  12\n"));
+        assert!(text.contains("    ds 16380, $ff                ; 02:4004"));
+    }
+}
@@ -6,6 +6,30 @@ use crate::engine::clock::Clock;
 use crate::engine::registers::Registers;
 use crate::engine::registers::RegisterNames;
 use crate::engine::memory::Memory;
+use crate::engine::interrupts::Interrupts;
+use crate::engine::interrupts::CpuState;
+use crate::engine::interrupts;
+use crate::engine::opcodes::{Instruction, Op, Operand, OPCODES, CB_OPCODES};
+use crate::engine::trace::Tracer;
+use crate::engine::debugger::Debugger;
+use crate::engine::gdb::GdbStub;
+use crate::engine::profiler::{Profiler, Location};
+use crate::engine::cdl;
+use crate::engine::cdl::CodeDataLog;
+use crate::engine::hooks;
+use crate::engine::hooks::{MemoryHooks, Intercept};
+use crate::engine::cheats::{Cheats, Cheat, CheatCode};
+use crate::engine::movie::{Movie, MovieMode, Anchor};
+use crate::engine::rewind::Rewind;
+use crate::engine::speed;
+use crate::engine::speed::Speed;
+use crate::engine::config::{Config, Action};
+use crate::engine::controller::PadState;
+use crate::engine::input;
+use crate::engine::input::{InputLayer, HeldActions, InputSource};
+use crate::engine::state::{StateWriter, StateReader};
+use crate::engine::symbols::Symbols;
+use crate::engine::opcodes;
 
 extern crate sdl2;
 
@@ -13,179 +37,458 @@ use chrono;
 
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
 use sdl2::surface::Surface;
+use sdl2::video::Window;
 
+use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
-use std::time::Duration;
-
+use std::time::{Duration, Instant};
+
+/// The joypad behind 0xFF00.
+///
+/// Bit 4 of the register selects the directions and bit 5 the buttons, each when low, and the
+/// low nibble reads as the selected rows ANDed together with 0 for held. The joypad interrupt
+/// is raised when one of those four lines falls, whether from a press or from selecting a row
+/// with something already held.
+///
+/// `layer` adds autofire and macros on top of the keys held, once a frame.
 pub struct ButtonState {
-    row1: u8,
-    row2: u8
+    /// buttons held down, as in `pressed()`
+    held: u8,
+    /// buttons held on the keyboard or a controller
+    keys: u8,
+    /// buttons `layer` pressed for this frame
+    overlay: u8,
+    /// `GPU::frames` when `layer` last ran, MAX before the first frame
+    last_frame: u64,
+    pub layer: InputLayer,
+    /// bits 4 and 5 as last written
+    select: u8,
+    /// Left with Right or Up with Down held reads as neither, which some games can't handle
+    pub block_opposite: bool
 }
 
 impl ButtonState {
     pub fn create() -> ButtonState {
         return ButtonState {
-            row1: 0xF,
-            row2: 0xF
+            held: 0,
+            keys: 0,
+            overlay: 0,
+            last_frame: u64::MAX,
+            layer: InputLayer::make_input_layer(),
+            select: 0,
+            block_opposite: false
         };
     }
 
     fn setKeyUp(&mut self, key: KeyNames, memory: &mut Box<dyn Memory>){
-        match key {
-            KeyNames::RIGHT => self.row1 |= 0x01,
-            KeyNames::LEFT => self.row1 |= 0x02,
-            KeyNames::UP => self.row1 |= 0x04,
-            KeyNames::DOWN => self.row1 |= 0x08,
-            KeyNames::A => self.row2 |= 0x01,
-            KeyNames::B => self.row2 |= 0x02,
-            KeyNames::SELECT => self.row2 |= 0x04,
-            KeyNames::START => self.row2 |= 0x08,
-            _ => {}
-        }
-
-        memory.setInterruptFlag(4);
+        self.keys &= !ButtonState::key_mask(&key);
+        self.set_pressed(self.keys | self.overlay, memory);
     }
 
     fn setKeyDown(&mut self, key: KeyNames, memory: &mut Box<dyn Memory>){
-        match key {
-            KeyNames::RIGHT => self.row1 &= 0x0F^0x01,
-            KeyNames::LEFT => self.row1 &= 0x0F^0x02,
-            KeyNames::UP => self.row1 &= 0x0F^0x04,
-            KeyNames::DOWN => self.row1 &= 0x0F^0x08,
-            KeyNames::A => self.row2 &= 0x0F^0x01,
-            KeyNames::B => self.row2 &= 0x0F^0x02,
-            KeyNames::SELECT => self.row2 &= 0x0F^0x04,
-            KeyNames::START => self.row2 &= 0x0F^0x08,
-            _ => {}
+        self.keys |= ButtonState::key_mask(&key);
+        self.set_pressed(self.keys | self.overlay, memory);
+    }
+
+    /// Buttons held on the keyboard or a controller, without autofire or macros
+    pub fn keys(&self) -> u8 {
+        return self.keys;
+    }
+
+    /// `keys` with what `layer` presses for the frame that's starting
+    pub fn start_frame(&mut self, keys: u8) -> u8 {
+        self.overlay = self.layer.frame(keys);
+        return keys | self.overlay;
+    }
+
+    /// Runs `layer` at the start of each frame, when there's no movie to do it
+    fn update_frame(&mut self, frame: u64, memory: &mut Box<dyn Memory>) {
+        if frame == self.last_frame {
+            return;
+        }
+        self.last_frame = frame;
+        if self.layer.is_active() || self.overlay != 0 {
+            let pressed = self.start_frame(self.keys);
+            self.set_pressed(pressed, memory);
         }
+    }
 
-        memory.setInterruptFlag(4);
+    /// Bit set for each button down: Right, Left, Up, Down in the low nibble, A, B, Select, Start in the high one
+    pub fn pressed(&self) -> u8 {
+        return self.held;
     }
 
-    fn updateMemory(&mut self, memory: &mut Box<dyn Memory>){
-        let mem = memory.get(0xFF00);
+    /// Holds exactly the buttons in `pressed`, as returned by `pressed()`
+    pub fn set_pressed(&mut self, pressed: u8, memory: &mut Box<dyn Memory>) {
+        let lines = self.lines();
+        self.held = pressed;
+        self.changed(lines, memory);
+    }
 
-        if mem & 0x30 == 0x10 {
-        //    println!("1");
-            memory.set(0xFF00, (self.row2 & 0x0F) + 0x50);
-        } else if mem & 0x30 == 0x20 {
-        //    println!("2");
-            memory.set(0xFF00, (self.row1 & 0x0F) + 0x60);
-        } else {
-        //    println!("none");
-        //top bits are always high?
-            memory.set(0xFF00, 0xFF);
+    /// `key`'s bit in `pressed()`
+    pub fn key_mask(key: &KeyNames) -> u8 {
+        return match key {
+            KeyNames::RIGHT => 0x01,
+            KeyNames::LEFT => 0x02,
+            KeyNames::UP => 0x04,
+            KeyNames::DOWN => 0x08,
+            KeyNames::A => 0x10,
+            KeyNames::B => 0x20,
+            KeyNames::SELECT => 0x40,
+            KeyNames::START => 0x80
+        };
+    }
+
+    /// 0xFF00 as the CPU sees it
+    pub fn read(&self) -> u8 {
+        return 0xC0 | self.select | self.lines();
+    }
+
+    /// The CPU wrote `val` to 0xFF00, only the row selects can be written
+    pub fn write(&mut self, val: u8, memory: &mut Box<dyn Memory>) {
+        let lines = self.lines();
+        self.select = val & 0x30;
+        self.changed(lines, memory);
+    }
+
+    /// Only the row selects the game wrote, what's held comes from the keyboard and controllers
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.select);
+    }
+
+    /// Buttons go back to what's held right now, not what was held when the snapshot was taken
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.select = input.u8()? & 0x30;
+        self.held = self.keys | self.overlay;
+        return Ok(());
+    }
+
+    fn any_pressed(&self) -> bool {
+        return self.held != 0;
+    }
+
+    /// `held` after `block_opposite`
+    fn effective(&self) -> u8 {
+        let mut res = self.held;
+        if self.block_opposite {
+            for pair in [0x03, 0x0C].iter() {
+                if res & pair == *pair {
+                    res &= !pair;
+                }
+            }
         }
+        return res;
+    }
 
-        //println!("{:x} ({:x}, {:x})", memory.get(0xFF00), self.row1, self.row2);
+    /// The low nibble of 0xFF00, a bit is 0 when its button is held in a selected row
+    fn lines(&self) -> u8 {
+        let pressed = self.effective();
+        let mut res = 0x0F;
+        if self.select & 0x10 == 0 {
+            res &= !pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            res &= !(pressed >> 4) & 0x0F;
+        }
+        return res;
+    }
+
+    /// Raises the interrupt if a line fell from `lines`, and keeps 0xFF00 in memory up to date
+    /// for anything that looks there directly
+    fn changed(&self, lines: u8, memory: &mut Box<dyn Memory>) {
+        if lines & !self.lines() != 0 {
+            memory.setInterruptFlag(interrupts::JOYPAD);
+        }
+        memory.set(0xFF00, self.read());
     }
 }
 
 pub struct Engine {
     pub memory: Box<dyn Memory>,
     pub registers: Registers,
-    pub enable_interrupt: InterruptState,
+    pub interrupts: Interrupts,
     pub gpu: GPU,
     pub clock: Clock,
-    pub buttons: ButtonState
+    pub buttons: ButtonState,
+    pub events: Vec<EngineEvent>,
+    /// clock cycles used so far by the instruction being executed
+    pub step_cycles: u32,
+    /// only run the CPU, the GPU and timer don't get ticked
+    pub cpu_only: bool,
+    pub tracer: Option<Tracer>,
+    pub debugger: Option<Debugger>,
+    pub gdb: Option<GdbStub>,
+    pub profiler: Option<Profiler>,
+    /// which ROM bytes were run as code and which were read as data
+    pub cdl: Option<CodeDataLog>,
+    /// callbacks on CPU reads, writes and opcode fetches
+    pub hooks: MemoryHooks,
+    pub cheats: Cheats,
+    /// joypad input being recorded or played back
+    pub movie: Option<Movie>,
+    /// snapshots of the last few seconds to step back through
+    pub rewind: Option<Rewind>,
+    /// pause, frame advance, fast forward and turbo for `run`
+    pub speed: Speed,
+    /// keyboard bindings for `run`
+    pub config: Config,
+    /// labels from the ROM's .sym file, empty if there isn't one
+    pub symbols: Symbols,
+    /// the DMG boot ROM from `load_boot_rom`, empty without one
+    pub boot_rom: Vec<u8>,
+    /// `boot_rom` covers 0x0000-0x00FF until the game writes to 0xFF50
+    pub boot_rom_mapped: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    CpuLocked { pc: u16, opcode: u8 },
+    /// the debugger asked to stop emulation
+    Quit,
+    /// a movie being played back ran out of input
+    MovieFinished { frames: usize }
+}
+
+impl fmt::Display for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineEvent::CpuLocked { pc, opcode } => write!(f, "CPU locked up by illegal opcode {:02X} at {:04X}", opcode, pc),
+            EngineEvent::Quit => write!(f, "Quit"),
+            EngineEvent::MovieFinished { frames } => write!(f, "Movie finished after {} frames", frames)
+        }
+    }
 }
 
 impl Engine {
-    pub fn run(&mut self, headless: bool){
+    pub fn run(&mut self){
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
-        let width = 800;
-        let height = 720;
-        let window = match headless {
-            false => video_subsystem.window("Rust Boy", width, height).build().unwrap(),
-            true => video_subsystem.window("Rust Boy", width, height).hidden().build().unwrap()
-        };
+        let width = 160 * self.config.scale;
+        let height = 144 * self.config.scale;
+        let window = video_subsystem.window("Rust Boy", width, height).build().unwrap();
 
         let mut canvas = window.into_canvas().build().unwrap();
         let mut event_pump = sdl_context.event_pump().unwrap();
 
+        // controllers show up as ControllerDeviceAdded events, including those plugged in already
+        let controller_subsystem = match sdl_context.game_controller() {
+            Ok(subsystem) => Some(subsystem),
+            Err(e) => {
+                println!("No controller support: {}", e);
+                None
+            }
+        };
+        if let (Some(subsystem), Some(path)) = (&controller_subsystem, &self.config.controller_mappings) {
+            if let Err(e) = subsystem.load_mappings(path) {
+                println!("Couldn't load controller mappings from {}: {}", path.display(), e);
+            }
+        }
+        let mut pads = HashMap::new();
+        let mut pad_state = PadState::make_pad_state(self.config.deadzone);
+        let mut held = HeldActions::make_held_actions();
+        let rates = self.config.autofire_rates;
+        self.buttons.layer.autofire_period = [input::autofire_period(rates[0]), input::autofire_period(rates[1])];
+
         let mut total_steps = 0;
+        let mut rewinding = false;
+        let mut last_frame = self.gpu.frames;
         self.gpu.draw(&mut canvas, width, height);
 
         'running: loop {
 
-            if total_steps % 1_000 == 0 { // only poll these every so often, it seems to kill performance otherwise
+            if total_steps % 1_000 == 0 || rewinding || self.speed.is_stopped() { // only poll these every so often, it seems to kill performance otherwise
                 for event in event_pump.poll_iter() {
-                    match event {
-                        Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                            break 'running
-                        },
-                        //todo: make these configurable?
-                        Event::KeyDown { keycode: Some(Keycode::F), .. } => {
-                            self.buttons.setKeyDown(KeyNames::A, &mut self.memory);
-                        },
-                        Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-                            self.buttons.setKeyDown(KeyNames::B, &mut self.memory);
+                    let quit = match event {
+                        Event::Quit {..} => true,
+                        Event::KeyDown { keycode: Some(key), repeat, .. } => match self.config.action(key) {
+                            // held keys only repeat the joypad and frame advance
+                            Some(action) if repeat && action.repeats() => self.do_action(action, true, canvas.window_mut(), &mut rewinding),
+                            Some(action) if !repeat => match held.set(InputSource::Key(key), action, true) {
+                                Some(down) => self.do_action(action, down, canvas.window_mut(), &mut rewinding),
+                                None => false
+                            },
+                            _ => false
                         },
-                        Event::KeyDown { keycode: Some(Keycode::R), .. } => {
-                            self.buttons.setKeyDown(KeyNames::START, &mut self.memory);
+                        Event::KeyUp { keycode: Some(key), .. } => match self.config.action(key).and_then(|action| Some((action, held.set(InputSource::Key(key), action, false)?))) {
+                            Some((action, down)) => self.do_action(action, down, canvas.window_mut(), &mut rewinding),
+                            None => false
                         },
-                        Event::KeyDown { keycode: Some(Keycode::E), .. } => {
-                            self.buttons.setKeyDown(KeyNames::SELECT, &mut self.memory);
+                        Event::ControllerDeviceAdded { which, .. } => {
+                            if let Some(subsystem) = &controller_subsystem {
+                                match subsystem.open(which) {
+                                    Ok(pad) => {
+                                        println!("Controller connected: {}", pad.name());
+                                        pads.insert(pad.instance_id() as u32, pad);
+                                    },
+                                    Err(e) => println!("Couldn't open controller {}: {}", which, e)
+                                }
+                            }
+                            false
                         },
-                        Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-                            self.buttons.setKeyDown(KeyNames::UP, &mut self.memory);
+                        Event::ControllerDeviceRemoved { which, .. } => {
+                            if let Some(pad) = pads.remove(&which) {
+                                println!("Controller disconnected: {}", pad.name());
+                            }
+                            let mut quit = false;
+                            for input in pad_state.remove(which) {
+                                if let Some(action) = self.config.pad_action(input) {
+                                    if let Some(down) = held.set(InputSource::Pad(which, input), action, false) {
+                                        quit |= self.do_action(action, down, canvas.window_mut(), &mut rewinding);
+                                    }
+                                }
+                            }
+                            quit
                         },
-                        Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                            self.buttons.setKeyDown(KeyNames::DOWN, &mut self.memory);
+                        Event::ControllerButtonDown { which, button, .. } | Event::ControllerButtonUp { which, button, .. } => {
+                            let down = matches!(event, Event::ControllerButtonDown {..});
+                            let change = pad_state.button(which, button, down).and_then(|(input, down)| {
+                                let action = self.config.pad_action(input)?;
+                                return Some((action, held.set(InputSource::Pad(which, input), action, down)?));
+                            });
+                            match change {
+                                Some((action, down)) => self.do_action(action, down, canvas.window_mut(), &mut rewinding),
+                                None => false
+                            }
                         },
-                        Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                            self.buttons.setKeyDown(KeyNames::LEFT, &mut self.memory);
+                        Event::ControllerAxisMotion { which, axis, value, .. } => {
+                            let mut quit = false;
+                            for (input, down) in pad_state.axis(which, axis, value) {
+                                if let Some(action) = self.config.pad_action(input) {
+                                    if let Some(down) = held.set(InputSource::Pad(which, input), action, down) {
+                                        quit |= self.do_action(action, down, canvas.window_mut(), &mut rewinding);
+                                    }
+                                }
+                            }
+                            quit
                         },
-                        Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                            self.buttons.setKeyDown(KeyNames::RIGHT, &mut self.memory);
-                        },
-                        Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                            self.screenshot(Path::new(format!("screenshots/screenshot{}.bmp", chrono::offset::Local::now()).as_str()));
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::F), .. } => {
-                            self.buttons.setKeyUp(KeyNames::A, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::D), .. } => {
-                            self.buttons.setKeyUp(KeyNames::B, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::R), .. } => {
-                            self.buttons.setKeyUp(KeyNames::START, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::E), .. } => {
-                            self.buttons.setKeyUp(KeyNames::SELECT, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::Up), .. } => {
-                            self.buttons.setKeyUp(KeyNames::UP, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::Down), .. } => {
-                            self.buttons.setKeyUp(KeyNames::DOWN, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::Left), .. } => {
-                            self.buttons.setKeyUp(KeyNames::LEFT, &mut self.memory);
-                        },
-                        Event::KeyUp { keycode: Some(Keycode::Right), .. } => {
-                            self.buttons.setKeyUp(KeyNames::RIGHT, &mut self.memory);
-                        },
-                        _ => {}
+                        _ => false
+                    };
+                    if quit {
+                        break 'running;
                     }
                 }
             }
-            if headless {
-                self.run_limited(100);
-                break 'running
+            if rewinding {
+                // one snapshot per interval of frames, so it goes back at normal speed
+                if self.rewind_step() {
+                    self.gpu.draw(&mut canvas, width, height);
+                }
+                let interval = self.rewind.as_ref().map_or(1, |rewind| rewind.interval);
+                std::thread::sleep(speed::FRAME_TIME * interval as u32);
+                self.speed.reset_timing();
+                continue 'running;
+            }
+
+            if self.speed.is_stopped() {
+                self.gpu.draw(&mut canvas, width, height);
+                std::thread::sleep(Duration::from_millis(10));
+                continue 'running;
             }
 
             total_steps += 1; 
             
             self.run_limited(1);
 
+            let frame_done = self.gpu.frames != last_frame;
+            if frame_done {
+                last_frame = self.gpu.frames;
+                if !self.speed.should_draw(last_frame) {
+                    self.gpu.time_to_draw = false;
+                }
+            }
+
+            for event in self.take_events() {
+                if event == EngineEvent::Quit {
+                    break 'running;
+                }
+                println!("{}", event);
+                canvas.window_mut().set_title(&format!("Rust Boy - {}", event)).ok();
+            }
+
             self.gpu.draw(&mut canvas, width, height);
+
+            if frame_done {
+                let wait = self.speed.end_frame(Instant::now());
+                if wait > Duration::from_secs(0) {
+                    std::thread::sleep(wait);
+                }
+            }
         }
     }
     
+    /// A key or controller input bound to `action` went down or up, returns true to quit
+    fn do_action(&mut self, action: Action, down: bool, window: &mut Window, rewinding: &mut bool) -> bool {
+        match (action, down) {
+            (Action::Button(button), true) => self.key_down(button),
+            (Action::Button(button), false) => self.key_up(button),
+            (Action::Quit, true) => return true,
+            (Action::Screenshot, true) => {
+                let path = self.config.screenshot_dir.join(format!("screenshot{}.bmp", chrono::offset::Local::now()));
+                let saved = fs::create_dir_all(&self.config.screenshot_dir).map_err(|e| e.to_string())
+                    .and_then(|_| self.screenshot(&path));
+                if let Err(e) = saved {
+                    println!("Couldn't save screenshot: {}", e);
+                }
+            },
+            (Action::Pause, true) => {
+                self.speed.paused = !self.speed.paused;
+                self.speed.reset_timing();
+                window.set_title(&speed_title(&self.speed)).ok();
+            },
+            (Action::FrameAdvance, true) => {
+                self.speed.paused = true;
+                self.speed.advance = true;
+                window.set_title(&speed_title(&self.speed)).ok();
+            },
+            (Action::FastForward, down) => {
+                self.speed.fast_forward = down;
+                window.set_title(&speed_title(&self.speed)).ok();
+            },
+            (Action::Turbo, true) => {
+                self.speed.turbo = !self.speed.turbo;
+                self.speed.reset_timing();
+                window.set_title(&speed_title(&self.speed)).ok();
+            },
+            (Action::Rewind, down) => *rewinding = down && self.rewind.is_some(),
+            (Action::Autofire(button), down) => self.buttons.layer.set_autofire(button, down),
+            (Action::RecordMacro, true) => {
+                let message = if !self.buttons.layer.is_recording() {
+                    self.buttons.layer.start_recording();
+                    "Recording macro".to_string()
+                } else {
+                    match self.buttons.layer.stop_recording() {
+                        Ok(Some(index)) => {
+                            let recorded = &self.buttons.layer.macros[index];
+                            format!("Recorded {}, {} frames", recorded.name, recorded.frames.len())
+                        },
+                        Ok(None) => "Nothing recorded".to_string(),
+                        Err(e) => e
+                    }
+                };
+                println!("{}", message);
+                window.set_title(&format!("Rust Boy - {}", message)).ok();
+            },
+            (Action::Macro(index), true) => {
+                if self.buttons.layer.play(index) {
+                    let message = format!("Playing {}", self.buttons.layer.macros[index].name);
+                    println!("{}", message);
+                    window.set_title(&format!("Rust Boy - {}", message)).ok();
+                }
+            },
+            (Action::Cheat(index), true) => {
+                if let Some(enabled) = self.toggle_cheat(index) {
+                    let message = format!("Cheat {} {}", index + 1, if enabled { "on" } else { "off" });
+                    println!("{}", message);
+                    window.set_title(&format!("Rust Boy - {}", message)).ok();
+                }
+            },
+            _ => {}
+        }
+        return false;
+    }
+
     pub fn screenshot(&mut self, path: & Path) -> Result<(), String> {
         let surface = Surface::new(160, 144, PixelFormatEnum::RGB24).unwrap();
         let mut canvas = surface.into_canvas()
@@ -198,779 +501,469 @@ impl Engine {
     pub fn run_limited(&mut self, itrs: u64) -> u64{
         let mut total_steps = 0 as u64;
         for i in 0..itrs {
-            let wait_time = self.execute_next_instruction();
+            // the debugger or gdb asked to stop
+            if self.events.contains(&EngineEvent::Quit) {
+                break;
+            }
 
-            self.gpu.tick(&mut self.memory, wait_time);
-            self.clock.tick(&mut self.memory, wait_time);
+            // the GPU and timer are ticked along with each memory access
+            let wait_time = self.execute_next_instruction();
 
             total_steps += wait_time as u64;
         }
         return total_steps;
     }
 
-    fn get_d8(&self, start: u16) -> u8 {
-        return self.memory.get(start);
-    }
-
-    fn get_d16(&self, start: u16) -> u16 {
-        return ((self.memory.get(start+1) as u16) << 8)
-              + (self.memory.get(start) as u16);
+    /// Runs until the GPU has finished `frames` more frames, returns the clock cycles taken
+    pub fn run_frames(&mut self, frames: u64) -> u64 {
+        let target = self.gpu.frames + frames;
+        let mut total_steps = 0 as u64;
+        while self.gpu.frames < target && !self.events.contains(&EngineEvent::Quit) {
+            total_steps += self.execute_next_instruction() as u64;
+        }
+        return total_steps;
     }
 
-    fn get_r8(&self, start: u16) -> i8 {
-        return self.memory.get(start) as i8;
+    /// A key on the keyboard went down, a recording movie picks it up at the next frame
+    pub fn key_down(&mut self, key: KeyNames) {
+        match self.movie.as_mut() {
+            Some(movie) if movie.mode == MovieMode::Recording => movie.held |= ButtonState::key_mask(&key),
+            Some(movie) if movie.mode == MovieMode::Playing => {},
+            _ => self.buttons.setKeyDown(key, &mut self.memory)
+        }
     }
 
-    fn get_a16(&self, start: u16) -> u16 {
-        return ((self.memory.get(start+1) as u16) << 8)
-              + (self.memory.get(start) as u16);
+    pub fn key_up(&mut self, key: KeyNames) {
+        match self.movie.as_mut() {
+            Some(movie) if movie.mode == MovieMode::Recording => movie.held &= !ButtonState::key_mask(&key),
+            Some(movie) if movie.mode == MovieMode::Playing => {},
+            _ => self.buttons.setKeyUp(key, &mut self.memory)
+        }
     }
 
-    fn execute_next_instruction(&mut self) -> u32 {
-        self.buttons.updateMemory(&mut self.memory);
-        self.check_dma_transfer();
-
-        let interrupt_flags = self.memory.get(0xFF0F);
-        if self.enable_interrupt == InterruptState::HaltNoInterrupt {
-
-            if (interrupt_flags & self.memory.get(0xFFFF)) > 0 {
-                self.enable_interrupt = InterruptState::Disabled;
-                return 4 + 16; //how long should this take?
-            }
-        } else if self.enable_interrupt == InterruptState::Enabled || self.enable_interrupt == InterruptState::Halt {
-            if ((interrupt_flags & self.memory.get(0xFFFF)) & 0x01) > 0 {
-                self.enable_interrupt = InterruptState::Disabled;
-                let register_val = self.registers.pc;
-                //println!("00 {}, {:x}", self.registers, interrupt_flags);
-
-                self.memory.push_stack(&mut self.registers, register_val);
-                self.registers.pc = 0x0040;
-                self.memory.set(0xFF0F, interrupt_flags - 0x01);
-                return 12;
-            } else if ((interrupt_flags & self.memory.get(0xFFFF)) & 0x02) > 0 {
-                self.enable_interrupt = InterruptState::Disabled;
-                let register_val = self.registers.pc;
-                //println!("01 {}, {:x}", self.registers, interrupt_flags);
-
-                self.memory.push_stack(&mut self.registers, register_val);
-                self.registers.pc = 0x0048;
-                self.memory.set(0xFF0F, interrupt_flags - 0x02);
-                return 12;
-            } else if ((interrupt_flags & self.memory.get(0xFFFF)) & 0x04) > 0 {
-                self.enable_interrupt = InterruptState::Disabled;
-                let register_val = self.registers.pc;
-                //println!("02 {}, {:x}", self.registers, interrupt_flags);
-
-                self.memory.push_stack(&mut self.registers, register_val);
-                self.registers.pc = 0x0050;
-                self.memory.set(0xFF0F, interrupt_flags - 0x04);
-                return 12;
-            } else if ((interrupt_flags & self.memory.get(0xFFFF)) & 0x08) > 0 {
-                self.enable_interrupt = InterruptState::Disabled;
-                let register_val = self.registers.pc;
-                //println!("03 {}, {:x}", self.registers, interrupt_flags);
-                self.memory.push_stack(&mut self.registers, register_val);
-                self.registers.pc = 0x0058;
-                self.memory.set(0xFF0F, interrupt_flags - 0x08);
-                return 12;
-            } else if ((interrupt_flags & self.memory.get(0xFFFF)) & 0x10) > 0 {
-                self.enable_interrupt = InterruptState::Disabled;
-                let register_val = self.registers.pc;
-                //println!("04 {}, {:x}", self.registers, interrupt_flags);
-                self.memory.push_stack(&mut self.registers, register_val);
-                self.registers.pc = 0x0060;
-                self.memory.set(0xFF0F, interrupt_flags - 0x10);
-                //println!("Interrupted input with {:b}", self.memory.get(0xFF00));
-                return 12;
+    /// Records or plays back `movie` from the next frame. Playback is only exact from the
+    /// state the movie was recorded at, so start a power on or `.sav` movie straight after
+    /// `make_engine`; one anchored to a snapshot loads it first.
+    pub fn start_movie(&mut self, mut movie: Movie) -> Result<(), String> {
+        if movie.mode == MovieMode::Playing {
+            let ram = match &movie.anchor {
+                Anchor::PowerOn => vec![0; self.memory.save().len()],
+                Anchor::SaveRam(ram) => ram.clone(),
+                Anchor::State(state) => {
+                    self.load_state(state).map_err(|e| format!("Movie's starting state: {}", e))?;
+                    vec![]
+                }
+            };
+            if !ram.is_empty() {
+                self.memory.load(ram);
             }
         }
+        movie.held = self.buttons.keys();
+        movie.next_frame = self.gpu.frames;
+        movie.position = 0;
+        self.movie = Some(movie);
+        return Ok(());
+    }
 
+    /// Snapshot of the whole machine but the ROM, for `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::make_state_writer();
+        self.registers.save_state(&mut out);
+        self.interrupts.save_state(&mut out);
+        self.gpu.save_state(&mut out);
+        self.clock.save_state(&mut out);
+        self.buttons.save_state(&mut out);
+        self.memory.save_state(&mut out);
+        out.bool(self.boot_rom_mapped);
+        return out.data;
+    }
 
-        if self.enable_interrupt == InterruptState::Halt || self.enable_interrupt == InterruptState::HaltNoInterrupt {
-            return 4; // sleep until an interrupt happens
+    /// Goes back to a `save_state` snapshot taken with the same ROM
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let mut input = StateReader::make_state_reader(state);
+        self.registers.load_state(&mut input)?;
+        self.interrupts.load_state(&mut input)?;
+        self.gpu.load_state(&mut input)?;
+        self.clock.load_state(&mut input)?;
+        self.buttons.load_state(&mut input)?;
+        self.memory.load_state(&mut input)?;
+        let boot_rom_mapped = input.bool()?;
+        if boot_rom_mapped && self.boot_rom.is_empty() {
+            return Err("Snapshot was taken while the boot ROM ran, load the boot ROM too".to_string());
         }
-
-        if self.enable_interrupt == InterruptState::EnabledNextOp{
-            self.enable_interrupt = InterruptState::Enabled;
-            //panic!("enable");
+        self.boot_rom_mapped = boot_rom_mapped;
+        if !input.is_done() {
+            return Err("Snapshot is longer than expected, is it from another ROM?".to_string());
         }
+        // the snapshot's copy of 0xFF00 has the buttons held back then
+        self.memory.set(0xFF00, self.buttons.read());
+        return Ok(());
+    }
 
-        //println!("{}", self.registers);
-        let first_byte = self.memory.get(self.registers.pc);
-
-        //println!("{:x?} -> {:x?}", self.registers.pc, first_byte);
-        let first_nibble = first_byte >> 4;
-        let second_nibble = first_byte & 0x0F;
-        //println!("Second nibble {}", second_nibble);
-
-        let resolved_second_register = match second_nibble {
-            0x0 => RegisterNames::B,
-            0x1 => RegisterNames::C,
-            0x2 => RegisterNames::D,
-            0x3 => RegisterNames::E,
-            0x4 => RegisterNames::H,
-            0x5 => RegisterNames::L,
-            0x6 => RegisterNames::HL,
-            0x7 => RegisterNames::A,
-            0x8 => RegisterNames::B,
-            0x9 => RegisterNames::C,
-            0xA => RegisterNames::D,
-            0xB => RegisterNames::E,
-            0xC => RegisterNames::H,
-            0xD => RegisterNames::L,
-            0xE => RegisterNames::HL,
-            0xF => RegisterNames::A,
-            _ => {
-                panic!("Bad nibble size {:?} from {:?}", second_nibble, first_byte);
-            }
+    /// Steps back to the last snapshot taken before this frame, false once there are none left
+    pub fn rewind_step(&mut self) -> bool {
+        let rewind = match self.rewind.as_mut() {
+            Some(rewind) => rewind,
+            None => return false
         };
+        while let Some((frame, state)) = rewind.pop() {
+            // the snapshot taken at the start of this frame is where we already are
+            if frame >= self.gpu.frames && rewind.len() > 0 {
+                continue;
+            }
+            rewind.next_frame = frame;
+            let loaded = self.load_state(&state);
+            return loaded.is_ok();
+        }
+        return false;
+    }
 
-        //println!("{:?} = {:?}, {:?}", first_byte, second_nibble, first_nibble);
-        let steps = match first_byte {
-            0x00 => {
-                // no op
-                self.registers.incr_pc(1);
-                return 4;
-            },
-
-            0x01 | 0x11 | 0x21 | 0x31 => { //2 byte load
-                let resolved_register = match first_byte {
-                    0x01 => RegisterNames::BC,
-                    0x11 => RegisterNames::DE,
-                    0x21 => RegisterNames::HL,
-                    0x31 => RegisterNames::SP,
-                    _ => panic!("how did we get here?")
-                };
-
-                let d16 = self.get_d16(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                self.registers.set_register(&resolved_register, d16);
-
-                self.registers.incr_pc(3);
-                return 12;
-            },
-
-            0x03 | 0x0B | 0x13 | 0x1B | 0x23 | 0x2B | 0x33 | 0x3B => {
-                let resolved_register = match first_byte {
-                    0x03 | 0x0B => RegisterNames::BC,
-                    0x13 | 0x1B => RegisterNames::DE,
-                    0x23 | 0x2B => RegisterNames::HL,
-                    0x33 | 0x3B => RegisterNames::SP,
-                    _ => panic!("how did we get here?")
-                };
-
-                if first_byte & 0x0F == 3 {
-                    self.registers.change_register(resolved_register, 1);
-                } else {
-                    self.registers.change_register(resolved_register, -1);
-                }
-                self.registers.incr_pc(1);
-
-                return 8;
-            },
-
-            0x04 => self.inc(RegisterNames::B, false),
-            0x05 => self.dec(RegisterNames::B, false),
-            0x0C => self.inc(RegisterNames::C, false),
-            0x0D => self.dec(RegisterNames::C, false),
-
-            0x14 => self.inc(RegisterNames::D, false),
-            0x15 => self.dec(RegisterNames::D, false),
-            0x1C => self.inc(RegisterNames::E, false),
-            0x1D => self.dec(RegisterNames::E, false),
-
-            0x24 => self.inc(RegisterNames::H, false),
-            0x25 => self.dec(RegisterNames::H, false),
-            0x2C => self.inc(RegisterNames::L, false),
-            0x2D => self.dec(RegisterNames::L, false),
-
-            0x34 => self.inc(RegisterNames::HL, true),
-            0x35 => self.dec(RegisterNames::HL, true),
-            0x3C => self.inc(RegisterNames::A, false),
-            0x3D => self.dec(RegisterNames::A, false),
-
-            //daa
-            0x27 => {
-                let old_sub = self.registers.is_subtract_flag();
-
-                let (a, c) = Engine::daa(self.registers.get_register(&RegisterNames::A),
-                    self.registers.is_cary_flag(),
-                    self.registers.is_half_cary_flag(),
-                    !old_sub);
-
-                self.registers.set_register(&RegisterNames::A, a);
-                self.registers.set_flags(a & 0xFF == 0, old_sub, false, c);
-                self.registers.incr_pc(1);
-                return 4;
-            },
-
-            0x37 => {
-                let zero_flag = self.registers.is_zero_flag();
-
-                self.registers.set_flags(zero_flag, false, false, true);
+    /// Input for a movie frame is recorded or set before its first instruction
+    fn update_movie(&mut self) {
+        let movie = match self.movie.as_mut() {
+            Some(movie) if movie.mode != MovieMode::Finished && self.gpu.frames >= movie.next_frame => movie,
+            _ => return
+        };
+        movie.next_frame = self.gpu.frames + 1;
+        let pressed = if movie.mode == MovieMode::Recording {
+            let pressed = self.buttons.start_frame(movie.held);
+            movie.frames.push(pressed);
+            pressed
+        } else if let Some(pressed) = movie.frames.get(movie.position) {
+            *pressed
+        } else {
+            movie.mode = MovieMode::Finished;
+            self.events.push(EngineEvent::MovieFinished { frames: movie.position });
+            0
+        };
+        movie.position += 1;
+        self.buttons.set_pressed(pressed, &mut self.memory);
+    }
 
-                self.registers.incr_pc(1);
-                return 4
+    /// Runs one instruction (or interrupt dispatch), returns how many clock cycles it took
+    fn execute_next_instruction(&mut self) -> u32 {
+        if self.movie.is_some() {
+            self.update_movie();
+        } else {
+            self.buttons.update_frame(self.gpu.frames, &mut self.memory);
+        }
+        if self.rewind.as_ref().map_or(false, |rewind| self.gpu.frames >= rewind.next_frame) {
+            let state = self.save_state();
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.next_frame = self.gpu.frames + rewind.interval;
+                rewind.push(self.gpu.frames, state);
             }
+        }
+        self.check_dma_transfer();
+        self.step_cycles = 0;
 
-            0x02 | 0x12 | 0x22 | 0x32 | 0x0A | 0x1A | 0x2A | 0x3A => {
-                let memory_loc = match first_byte & 0xF0 {
-                    0x00 => self.registers.get_register(&RegisterNames::BC),
-                    0x10 => self.registers.get_register(&RegisterNames::DE),
-                    0x20 | 0x30 => self.registers.get_register(&RegisterNames::HL),
-                    _ => panic!("how did we get here?")
-                };
+        if self.gpu.frames != self.cheats.last_frame {
+            self.cheats.last_frame = self.gpu.frames;
+            self.cheats.write_ram(&mut *self.memory);
+        }
 
-                if first_byte % 16 == 2 {
-                    self.memory.set(memory_loc, self.registers.get_register(&RegisterNames::A) as u8);
-                } else {
-                    let memory_val = self.memory.get(memory_loc);
-                    //println!("loading {:x} -> ({:x})", memory_loc, memory_val);
-                    self.registers.set_register(&RegisterNames::A, memory_val as u16);
+        let pending = Interrupts::pending(&*self.memory);
+        match self.interrupts.state {
+            CpuState::Halted => {
+                if pending == 0 {
+                    self.tick(); // sleep until an interrupt happens
+                    if let Some(profiler) = self.profiler.as_mut() {
+                        profiler.halted(self.step_cycles);
+                    }
+                    return self.step_cycles;
                 }
-
-                 match first_byte {
-                     0x22 | 0x2A => self.registers.change_register(RegisterNames::HL, 1),
-                     0x32 | 0x3A => self.registers.change_register(RegisterNames::HL, -1),
-                     _ => {}
-                 };
-
-                self.registers.incr_pc(1);
-                return 8;
+                self.interrupts.state = CpuState::Running;
             },
-
-            0x08 => {
-                let target = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                self.memory.setLong(target, self.registers.get_register(&RegisterNames::SP));
-
-                self.registers.incr_pc(3);
-                return 20;
-            },
-
-            0x09 | 0x19 | 0x29 | 0x39 => {
-                let other_val = match first_byte {
-                    0x09 => self.registers.get_register(&RegisterNames::BC),
-                    0x19 => self.registers.get_register(&RegisterNames::DE),
-                    0x29 => self.registers.get_register(&RegisterNames::HL),
-                    0x39 => self.registers.get_register(&RegisterNames::SP),
-                    _ => panic!("How did we get here?")
-                };
-
-                // zero flag doesn't get changed
-                let old_z = self.registers.is_zero_flag();
-
-                self.math_to_reg_reshl(&RegisterNames::HL, MathNames::ADD, other_val, false);
-
-                //println!("A: {}, {}, {}", self.registers.is_zero_flag(), self.registers.is_cary_flag(), self.registers.is_half_cary_flag());
-                self.registers.set_zero_flag(old_z);
-
-                self.registers.incr_pc(1);
-                return 8;
-            },
-
-            0x10 => {
-                self.registers.incr_pc(2);
-                return 8;
-            },
-
-            0x2F => {
-                let oldZ = self.registers.is_zero_flag();
-
-                let carry =  if first_byte == 0x2F {self.registers.is_cary_flag()}
-                                            else{ !self.registers.is_cary_flag()};
-
-                let oldA = self.registers.get_register(&RegisterNames::A);
-
-                self.registers.set_register(&RegisterNames::A, !oldA);
-                self.registers.set_flags(oldZ, first_byte == 0x2F, first_byte == 0x2F, carry);
-
-                self.registers.incr_pc(1);
-                return 4;
+            CpuState::Stopped => {
+                if !self.buttons.any_pressed() {
+                    self.tick();
+                    if let Some(profiler) = self.profiler.as_mut() {
+                        profiler.halted(self.step_cycles);
+                    }
+                    return self.step_cycles;
+                }
+                self.interrupts.state = CpuState::Running;
             },
-
-            0x3F => {
-                let oldZ = self.registers.is_zero_flag();
-
-                let carry =  self.registers.is_cary_flag();
-
-                self.registers.set_flags(oldZ, false, false, !carry);
-
-                self.registers.incr_pc(1);
-                return 4;
+            CpuState::Locked => {
+                self.tick();
+                return self.step_cycles;
             },
+            CpuState::Running => {}
+        }
 
-            0x36 => {
-                let memory_loc = self.registers.get_register(&RegisterNames::HL);
-
-                let d8 = self.get_d8(self.registers.get_register(&RegisterNames::PC) + 1);
+        if self.interrupts.ime && pending != 0 {
+            self.dispatch_interrupt();
+            if self.profiler.is_some() {
+                self.profile_interrupt();
+            }
+            return self.step_cycles;
+        }
 
-                self.memory.set(memory_loc, d8);
+        if let Some(mut debugger) = self.debugger.take() {
+            debugger.before_instruction(self);
+            self.debugger = Some(debugger);
+        }
 
-                self.registers.incr_pc(2);
-                return 12;
+        if let Some(mut gdb) = self.gdb.take() {
+            gdb.before_instruction(self);
+            if !gdb.detached {
+                self.gdb = Some(gdb);
             }
+        }
 
-            0x06 | 0x16 | 0x26 | 0x0E | 0x1E | 0x2E | 0x3E => {
-                let target_register = match first_byte {
-                    0x06 => RegisterNames::B,
-                    0x16 => RegisterNames::D,
-                    0x26 => RegisterNames::H,
-                    0x0E => RegisterNames::C,
-                    0x1E => RegisterNames::E,
-                    0x2E => RegisterNames::L,
-                    0x3E => RegisterNames::A,
-                    _ => panic!("how did we get here?")
-                };
-                let source_value
-                      = self.get_d8(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                self.registers.set_register(&target_register, source_value as u16);
-                self.registers.incr_pc(2);
-                return 8;
-            },
+        if self.events.contains(&EngineEvent::Quit) {
+            return self.step_cycles;
+        }
 
-            0x07 => {
-                self.math_to_a(MathNames::RL, 0);
-                self.registers.set_zero_flag(false);
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.log(&self.registers, &*self.memory, &self.symbols);
+        }
 
-                self.registers.incr_pc(1);
-                return 4;
-            },
-            0x17 => {
-                self.math_to_a(MathNames::RLC, 0);
-                self.registers.set_zero_flag(false);
+        // where the instruction is, its SP and what it is, for the profiler
+        let profiled = match self.profiler {
+            Some(_) => Some(((self.bank_of(self.registers.pc), self.registers.pc), self.registers.sp,
+                             opcodes::decode(&*self.memory, self.registers.pc).op)),
+            None => None
+        };
 
-                self.registers.incr_pc(1);
-                return 4;
-            }
+        let ei_was_pending = self.interrupts.ime_pending;
+        self.execute_instruction();
+        self.interrupts.finish_instruction(ei_was_pending);
 
-            0x0F => {
-                self.math_to_a(MathNames::RR, 0);
-                self.registers.set_zero_flag(false);
+        if let Some((location, sp, op)) = profiled {
+            self.profile_instruction(location, sp, op);
+        }
 
-                self.registers.incr_pc(1);
-                return 4;
-            },
-            0x1F => {
-                self.math_to_a(MathNames::RRC, 0);
-                self.registers.set_zero_flag(false);
+        return self.step_cycles;
+    }
 
-                self.registers.incr_pc(1);
-                return 4;
+    /// Counts the instruction that just ran from `location` with SP at `sp`, and any call or return it made
+    fn profile_instruction(&mut self, location: Location, sp: u16, op: Op) {
+        let target = (self.bank_of(self.registers.pc), self.registers.pc);
+        let new_sp = self.registers.sp;
+        let cycles = self.step_cycles;
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.instruction(location, cycles);
+            match op {
+                // only when the call was taken, which pushed the return address
+                Op::Call | Op::Rst if new_sp == sp.wrapping_sub(2) => profiler.call(target, new_sp),
+                Op::Ret | Op::Reti if new_sp == sp.wrapping_add(2) => profiler.ret(sp),
+                _ => {}
             }
+        }
+    }
 
-            // jump relative
-            0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
-                let decide_to_jump = match first_byte {
-                    0x18 => true,
-                    0x20 => !self.registers.is_zero_flag(),//nz
-                    0x28 => self.registers.is_zero_flag(),//z
-                    0x30 => !self.registers.is_cary_flag(),//nc
-                    0x38 => self.registers.is_cary_flag(),//c
-                    _ => panic!("how did we get here?")
-                };
-
-                if !decide_to_jump {
-                    self.registers.incr_pc(2);
-                    return 8;
-                } else {
-                    let to_increase = self.get_r8(self.registers.get_register(&RegisterNames::PC) + 1);
-                    self.registers.incr_pc(to_increase as i32 + 2); //+2 to handle current instr
-                    return 12;
-                }
-            }
+    /// Tells the profiler about the interrupt handler that was just entered
+    fn profile_interrupt(&mut self) {
+        let handler = (self.bank_of(self.registers.pc), self.registers.pc);
+        let (sp, cycles) = (self.registers.sp, self.step_cycles);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.interrupt(handler, sp, cycles);
+        }
+    }
 
-            // load block
-            0x40..=0x7F => {
-                if first_byte == 0x76 {
-                    self.registers.incr_pc(1);
-                    if self.enable_interrupt == InterruptState::Disabled {
-                        self.enable_interrupt = InterruptState::HaltNoInterrupt;
-                        //println!("{}", self.registers);
-                        //self.registers.incr_pc(1);
-                        //panic!("weird Halt");
-                    } else {
-                        self.enable_interrupt = InterruptState::Halt;
-                    }
-                    //panic!("{:x?}", self.memory.get(0xFFFF));
-                    return 4;
-                } else {
-                    let resolved_first_register = match first_byte {
-                        0x40..=0x47 => RegisterNames::B,
-                        0x48..=0x4F => RegisterNames::C,
-                        0x50..=0x57 => RegisterNames::D,
-                        0x58..=0x5F => RegisterNames::E,
-                        0x60..=0x67 => RegisterNames::H,
-                        0x68..=0x6F => RegisterNames::L,
-                        0x70..=0x77 => RegisterNames::HL,
-                        0x78..=0x7F => RegisterNames::A,
-                        _ => {
-                            panic!("first byte was modified");
-                        }
-                    };
+    /// Executes the instruction at PC and nothing else: no interrupts, DMA or joypad. Along
+    /// with `cpu_only` this means only the CPU touches memory, which the single step tests need.
+    pub fn step_cpu(&mut self) -> u32 {
+        self.step_cycles = 0;
 
+        let ei_was_pending = self.interrupts.ime_pending;
+        self.execute_instruction();
+        self.interrupts.finish_instruction(ei_was_pending);
 
-                    let mut initial = self.registers.get_register(&resolved_second_register);
-                    if resolved_second_register == RegisterNames::HL{
-                        initial = self.memory.get(initial) as u16;
-                    }
-                    if resolved_first_register == RegisterNames::HL {
-                        let hl_val = self.registers.get_register(&RegisterNames::HL);
-                        self.memory.set(hl_val, initial as u8);
-                    } else {
-                        self.registers.set_register(&resolved_first_register, initial);
-                    }
+        return self.step_cycles;
+    }
 
-                    self.registers.incr_pc(1);
+    /// Advances everything but the CPU by one M-cycle (4 clock cycles)
+    fn tick(&mut self) {
+        self.step_cycles += 4;
 
-                    if resolved_first_register == RegisterNames::HL || resolved_second_register == RegisterNames::HL {
-                        return 8;
-                    }
-                    return 4;
-                }
-            },
-            0x80..=0x87 => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::ADD, other_value);
-                self.registers.incr_pc(1);
+        if self.cpu_only {
+            return;
+        }
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+        self.gpu.tick(&mut self.memory, 4);
+        self.clock.tick(&mut self.memory, 4);
+    }
 
-            0x88..=0x8F => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::ADDC, other_value);
-                self.registers.incr_pc(1);
+    /// A CPU read, which takes an M-cycle
+    fn cycle_read(&mut self, addr: u16) -> u8 {
+        return self.cycle_read_as(addr, cdl::DATA, hooks::READ);
+    }
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+    /// A read of the instruction itself, `flag` says whether it's the opcode or an operand
+    fn cycle_fetch(&mut self, addr: u16, flag: u8) -> u8 {
+        return self.cycle_read_as(addr, flag, hooks::READ);
+    }
 
-            0x90..=0x97 => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::SUB, other_value);
-                self.registers.incr_pc(1);
+    /// `cdl_flag` is what the code/data log records, `hook_kind` which hooks get to see it
+    fn cycle_read_as(&mut self, addr: u16, cdl_flag: u8, hook_kind: u8) -> u8 {
+        self.tick();
+        let mut val = if addr == 0xFF00 {
+            self.buttons.read()
+        } else if addr == 0xFF44 && self.tracer.as_ref().map_or(false, |t| t.stub_ly) {
+            0x90
+        } else if addr < 0x100 && self.boot_rom_mapped {
+            self.boot_rom[addr as usize]
+        } else {
+            self.memory.get(addr)
+        };
+        if !self.hooks.is_empty() {
+            val = match self.hooks.check(hook_kind, addr, val) {
+                Intercept::Pass => val,
+                Intercept::Value(replacement) => replacement,
+                Intercept::Drop => 0xFF
+            };
+        }
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.log(addr, self.memory.rom_bank(), cdl_flag);
+        }
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.check_access(addr, val, false);
+        }
+        if let Some(gdb) = self.gdb.as_mut() {
+            gdb.check_access(addr, false);
+        }
+        return val;
+    }
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+    /// A CPU write, which takes an M-cycle
+    fn cycle_write(&mut self, addr: u16, mut val: u8) {
+        self.tick();
+        let mut dropped = false;
+        if !self.hooks.is_empty() {
+            match self.hooks.check(hooks::WRITE, addr, val) {
+                Intercept::Pass => {},
+                Intercept::Value(replacement) => val = replacement,
+                Intercept::Drop => dropped = true
+            }
+        }
+        if !dropped && addr == 0xFF00 {
+            self.buttons.write(val, &mut self.memory);
+        } else if !dropped {
+            if addr == 0xFF50 && val != 0 {
+                self.boot_rom_mapped = false;
+            }
+            self.memory.set(addr, val);
+        }
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.check_access(addr, val, true);
+        }
+        if let Some(gdb) = self.gdb.as_mut() {
+            gdb.check_access(addr, true);
+        }
+    }
 
-            0x98..=0x9F => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::SUBC, other_value);
-                self.registers.incr_pc(1);
+    fn push(&mut self, val: u16) {
+        let sp = self.registers.sp.wrapping_sub(1);
+        self.cycle_write(sp, (val >> 8) as u8);
+        let sp = sp.wrapping_sub(1);
+        self.cycle_write(sp, (val & 0xFF) as u8);
+        self.registers.sp = sp;
+    }
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+    fn pop(&mut self) -> u16 {
+        let sp = self.registers.sp;
+        let low_byte = self.cycle_read(sp) as u16;
+        let high_byte = self.cycle_read(sp.wrapping_add(1)) as u16;
+        self.registers.sp = sp.wrapping_add(2);
 
-            0xA0..=0xA7 => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::AND, other_value);
-                self.registers.incr_pc(1);
+        return (high_byte << 8) + low_byte;
+    }
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+    fn dispatch_interrupt(&mut self) {
+        self.interrupts.disable();
+        self.tick();
+        self.tick();
 
-            0xA8..=0xAF => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::XOR, other_value);
-                self.registers.incr_pc(1);
+        let pc = self.registers.pc;
+        let mut sp = self.registers.sp.wrapping_sub(1);
+        self.cycle_write(sp, (pc >> 8) as u8);
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+        // the high byte push can overwrite IE (SP = 0x0000), which cancels the dispatch
+        let pending = Interrupts::pending(&*self.memory);
 
-            0xB0..=0xB7 => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::OR, other_value);
-                self.registers.incr_pc(1);
+        sp = sp.wrapping_sub(1);
+        self.cycle_write(sp, (pc & 0xFF) as u8);
+        self.registers.sp = sp;
+        self.tick();
 
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
+        match Interrupts::highest_priority(pending) {
+            Some(interrupt) => {
+                Interrupts::acknowledge(&mut *self.memory, interrupt);
+                self.registers.pc = Interrupts::vector(interrupt);
             },
+            None => {
+                self.registers.pc = 0x0000;
+            }
+        }
+    }
 
-            0xB8..=0xBF => {
-                let other_value = self.get_register_or_hl(&resolved_second_register);
-                self.math_to_a(MathNames::CP, other_value);
-                self.registers.incr_pc(1);
-                if resolved_second_register == RegisterNames::HL {
-                    return 8;
-                } else {
-                    return 4;
-                }
-            },
+    fn execute_instruction(&mut self) {
+        let opcode = self.cycle_read_as(self.registers.pc, cdl::OPCODE, hooks::EXECUTE);
 
-            0xC2 => { // jump nz
-                if self.registers.is_zero_flag() {
-                    self.registers.incr_pc(3);
+        if self.interrupts.take_halt_bug() {
+            // PC doesn't advance past the opcode, so the same byte is read again as the next one
+            self.registers.incr_pc(-1);
+        }
 
-                    return 12;
-                } else {
-                    let target = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-                    self.registers.set_register(&RegisterNames::PC, target);
+        let pc = self.registers.pc;
+        let mut instr = &OPCODES[opcode as usize];
 
-                    return 16;
-                }
-            },
+        if instr.op == Op::Illegal {
+            self.lock_up(opcode);
+            return;
+        }
+        if instr.op == Op::Prefix {
+            instr = &CB_OPCODES[self.cycle_fetch(pc.wrapping_add(1), cdl::OPCODE) as usize];
+        }
 
-            0xC3 => { // jump
-                //println!("jump {:?} , {:?}", self.rom[(self.registers.pc + 1) as usize], self.rom[(self.registers.pc + 2) as usize]);
-                let target = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-                //println!("target is {:?}", target);
-                self.registers.set_register(&RegisterNames::PC, target);
-                return 16;
-            },
-            0xE9 => { // jump hl
-                //println!("jump {:?} , {:?}", self.rom[(self.registers.pc + 1) as usize], self.rom[(self.registers.pc + 2) as usize]);
-                let target = self.registers.get_register(&RegisterNames::HL);
-                //println!("target is {:?}", target);
-                self.registers.set_register(&RegisterNames::PC, target);
-                return 4;
+        let imm = match instr.dst.immediate_bytes() + instr.src.immediate_bytes() {
+            1 => self.cycle_fetch(pc.wrapping_add(1), cdl::OPERAND) as u16,
+            2 => {
+                let low_byte = self.cycle_fetch(pc.wrapping_add(1), cdl::OPERAND) as u16;
+                let high_byte = self.cycle_fetch(pc.wrapping_add(2), cdl::OPERAND) as u16;
+                (high_byte << 8) + low_byte
             },
+            _ => 0
+        };
+        self.registers.pc = pc.wrapping_add(instr.length as u16);
 
-            0xCB => self.run_cb(),
-
-            // returns
-            0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9=> {
-                let decide_to_jump = match first_byte {
-                    0xC9 | 0xD9=> true,
-                    0xC0 => !self.registers.is_zero_flag(),//nz
-                    0xC8 => self.registers.is_zero_flag(),//z
-                    0xD0 => !self.registers.is_cary_flag(),//nc
-                    0xD8 => self.registers.is_cary_flag(),//c
-                    _ => panic!("how did we get here?")
-                };
-
-                if first_byte == 0xD9 {
-                    self.enable_interrupt = InterruptState::Enabled;
-                }
+        self.execute(instr, imm);
+    }
 
-                self.registers.incr_pc(1);
-                if !decide_to_jump {
-                    return 8;
+    /// Runs a decoded instruction with PC already past it and the immediate fetched.
+    ///
+    /// Memory goes through `cycle_read` / `cycle_write` and internal delays through `tick`
+    /// so everything else sees each access on the M-cycle it happens in.
+    fn execute(&mut self, instr: &Instruction, imm: u16) {
+        match instr.op {
+            Op::Nop | Op::Prefix | Op::Illegal => {},
+
+            Op::Stop => self.interrupts.stop(),
+            Op::Halt => self.interrupts.halt(&*self.memory),
+            Op::Di => self.interrupts.disable(),
+            Op::Ei => self.interrupts.enable_delayed(),
+
+            Op::Ld => {
+                let value = self.read_operand(instr.src, imm);
+
+                if instr.dst == Operand::IndA16 && instr.src.is_wide() {
+                    self.cycle_write(imm, (value & 0xFF) as u8);
+                    self.cycle_write(imm.wrapping_add(1), (value >> 8) as u8);
                 } else {
-                    let new_pc = self.memory.pop_stack(&mut self.registers);
-                    self.registers.set_register(&RegisterNames::PC, new_pc);
-
-                    if first_byte == 0xC9 || first_byte == 0xD9 {
-                        return 16;
-                    } else {
-                        return 20;
+                    if instr.dst == Operand::SP && instr.src == Operand::HL {
+                        self.tick();
                     }
+                    self.write_operand(instr.dst, imm, value);
                 }
             },
 
-            0xC1 | 0xC5 | 0xD1 | 0xD5 | 0xE1 | 0xE5 | 0xF1 | 0xF5 => {
-                let reg = match first_byte {
-                    0xC1 | 0xC5 => RegisterNames::BC,
-                    0xD1 | 0xD5 => RegisterNames::DE,
-                    0xE1 | 0xE5 => RegisterNames::HL,
-                    0xF1 | 0xF5 => RegisterNames::AF,
-                    _ => panic!("how did we get here")
-                };
-
-                self.registers.incr_pc(1);
-
-                if second_nibble == 1 {
-                    let new_reg = self.memory.pop_stack(&mut self.registers);
-                    self.registers.set_register(&reg, new_reg);
-                    return 12;
-                } else {
-                    let register_val = self.registers.get_register(&reg);
-                    self.memory.push_stack(&mut self.registers, register_val);
-                    return 16;
-                }
-            },
-
-            // call
-            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC=> {
-                let decide_to_jump = match first_byte {
-                    0xCD => true,
-                    0xC4 => !self.registers.is_zero_flag(),//nz
-                    0xCC => self.registers.is_zero_flag(),//z
-                    0xD4 => !self.registers.is_cary_flag(),//nc
-                    0xDC => self.registers.is_cary_flag(),//c
-                    _ => panic!("how did we get here?")
-                };
-
-                let a16 = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                self.registers.incr_pc(3);
-                if !decide_to_jump {
-                    return 12;
-                } else {
-                    let old_pc = self.registers.get_register(&RegisterNames::PC);
-
-                    self.memory.push_stack(&mut self.registers, old_pc);
-
-                    self.registers.set_register(&RegisterNames::PC, a16);
-
-                    return 24;
-                }
-            },
-
-            0xCA | 0xC2 | 0xDA | 0xD2=> {
-                let decide_to_jump = match first_byte {
-                    0xCA => self.registers.is_zero_flag(),//z
-                    0xC2 => self.registers.is_zero_flag(),//z
-                    0xDA => self.registers.is_cary_flag(),//c
-                    0xD2 => !self.registers.is_cary_flag(),//nc
-                    _ => panic!("how did we get here?")
-                };
-
-                let a16 = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                self.registers.incr_pc(3);
-                if !decide_to_jump {
-                    return 12;
-                } else {
-                    self.registers.set_register(&RegisterNames::PC, a16);
-
-                    return 24;
-                }
-            },
-
-            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => { //d8 math
-                let math_type = match first_byte {
-                    0xC6 => MathNames::ADD,
-                    0xCE => MathNames::ADDC,
-                    0xD6 => MathNames::SUB,
-                    0xDE => MathNames::SUBC,
-                    0xE6 => MathNames::AND,
-                    0xEE => MathNames::XOR,
-                    0xF6 => MathNames::OR,
-                    0xFE => MathNames::CP,
-                    _ => panic!("how did we get here")
-                };
-                let other_value = self.get_d8(self.registers.get_register(&RegisterNames::PC) + 1) as u16;
-                self.math_to_a(math_type, other_value);
-                self.registers.incr_pc(2);
-                return 8;
-            },
-
-            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
-                let old_pc = self.registers.get_register(&RegisterNames::PC) + 1;
-                self.memory.push_stack(&mut self.registers, old_pc);
-
-                let new_pc = match first_byte {
-                    0xC7 => 0x0000,
-                    0xCF => 0x0008,
-                    0xD7 => 0x0010,
-                    0xDF => 0x0018,
-                    0xE7 => 0x0020,
-                    0xEF => 0x0028,
-                    0xF7 => 0x0030,
-                    0xFF => 0x0038,
-                    _ => panic!("how did we get here")
-                };
-
-
-
-                self.registers.set_register(&RegisterNames::PC, new_pc);
-
-                return 16;
-            },
-
-            0xE0 => { // load a into a8
-                let target = self.get_d8(self.registers.get_register(&RegisterNames::PC) + 1) as u16 + 0xFF00;
-
-                self.memory.set(target, self.registers.get_register(&RegisterNames::A) as u8);
-
-                self.registers.incr_pc(2);
-
-                return 12;
-            },
-
-            0xE2 => { // load a into a8
-                let target =self.registers.get_register(&RegisterNames::C) as u16 + 0xFF00;
-
-                self.memory.set(target, self.registers.get_register(&RegisterNames::A) as u8);
-
-                self.registers.incr_pc(1);
-
-                return 8;
-            },
-
-            0xE8 => {
-                let target = self.get_r8(self.registers.get_register(&RegisterNames::PC) + 1);
-                self.math_to_reg(&RegisterNames::SP, MathNames::ADD, target as u16);
-
-                self.registers.set_zero_flag(false);
-
-                self.registers.incr_pc(2);
-
-                return 16;
-            }
-
-            0xEA => { // load a into a16
-                let target = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                self.memory.set(target, self.registers.get_register(&RegisterNames::A) as u8);
-
-                self.registers.incr_pc(3);
-
-                return 16;
-            },
-
-            0xF0 => { // load a8 into a
-                let target = self.get_d8(self.registers.get_register(&RegisterNames::PC) + 1) as u16 + 0xFF00;
-
-                let res = self.memory.get(target);
-                //println!("{:x} -> ({:x})", target, res);
-                self.registers.set_register(&RegisterNames::A, res as u16);
-
-                self.registers.incr_pc(2);
-
-                return 12;
-            },
-
-            0xF2 => {
-                let target = self.memory.get(self.registers.get_register(&RegisterNames::C) as u16 + 0xFF00);
-                self.registers.set_register(&RegisterNames::A, target as u16);
-                self.registers.incr_pc(1);
-                return 8;
-            },
-
-            //interrupts
-            0xF3 => {
-                self.enable_interrupt = InterruptState::Disabled;
-                self.registers.incr_pc(1);
-                return 4;
-            },
-            0xFB => {
-                self.enable_interrupt = InterruptState::EnabledNextOp;
-                self.registers.incr_pc(1);
-                return 4;
-            },
-
-            0xF8 => {
+            Op::LdHlSp => {
                 let target = self.registers.get_register(&RegisterNames::SP) as i32;
-                let d8 = self.get_r8(self.registers.get_register(&RegisterNames::PC) + 1) as i32;
+                let d8 = imm as u8 as i8 as i32;
+                self.tick();
 
                 self.registers.set_register(&RegisterNames::HL, ((target + d8) & 0xFFFF) as u16);
 
@@ -980,293 +973,409 @@ impl Engine {
                                 ((target & 0x00F) + (d8 & 0x00F)) & 0x0010 == 0x0010,
                                 (target & 0x0FF) + (d8 & 0x0FF) > 0x00FF
                 );
-
-                self.registers.incr_pc(2);
-
-                return 12;
             },
 
-            0xF9 => {
-                let target = self.registers.get_register(&RegisterNames::HL);
-
-                self.registers.set_register(&RegisterNames::SP, target);
-
-                self.registers.incr_pc(1);
-
-                return 8;
+            Op::Push => {
+                let value = self.read_operand(instr.dst, imm);
+                self.tick();
+                self.push(value);
             },
 
-            0xFA => { // load a8 into a
-                let target = self.get_a16(self.registers.get_register(&RegisterNames::PC) + 1);
-
-                let res = self.memory.get(target);
-                self.registers.set_register(&RegisterNames::A, res as u16);
-
-                self.registers.incr_pc(3);
-
-                return 16;
+            Op::Pop => {
+                let value = self.pop();
+                self.write_operand(instr.dst, imm, value);
             },
 
-            _ => {
-                print!("Don't understand instr {:x?}", self.memory.get(self.registers.get_register(&RegisterNames::PC)));
+            Op::Inc | Op::Dec => {
+                let value = self.read_operand(instr.dst, imm);
 
-                self.registers.incr_pc(1);
+                if instr.dst.is_wide() {
+                    // 16 bit inc / dec doesn't touch the flags
+                    let res = if instr.op == Op::Inc {value.wrapping_add(1)} else {value.wrapping_sub(1)};
+                    self.write_operand(instr.dst, imm, res);
+                    self.tick();
+                } else {
+                    let res = self.inc_dec(instr.op, value as u8);
+                    self.write_operand(instr.dst, imm, res as u16);
+                }
+            },
 
-                return 16;
-            }
-        };
+            Op::Add if instr.dst == Operand::HL => {
+                let other_val = self.read_operand(instr.src, imm);
 
-        return steps;
-    }
+                // zero flag doesn't get changed
+                let old_z = self.registers.is_zero_flag();
 
-    fn check_dma_transfer(&mut self) {
-        //"The DMA Transfer (40*28 bit) from internal ROM or RAM ($0000-$F19F) to the OAM (address $FE00-$FE9F)"
+                self.math_to_reg_reshl(&RegisterNames::HL, MathNames::ADD, other_val, false);
+                self.tick();
 
-        let start_addr = self.memory.get(0xFF46) as u16;
+                self.registers.set_zero_flag(old_z);
+            },
 
-        if start_addr > 0 {
-            for i in 0..(40*4) {
-                self.memory.set(0xFE00 + i, self.memory.get(start_addr * 0x100 + i));
-            }
-        }
-        self.memory.set(0xFF46, 0);
-    }
+            Op::Add if instr.dst == Operand::SP => {
+                let offset = imm as u8 as i8;
+                self.math_to_reg(&RegisterNames::SP, MathNames::ADD, offset as u16);
+                self.tick();
+                self.tick();
 
-    fn get_register_or_hl(&mut self, register: &RegisterNames) -> u16 {
-        let reg_val = self.registers.get_register(&register);
+                self.registers.set_zero_flag(false);
+            },
 
-        if register == &RegisterNames::HL {
-            self.memory.get(reg_val) as u16
-        } else {
-            reg_val
-        }
-    }
+            Op::Add | Op::Adc | Op::Sub | Op::Sbc | Op::And | Op::Xor | Op::Or | Op::Cp => {
+                let math_type = match instr.op {
+                    Op::Add => MathNames::ADD,
+                    Op::Adc => MathNames::ADDC,
+                    Op::Sub => MathNames::SUB,
+                    Op::Sbc => MathNames::SUBC,
+                    Op::And => MathNames::AND,
+                    Op::Xor => MathNames::XOR,
+                    Op::Or => MathNames::OR,
+                    _ => MathNames::CP
+                };
+                let other_value = self.read_operand(instr.src, imm);
+                self.math_to_a(math_type, other_value);
+            },
 
-    fn set_zero_flag_if_zero(&mut self, register: &RegisterNames) {
-        if self.get_register_or_hl(register) == 0 {
-            self.registers.set_zero_flag(true);
-        }
-    }
+            Op::Rlca | Op::Rla | Op::Rrca | Op::Rra => {
+                let shift_op = match instr.op {
+                    Op::Rlca => Op::Rlc,
+                    Op::Rla => Op::Rl,
+                    Op::Rrca => Op::Rrc,
+                    _ => Op::Rr
+                };
+                let a = self.registers.a;
+                self.registers.a = self.shift(shift_op, a);
+                self.registers.set_zero_flag(false);
+            },
 
-    fn run_cb(&mut self) -> u32 {
-        self.registers.incr_pc(1);
-
-        let first_byte = self.memory.get(self.registers.pc);
-
-        //println!("{}", self.registers);
-        //println!("{:x?} -> {:x?}", self.registers.pc, first_byte);
-        let first_nibble = first_byte >> 4;
-        let second_nibble = first_byte & 0x0F;
-
-        let resolved_register = match second_nibble {
-            0x0 => RegisterNames::B,
-            0x1 => RegisterNames::C,
-            0x2 => RegisterNames::D,
-            0x3 => RegisterNames::E,
-            0x4 => RegisterNames::H,
-            0x5 => RegisterNames::L,
-            0x6 => RegisterNames::HL,
-            0x7 => RegisterNames::A,
-            0x8 => RegisterNames::B,
-            0x9 => RegisterNames::C,
-            0xA => RegisterNames::D,
-            0xB => RegisterNames::E,
-            0xC => RegisterNames::H,
-            0xD => RegisterNames::L,
-            0xE => RegisterNames::HL,
-            0xF => RegisterNames::A,
-            _ => {
-                panic!("Bad nibble size {:?} from {:?}", second_nibble, first_byte);
-            }
-        };
+            Op::Daa => {
+                let old_sub = self.registers.is_subtract_flag();
 
-        let reg_val = self.registers.get_register(&resolved_register);
-        let initial_val;
-        if resolved_register == RegisterNames::HL {
-            initial_val = self.memory.get(reg_val);
-        } else {
-            initial_val = reg_val as u8;
-        }
+                let (a, c) = Engine::daa(self.registers.get_register(&RegisterNames::A),
+                    self.registers.is_cary_flag(),
+                    self.registers.is_half_cary_flag(),
+                    !old_sub);
+                self.registers.set_register(&RegisterNames::A, a);
+                self.registers.set_flags(a & 0xFF == 0, old_sub, false, c);
+            },
 
-        let res;
-        self.registers.incr_pc(1);
-        let steps = match first_byte {
-            0x00..=0x07 => {
-                self.math_to_reg_reshl(&resolved_register, MathNames::RL, 0, true);
+            Op::Cpl => {
+                let old_z = self.registers.is_zero_flag();
+                let carry = self.registers.is_cary_flag();
 
-                8
+                self.registers.a = !self.registers.a;
+                self.registers.set_flags(old_z, true, true, carry);
             },
-            0x08..=0x0F => {
-                self.math_to_reg_reshl(&resolved_register, MathNames::RR, 0, true);
 
-                8
-            },
-            0x10..=0x17 => {
-                self.math_to_reg_reshl(&resolved_register, MathNames::RLC, 0, true);
+            Op::Scf => {
+                let old_z = self.registers.is_zero_flag();
 
-                8
+                self.registers.set_flags(old_z, false, false, true);
             },
-            0x18..=0x1F => {
-                self.math_to_reg_reshl(&resolved_register, MathNames::RRC, 0, true);
 
-                8
+            Op::Ccf => {
+                let old_z = self.registers.is_zero_flag();
+                let carry = self.registers.is_cary_flag();
+
+                self.registers.set_flags(old_z, false, false, !carry);
             },
-            0x20..=0x27 => {
-                let res = (initial_val << 1) & 0xFF;
 
-                self.registers.set_flags(res == 0, false, false, initial_val >= 0x80);
-                if resolved_register == RegisterNames::HL {
-                    self.memory.set(reg_val, res);
-                    return 16;
-                } else {
-                    self.registers.set_register(&resolved_register, res as u16);
-                    return 8;
+            Op::Jr => {
+                if self.condition(instr.dst) {
+                    let offset = imm as u8 as i8;
+                    self.registers.pc = self.registers.pc.wrapping_add(offset as u16);
+                    self.tick();
                 }
             },
-            0x28..=0x2F => {
-                let res = ((initial_val >> 1) & 0xFF) | (initial_val & 0x80);
 
-                self.registers.set_flags(res == 0, false, false, initial_val % 2 == 1);
-                if resolved_register == RegisterNames::HL {
-                    self.memory.set(reg_val, res);
-                    return 16;
-                } else {
-                    self.registers.set_register(&resolved_register, res as u16);
-                    return 8;
+            Op::Jp => {
+                if instr.dst == Operand::HL {
+                    self.registers.pc = self.registers.get_register(&RegisterNames::HL);
+                } else if self.condition(instr.dst) {
+                    self.registers.pc = imm;
+                    self.tick();
                 }
             },
-            0x30..=0x37 => {
-                let result = (initial_val >> 4) + (initial_val << 4);
-
-                self.registers.set_flags(result == 0, false, false, false);
 
-                if resolved_register == RegisterNames::HL {
-                    self.memory.set(reg_val, result);
-                    return 16;
-                } else {
-                    self.registers.set_register(&resolved_register, result as u16);
-                    return 8;
+            Op::Call => {
+                if self.condition(instr.dst) {
+                    let return_pc = self.registers.pc;
+                    self.tick();
+                    self.push(return_pc);
+                    self.registers.pc = imm;
                 }
             },
-            0x38..=0x3F => {
-                res = initial_val >> 1;
-
-                self.registers.set_flags( res == 0, false, false, initial_val & 1 == 1);
 
-                if resolved_register == RegisterNames::HL {
-                    self.memory.set(reg_val, res);
-                    return 16;
-                } else {
-                    self.registers.set_register(&resolved_register, res as u16);
+            Op::Ret => {
+                if instr.dst != Operand::None {
+                    // checking the condition costs a cycle
+                    self.tick();
+                }
+                if self.condition(instr.dst) {
+                    self.registers.pc = self.pop();
+                    self.tick();
                 }
+            },
 
-                8
+            Op::Reti => {
+                self.registers.pc = self.pop();
+                self.tick();
+                self.interrupts.enable();
             },
 
-            0x40..=0x7F => {
-                let bit = (first_byte - 0x40) / 8;
+            Op::Rst => {
+                let return_pc = self.registers.pc;
+                self.tick();
+                self.push(return_pc);
+
+                if let Operand::Vector(vector) = instr.dst {
+                    self.registers.pc = vector;
+                }
+            },
 
-                let val = (initial_val & (1 << bit)) > 0;
+            Op::Rlc | Op::Rrc | Op::Rl | Op::Rr | Op::Sla | Op::Sra | Op::Swap | Op::Srl => {
+                let value = self.read_operand(instr.dst, imm) as u8;
+                let res = self.shift(instr.op, value);
+                self.write_operand(instr.dst, imm, res as u16);
+            },
 
+            Op::Bit => {
+                let value = self.read_operand(instr.src, imm);
                 let old_cary = self.registers.is_cary_flag();
 
-                self.registers.set_flags(!val, false, true, old_cary);
-
-                if resolved_register == RegisterNames::HL {
-                    return 16;
-                } else {
-                    return 8;
+                if let Operand::Bit(bit) = instr.dst {
+                    self.registers.set_flags(value & (1 << bit) == 0, false, true, old_cary);
                 }
             },
 
-            0x80..=0xFF => { //set a bit high or low
-                let high = first_byte >= 0xC0;
-                let bit = ((first_byte - 0x80) / 8) % 8;
+            Op::Res | Op::Set => {
+                let value = self.read_operand(instr.src, imm);
 
-                if high {
-                    res = initial_val | (1 << bit);
-                } else {
-                    res = initial_val & (!(1 << bit));
+                if let Operand::Bit(bit) = instr.dst {
+                    let res = if instr.op == Op::Set {value | (1 << bit)} else {value & !(1 << bit)};
+                    self.write_operand(instr.src, imm, res);
                 }
+            }
+        };
+    }
 
-                if resolved_register == RegisterNames::HL {
-                    self.memory.set(reg_val, res);
-                    return 16;
-                } else {
-                    self.registers.set_register(&resolved_register, res as u16);
-                }
+    fn condition(&mut self, operand: Operand) -> bool {
+        return match operand {
+            Operand::CondNZ => !self.registers.is_zero_flag(),
+            Operand::CondZ => self.registers.is_zero_flag(),
+            Operand::CondNC => !self.registers.is_cary_flag(),
+            Operand::CondC => self.registers.is_cary_flag(),
+            _ => true
+        };
+    }
 
-                8
-            }
+    fn operand_register(operand: Operand) -> Option<RegisterNames> {
+        return match operand {
+            Operand::A => Some(RegisterNames::A),
+            Operand::B => Some(RegisterNames::B),
+            Operand::C => Some(RegisterNames::C),
+            Operand::D => Some(RegisterNames::D),
+            Operand::E => Some(RegisterNames::E),
+            Operand::H => Some(RegisterNames::H),
+            Operand::L => Some(RegisterNames::L),
+            Operand::AF => Some(RegisterNames::AF),
+            Operand::BC => Some(RegisterNames::BC),
+            Operand::DE => Some(RegisterNames::DE),
+            Operand::HL => Some(RegisterNames::HL),
+            Operand::SP => Some(RegisterNames::SP),
+            _ => None
+        };
+    }
+
+    /// Memory address an indirect operand points at, post incrementing / decrementing HL for [hl+] / [hl-]
+    fn operand_address(&mut self, operand: Operand, imm: u16) -> u16 {
+        return match operand {
+            Operand::IndBC => self.registers.get_register(&RegisterNames::BC),
+            Operand::IndDE => self.registers.get_register(&RegisterNames::DE),
+            Operand::IndHL => self.registers.get_register(&RegisterNames::HL),
+            Operand::IndHLInc => {
+                let hl = self.registers.get_register(&RegisterNames::HL);
+                self.registers.set_register(&RegisterNames::HL, hl.wrapping_add(1));
+                hl
+            },
+            Operand::IndHLDec => {
+                let hl = self.registers.get_register(&RegisterNames::HL);
+                self.registers.set_register(&RegisterNames::HL, hl.wrapping_sub(1));
+                hl
+            },
+            Operand::IndA16 => imm,
+            Operand::IndA8 => 0xFF00 + (imm & 0xFF),
+            Operand::IndC => 0xFF00 + self.registers.c as u16,
+            _ => panic!("{:?} isn't an address", operand)
+        };
+    }
+
+    fn read_operand(&mut self, operand: Operand, imm: u16) -> u16 {
+        if let Some(register) = Engine::operand_register(operand) {
+            return self.registers.get_register(&register);
+        }
+
+        return match operand {
+            Operand::D8 | Operand::D16 | Operand::R8 | Operand::A16 => imm,
             _ => {
-                println!("unknown cb {:x?}", first_byte);
-                res = 0;
-                8
+                let addr = self.operand_address(operand, imm);
+                self.cycle_read(addr) as u16
             }
         };
+    }
 
+    fn write_operand(&mut self, operand: Operand, imm: u16, value: u16) {
+        if let Some(register) = Engine::operand_register(operand) {
+            self.registers.set_register(&register, value);
+            return;
+        }
 
-        return steps;
+        let addr = self.operand_address(operand, imm);
+        self.cycle_write(addr, value as u8);
     }
 
-    fn inc(&mut self, register: RegisterNames, resolve_hl: bool) -> u32 {
-        let currentFlags = self.registers.get_register(&RegisterNames::F);
+    /// 8 bit inc / dec, the carry flag is left alone
+    fn inc_dec(&mut self, op: Op, value: u8) -> u8 {
+        let old_cary = self.registers.is_cary_flag();
 
-        let currentCary = self.registers.is_cary_flag();
+        if op == Op::Inc {
+            let res = value.wrapping_add(1);
+            self.registers.set_flags(res == 0, false, value & 0x0F == 0x0F, old_cary);
+            return res;
+        } else {
+            let res = value.wrapping_sub(1);
+            self.registers.set_flags(res == 0, true, value & 0x0F == 0x00, old_cary);
+            return res;
+        }
+    }
 
-        self.math_to_reg_reshl(&register, MathNames::ADD, 1, resolve_hl);
+    /// Rotates and shifts, both the CB prefixed ones and the A only versions
+    fn shift(&mut self, op: Op, value: u8) -> u8 {
+        let carry_in = if self.registers.is_cary_flag() {1} else {0};
+
+        let (res, carry) = match op {
+            Op::Rlc => ((value << 1) | (value >> 7), value & 0x80 != 0),
+            Op::Rrc => ((value >> 1) | (value << 7), value & 0x01 != 0),
+            Op::Rl => ((value << 1) | carry_in, value & 0x80 != 0),
+            Op::Rr => ((value >> 1) | (carry_in << 7), value & 0x01 != 0),
+            Op::Sla => (value << 1, value & 0x80 != 0),
+            Op::Sra => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            Op::Swap => ((value >> 4) | (value << 4), false),
+            Op::Srl => (value >> 1, value & 0x01 != 0),
+            _ => panic!("{:?} isn't a shift", op)
+        };
 
-        self.registers.incr_pc(1);
+        self.registers.set_flags(res == 0, false, false, carry);
+        return res;
+    }
 
-        if resolve_hl {
-            // carry isn't changed
-            if self.registers.is_cary_flag() != currentCary {
-                let flags = self.registers.get_register(&RegisterNames::F);
-                self.registers.set_register(&RegisterNames::F, flags ^ 16);
-            }
-            return 12;
-        } else if register == RegisterNames::BC || register == RegisterNames::DE || register == RegisterNames::HL || register == RegisterNames::SP {
-            self.registers.set_register(&RegisterNames::F, currentFlags);
-            return 8;
-        } else {
-            // carry isn't changed
-            if self.registers.is_cary_flag() != currentCary {
-                let flags = self.registers.get_register(&RegisterNames::F);
-                self.registers.set_register(&RegisterNames::F, flags ^ 16);
-            }
+    /// Undefined opcodes hang the CPU until it's power cycled, interrupts included
+    fn lock_up(&mut self, opcode: u8) {
+        self.interrupts.state = CpuState::Locked;
+        self.events.push(EngineEvent::CpuLocked { pc: self.registers.pc, opcode: opcode });
+    }
+
+    /// ROM bank `addr` is in, None outside of ROM
+    pub fn bank_of(&self, addr: u16) -> Option<u16> {
+        return match addr {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => Some(self.memory.rom_bank()),
+            _ => None
+        };
+    }
+
+    /// Disassembly of the instruction at `addr` and its length, using the loaded symbols
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        return opcodes::disassemble_with_symbols(&*self.memory, addr, &self.symbols, self.memory.rom_bank());
+    }
+
+    /// `01:4123 (Main.loop+3)`, the bank is left off outside of ROM and the label if there is none
+    pub fn describe_addr(&self, addr: u16) -> String {
+        let location = match self.bank_of(addr) {
+            Some(bank) => format!("{:02X}:{:04X}", bank, addr),
+            None => format!("{:04X}", addr)
+        };
+        return match self.symbols.describe(addr, self.memory.rom_bank()) {
+            Some(label) => format!("{} ({})", location, label),
+            None => location
+        };
+    }
+
+    /// Starts from the 256 byte DMG boot ROM instead of the state it leaves behind. It's
+    /// mapped over 0x0000-0x00FF until the game writes to 0xFF50.
+    pub fn load_boot_rom(&mut self, boot: Vec<u8>) -> Result<(), String> {
+        if boot.len() != 0x100 {
+            return Err(format!("Boot ROM is {} bytes, a DMG one is 256", boot.len()));
+        }
+        self.boot_rom = boot;
+        self.boot_rom_mapped = true;
+        self.registers = Registers { pc: 0, sp: 0, a: 0, b: 0, c: 0, d: 0, e: 0, f: 0, h: 0, l: 0 };
+        self.memory.set(0xFF40, 0x00);
+        return Ok(());
+    }
+
+    /// Replaces the loaded cheats
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        let old_hooks = std::mem::replace(&mut self.cheats, cheats).hook_ids;
+        for id in old_hooks {
+            self.hooks.remove(id);
         }
+        self.update_cheat_hooks();
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cheats.cheats.push(cheat);
+        self.update_cheat_hooks();
+    }
 
-        return 4;
+    /// Turns cheat `index` on or off, returns whether it's now on
+    pub fn toggle_cheat(&mut self, index: usize) -> Option<bool> {
+        let cheat = self.cheats.cheats.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        let enabled = cheat.enabled;
+        self.update_cheat_hooks();
+        return Some(enabled);
     }
 
-    fn dec(&mut self, register: RegisterNames, resolve_hl: bool) -> u32 {
-        let currentFlags = self.registers.get_register(&RegisterNames::F);
+    /// Game Genie codes patch ROM reads through memory hooks, one per enabled code
+    fn update_cheat_hooks(&mut self) {
+        for id in self.cheats.hook_ids.drain(..) {
+            self.hooks.remove(id);
+        }
+        for patch in self.cheats.rom_patches() {
+            if let CheatCode::GameGenie { addr, value, compare } = patch {
+                let id = self.hooks.add(hooks::READ | hooks::EXECUTE, addr..=addr, Box::new(move |_, _, original| {
+                    return match compare {
+                        Some(compare) if compare != original => Intercept::Pass,
+                        _ => Intercept::Value(value)
+                    };
+                }));
+                self.cheats.hook_ids.push(id);
+            }
+        }
+    }
 
-        let currentCary = self.registers.is_cary_flag();
+    /// Events raised since the last call, for the frontend or a debugger to report
+    pub fn take_events(&mut self) -> Vec<EngineEvent> {
+        return std::mem::take(&mut self.events);
+    }
 
-        self.math_to_reg_reshl(&register, MathNames::SUB, 1, resolve_hl);
+    fn check_dma_transfer(&mut self) {
+        //"The DMA Transfer (40*28 bit) from internal ROM or RAM ($0000-$F19F) to the OAM (address $FE00-$FE9F)"
 
-        self.registers.incr_pc(1);
+        let start_addr = self.memory.get(0xFF46) as u16;
 
-        if resolve_hl {
-            // carry isn't changed
-            if self.registers.is_cary_flag() != currentCary {
-                let flags = self.registers.get_register(&RegisterNames::F);
-                self.registers.set_register(&RegisterNames::F, flags ^ 16);
+        if start_addr > 0 {
+            for i in 0..(40*4) {
+                self.memory.set(0xFE00 + i, self.memory.get(start_addr * 0x100 + i));
             }
-            return 12;
-        } else if register == RegisterNames::BC || register == RegisterNames::DE || register == RegisterNames::HL || register == RegisterNames::SP {
-            self.registers.set_register(&RegisterNames::F, currentFlags);
-            return 8;
-        } else {
-            // carry isn't changed
-            if self.registers.is_cary_flag() != currentCary {
-                let flags = self.registers.get_register(&RegisterNames::F);
-                self.registers.set_register(&RegisterNames::F, flags ^ 16);
+            if let Some(cdl) = self.cdl.as_mut() {
+                for i in 0..(40*4) {
+                    cdl.log(start_addr * 0x100 + i, self.memory.rom_bank(), cdl::DMA);
+                }
             }
         }
-
-        return 4;
+        self.memory.set(0xFF46, 0);
     }
 
     fn math_to_a(&mut self, math_type: MathNames, other_value: u16) {
@@ -1486,34 +1595,6 @@ impl Engine {
                                 initial_a < other_value
                 );
             }
-            MathNames::RR => {
-                result = (initial_a >> 1) + (initial_a << 7);
-                self.registers.set_flags((result & 0xFF) == 0, false, false, initial_a % 2 == 1);
-            }
-
-            MathNames::RRC => {
-                result = initial_a >> 1;
-                result += self.registers.get_register(&RegisterNames::F) / 0x10 % 2 * 0x80;
-
-                self.registers.set_flags((result & 0xFF) == 0, false, false, initial_a % 2 == 1);
-            }
-
-            MathNames::RL => {
-                result = (initial_a << 1) & 0xFF;
-
-                if initial_a >= 0x80 {
-                    result += 1;
-                }
-
-                self.registers.set_flags((result & 0xFF) == 0, false, false, initial_a >= 0x80);
-            }
-
-            MathNames::RLC => {
-                result = (initial_a << 1) & 0xFF;
-                result += if self.registers.is_cary_flag() {1} else {0};
-
-                self.registers.set_flags((result & 0xFF) == 0, false, false, initial_a >= 0x80);
-            }
         };
 
         if register == &RegisterNames::HL && resolve_hl {
@@ -1557,20 +1638,22 @@ impl Engine {
 enum MathNames {
     ADD, ADDC,
     SUB, SUBC,
-    AND, XOR, OR, CP,
-    RR, RRC, RL, RLC
+    AND, XOR, OR, CP
 }
 
-#[derive(Debug)]
+fn speed_title(speed: &Speed) -> String {
+    let description = speed.describe();
+    if description.is_empty() {
+        return "Rust Boy".to_string();
+    }
+    return format!("Rust Boy - {}", description);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum KeyNames {
     A, B, START, SELECT, LEFT, RIGHT, UP, DOWN
 }
 
-#[derive(Debug)]
-#[derive(PartialEq)]
-pub enum InterruptState {
-    Enabled, Disabled, EnabledNextOp, Halt, HaltNoInterrupt
-}
 
 #[cfg(test)]
 mod tests {
@@ -1583,8 +1666,26 @@ mod tests {
     use crate::engine::engine::Memory;
     use crate::engine::engine::MathNames;
     use crate::engine::make_engine;
-    use crate::engine::engine::InterruptState;
+    use crate::engine::interrupts;
+    use crate::engine::interrupts::Interrupts;
     use crate::engine::engine::ButtonState;
+    use crate::engine::engine::EngineEvent;
+    use crate::engine::profiler::Profiler;
+    use crate::engine::cdl;
+    use crate::engine::cdl::CodeDataLog;
+    use crate::engine::hooks;
+    use crate::engine::hooks::{MemoryHooks, Intercept};
+    use crate::engine::cheats::Cheats;
+    use crate::engine::movie::{Movie, crc32};
+    use crate::engine::input::Macro;
+    use crate::engine::rewind::Rewind;
+    use crate::engine::speed::Speed;
+    use crate::engine::config::Config;
+    use crate::engine::engine::KeyNames;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::engine::symbols::Symbols;
+    use crate::engine::trace::Tracer;
 
     #[test]
     fn test_math_sub(){
@@ -1593,10 +1694,27 @@ mod tests {
         let mut eng = Engine{
             memory: memory::make_memory(vec![0; 0xFFFF]),
             registers: reg,
-            enable_interrupt: InterruptState::Disabled,
+            interrupts: Interrupts::make_interrupts(),
             gpu: GPU::make_gpu(),
             clock: Clock::make_clock(),
-            buttons: ButtonState::create()
+            buttons: ButtonState::create(),
+            events: vec![],
+            step_cycles: 0,
+            cpu_only: false,
+            tracer: None,
+            debugger: None,
+            gdb: None,
+            profiler: None,
+            cdl: None,
+            hooks: MemoryHooks::make_memory_hooks(),
+            cheats: Cheats::make_cheats(),
+            movie: None,
+            rewind: None,
+            speed: Speed::make_speed(),
+            config: Config::make_config(),
+            symbols: Symbols::make_symbols(),
+            boot_rom: vec![],
+            boot_rom_mapped: false
         };
 
         eng.registers.set_register(&RegisterNames::A, 0);
@@ -1628,10 +1746,27 @@ mod tests {
         let mut eng = Engine{
             memory: memory::make_memory(vec![0; 0xFFFF]),
             registers: reg,
-            enable_interrupt: InterruptState::Disabled,
+            interrupts: Interrupts::make_interrupts(),
             gpu: GPU::make_gpu(),
             clock: Clock::make_clock(),
-            buttons: ButtonState::create()
+            buttons: ButtonState::create(),
+            events: vec![],
+            step_cycles: 0,
+            cpu_only: false,
+            tracer: None,
+            debugger: None,
+            gdb: None,
+            profiler: None,
+            cdl: None,
+            hooks: MemoryHooks::make_memory_hooks(),
+            cheats: Cheats::make_cheats(),
+            movie: None,
+            rewind: None,
+            speed: Speed::make_speed(),
+            config: Config::make_config(),
+            symbols: Symbols::make_symbols(),
+            boot_rom: vec![],
+            boot_rom_mapped: false
         };
 
         eng.registers.set_register(&RegisterNames::A, 0);
@@ -1772,10 +1907,27 @@ mod tests {
         let mut eng = Engine{
             memory: memory::make_memory(vec![0; 0xFFFF]),
             registers: reg,
-            enable_interrupt: InterruptState::Disabled,
+            interrupts: Interrupts::make_interrupts(),
             gpu: GPU::make_gpu(),
             clock: Clock::make_clock(),
-            buttons: ButtonState::create()
+            buttons: ButtonState::create(),
+            events: vec![],
+            step_cycles: 0,
+            cpu_only: false,
+            tracer: None,
+            debugger: None,
+            gdb: None,
+            profiler: None,
+            cdl: None,
+            hooks: MemoryHooks::make_memory_hooks(),
+            cheats: Cheats::make_cheats(),
+            movie: None,
+            rewind: None,
+            speed: Speed::make_speed(),
+            config: Config::make_config(),
+            symbols: Symbols::make_symbols(),
+            boot_rom: vec![],
+            boot_rom_mapped: false
         };
 
         eng.registers.set_register(&RegisterNames::A, 0xFF);
@@ -1807,10 +1959,27 @@ mod tests {
         let mut eng = Engine{
             memory: memory::make_memory(vec![0; 0xFFFF]),
             registers: reg,
-            enable_interrupt: InterruptState::Disabled,
+            interrupts: Interrupts::make_interrupts(),
             gpu: GPU::make_gpu(),
             clock: Clock::make_clock(),
-            buttons: ButtonState::create()
+            buttons: ButtonState::create(),
+            events: vec![],
+            step_cycles: 0,
+            cpu_only: false,
+            tracer: None,
+            debugger: None,
+            gdb: None,
+            profiler: None,
+            cdl: None,
+            hooks: MemoryHooks::make_memory_hooks(),
+            cheats: Cheats::make_cheats(),
+            movie: None,
+            rewind: None,
+            speed: Speed::make_speed(),
+            config: Config::make_config(),
+            symbols: Symbols::make_symbols(),
+            boot_rom: vec![],
+            boot_rom_mapped: false
         };
 
         eng.registers.set_register(&RegisterNames::A, 0xFF);
@@ -1863,4 +2032,500 @@ mod tests {
         assert_eq!(51, eng.registers.a);
         assert_eq!(42, eng.registers.c);
     }
+
+    #[test]
+    fn test_profiler(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0105].copy_from_slice(&[0xCD, 0x00, 0x11, 0x0E, 42]);      // call $1100, ld c, 42
+        rom[0x1100..0x110C].copy_from_slice(&[
+            0x3E, 0x01, 0x06, 0x05,                                             // ld a, 1; ld b, 5
+            0x00, 0xC6, 10, 0x05, 0xC2, 0x04, 0x11,                             // nop; add a, 10; dec b; jp nz, $1104
+            0xC8                                                                // ret z
+        ]);
+
+        let mut eng = make_engine(rom);
+        eng.profiler = Some(Profiler::make_profiler());
+        eng.run_limited(25);
+        assert_eq!(42, eng.registers.c);
+
+        let profiler = eng.profiler.as_ref().unwrap();
+        assert_eq!(25, profiler.instructions);
+        assert_eq!(224, profiler.cycles);
+        assert_eq!(5, profiler.per_pc[&(Some(0), 0x1104)].instructions);
+
+        let sub = profiler.function_counts()[&(Some(0), 0x1100)];
+        assert_eq!((1, 192, 192), (sub.calls, sub.self_cycles, sub.total_cycles));
+        assert_eq!(32, profiler.stacks[&vec![]]);
+    }
+
+    #[test]
+    fn test_code_data_log(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0109].copy_from_slice(&[
+            0xFA, 0x00, 0x02,       // ld a, [$0200]
+            0x21, 0x05, 0x40,       // ld hl, $4005
+            0x7E,                   // ld a, [hl]
+            0xCB, 0x37              // swap a
+        ]);
+
+        let mut eng = make_engine(rom);
+        eng.cdl = Some(CodeDataLog::make_code_data_log(0x8000));
+        eng.run_limited(4);
+
+        let flags = &eng.cdl.as_ref().unwrap().flags;
+        assert_eq!([cdl::OPCODE, cdl::OPERAND, cdl::OPERAND, cdl::OPCODE, cdl::OPERAND, cdl::OPERAND,
+                    cdl::OPCODE, cdl::OPCODE, cdl::OPCODE, 0], flags[0x0100..0x010A]);
+        assert_eq!(cdl::DATA, flags[0x0200]);
+        assert_eq!(cdl::DATA, flags[0x4005]);
+        assert_eq!(11, flags.iter().filter(|f| **f != 0).count());
+    }
+
+    #[test]
+    fn test_memory_hooks(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0106].copy_from_slice(&[
+            0xFA, 0x00, 0xC0,       // ld a, [$c000]
+            0xEA, 0x01, 0xC0        // ld [$c001], a
+        ]);
+
+        let mut eng = make_engine(rom);
+        let executed = Rc::new(RefCell::new(vec![]));
+        let log = executed.clone();
+        eng.hooks.add(hooks::EXECUTE, 0x0000..=0x7FFF, Box::new(move |_, addr, _| {
+            log.borrow_mut().push(addr);
+            return Intercept::Pass;
+        }));
+        eng.hooks.add(hooks::READ, 0xC000..=0xC000, Box::new(|_, _, _| Intercept::Value(0x42)));
+        eng.hooks.add(hooks::WRITE, 0xC001..=0xC001, Box::new(|_, _, _| Intercept::Drop));
+        eng.run_limited(2);
+
+        assert_eq!(0x42, eng.registers.a);
+        assert_eq!(0, eng.memory.get(0xC001));
+        assert_eq!(vec![0x100, 0x103], *executed.borrow());
+    }
+
+    #[test]
+    fn test_stub_ly_still_hooked(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0102].copy_from_slice(&[0xF0, 0x44]);     // ldh a, [$44]
+
+        let mut eng = make_engine(rom);
+        let path = std::env::temp_dir().join("rustboy_stub_ly.log");
+        eng.tracer = Some(Tracer::make_tracer(&path, true).unwrap());
+        let seen = Rc::new(RefCell::new(vec![]));
+        let log = seen.clone();
+        eng.hooks.add(hooks::READ, 0xFF44..=0xFF44, Box::new(move |_, _, val| {
+            log.borrow_mut().push(val);
+            return Intercept::Pass;
+        }));
+        eng.run_limited(1);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(0x90, eng.registers.a);
+        assert_eq!(vec![0x90], *seen.borrow());
+    }
+
+    #[test]
+    fn test_cheats(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0103].copy_from_slice(&[0x3E, 0x05, 0x18]);     // ld a, 5; jr -2
+        rom[0x0103] = 0xFE;
+
+        let mut eng = make_engine(rom);
+        // ld a, 9 if the rom has 5 there, and 99 lives in c000
+        eng.set_cheats(Cheats::parse("cheat0_code = \"091-01F-FEE\"\ncheat0_enable = true\n\
+                                      cheat1_code = \"016300C0\"\ncheat1_enable = true\n").unwrap());
+        eng.run_limited(1);
+        assert_eq!(9, eng.registers.a);
+
+        // written before the first instruction after VBlank starts
+        eng.run_frames(1);
+        eng.run_limited(1);
+        assert_eq!(0x63, eng.memory.get(0xC000));
+
+        assert_eq!(Some(false), eng.toggle_cheat(0));
+        eng.registers.pc = 0x100;
+        eng.run_limited(1);
+        assert_eq!(5, eng.registers.a);
+        assert_eq!(None, eng.toggle_cheat(2));
+    }
+
+    #[test]
+    fn test_joypad_register(){
+        let mut eng = make_engine(vec![0; 0x8000]);
+        let joypad_interrupt = |eng: &mut Engine| {
+            let flags = eng.memory.get(0xFF0F);
+            eng.memory.set(0xFF0F, 0);
+            return flags & (1 << interrupts::JOYPAD) != 0;
+        };
+        assert_eq!(0xCF, eng.memory.get(0xFF00));
+
+        // directions only
+        eng.buttons.write(0x20, &mut eng.memory);
+        eng.key_down(KeyNames::RIGHT);
+        assert_eq!(0xEE, eng.buttons.read());
+        assert!(joypad_interrupt(&mut eng));
+        eng.key_down(KeyNames::A);
+        assert!(!joypad_interrupt(&mut eng));
+        eng.key_up(KeyNames::RIGHT);
+        assert_eq!(0xEF, eng.buttons.read());
+        assert!(!joypad_interrupt(&mut eng));
+
+        // selecting the row with A held pulls a line low
+        eng.buttons.write(0x10, &mut eng.memory);
+        assert_eq!(0xDE, eng.buttons.read());
+        assert!(joypad_interrupt(&mut eng));
+
+        // both rows ANDed together
+        eng.buttons.write(0x00, &mut eng.memory);
+        eng.key_down(KeyNames::LEFT);
+        assert_eq!(0xCC, eng.buttons.read());
+        assert_eq!(0xCC, eng.memory.get(0xFF00));
+
+        eng.key_up(KeyNames::A);
+        eng.key_down(KeyNames::RIGHT);
+        assert_eq!(0xCC, eng.buttons.read());
+        eng.buttons.block_opposite = true;
+        eng.key_down(KeyNames::UP);
+        assert_eq!(0xCB, eng.buttons.read());
+    }
+
+    #[test]
+    fn test_autofire_and_macros(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);     // jr -2
+
+        // a recording movie keeps what autofire pressed
+        let mut eng = make_engine(rom.clone());
+        let save_ram = eng.memory.save();
+        eng.start_movie(Movie::make_recording(crc32(&rom), save_ram)).unwrap();
+        eng.buttons.layer.autofire_period = [2, 2];
+        eng.buttons.layer.set_autofire(KeyNames::A, true);
+        eng.key_down(KeyNames::START);
+        eng.run_frames(4);
+        let movie = eng.movie.take().unwrap();
+        assert_eq!(vec![0x90, 0x80, 0x90, 0x80], movie.frames);
+
+        let mut eng = make_engine(rom);
+        eng.buttons.layer.macros.push(Macro { name: "hold".to_string(), frames: vec![0x20, 0x20] });
+        assert!(eng.buttons.layer.play(0));
+        eng.key_down(KeyNames::UP);
+        eng.run_limited(1);
+        assert_eq!(0x24, eng.buttons.pressed());
+        // released at the start of the frame after it, keys that are held stay down
+        eng.run_frames(2);
+        eng.run_limited(1);
+        assert_eq!(0x04, eng.buttons.pressed());
+        assert_eq!(0x04, eng.buttons.keys());
+    }
+
+    #[test]
+    fn test_boot_rom(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0x3C;     // inc a, hidden by the boot ROM
+        let mut boot = vec![0; 0x100];
+        boot[0x00..0x04].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);     // ld a,1; ldh (50),a
+        boot[0xFE..0x100].copy_from_slice(&[0x18, 0xFE]);
+
+        let mut eng = make_engine(rom.clone());
+        assert!(eng.load_boot_rom(vec![0; 10]).is_err());
+        eng.load_boot_rom(boot).unwrap();
+        assert_eq!(0, eng.registers.pc);
+        let state = eng.save_state();
+        eng.run_limited(2);
+        assert_eq!(1, eng.registers.a);
+        // unmapped, the cartridge shows through
+        eng.registers.pc = 0;
+        eng.run_limited(1);
+        assert_eq!(2, eng.registers.a);
+
+        // a snapshot from before the write to 0xFF50 maps it back
+        eng.load_state(&state).unwrap();
+        assert!(eng.boot_rom_mapped);
+        eng.run_limited(1);
+        assert_eq!(1, eng.registers.a);
+        assert!(make_engine(rom).load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_movie_playback(){
+        let mut rom = vec![0; 0x8000];
+        // select the buttons, then add every joypad read to b
+        rom[0x0100..0x010A].copy_from_slice(&[0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0x80, 0x47, 0x18, 0xFA]);
+
+        let mut eng = make_engine(rom.clone());
+        let save_ram = eng.memory.save();
+        eng.start_movie(Movie::make_recording(crc32(&rom), save_ram)).unwrap();
+        eng.run_limited(1000);
+        eng.key_down(KeyNames::A);
+        // held back until the next frame
+        assert_eq!(0, eng.buttons.pressed());
+        eng.run_frames(2);
+        eng.run_limited(3000);
+        eng.key_up(KeyNames::A);
+        eng.run_frames(2);
+        let movie = eng.movie.take().unwrap();
+        assert_eq!(vec![0, 0x10, 0x10, 0], movie.frames);
+
+        let mut replay = make_engine(rom.clone());
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        movie.check_rom(&rom).unwrap();
+        replay.start_movie(movie).unwrap();
+        replay.run_frames(eng.gpu.frames);
+        assert_eq!(eng.registers.b, replay.registers.b);
+        assert_eq!(eng.registers.pc, replay.registers.pc);
+        assert!(replay.take_events().is_empty());
+
+        replay.run_frames(1);
+        replay.run_limited(1);
+        assert_eq!(vec![EngineEvent::MovieFinished { frames: 4 }], replay.take_events());
+    }
+
+    #[test]
+    fn test_movie_from_state(){
+        let mut rom = vec![0; 0x8000];
+        // select the buttons, then add every joypad read to b
+        rom[0x0100..0x010A].copy_from_slice(&[0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0x80, 0x47, 0x18, 0xFA]);
+
+        let mut eng = make_engine(rom.clone());
+        eng.key_down(KeyNames::A);
+        eng.run_frames(3);
+        eng.key_up(KeyNames::A);
+        eng.run_limited(500);
+        eng.start_movie(Movie::make_state_recording(crc32(&rom), eng.save_state())).unwrap();
+        eng.run_frames(1);
+        eng.key_down(KeyNames::A);
+        eng.run_frames(2);
+        let movie = eng.movie.take().unwrap();
+        assert_eq!(vec![0, 0x10, 0x10], movie.frames);
+
+        // a fresh engine picks up from the snapshot, not from power on
+        let mut replay = make_engine(rom.clone());
+        replay.start_movie(Movie::from_bytes(&movie.to_bytes()).unwrap()).unwrap();
+        replay.run_frames(eng.gpu.frames - replay.gpu.frames);
+        assert_eq!(eng.gpu.frames, replay.gpu.frames);
+        assert_eq!(eng.registers.b, replay.registers.b);
+        assert_eq!(eng.registers.pc, replay.registers.pc);
+    }
+
+    #[test]
+    fn test_save_state_and_rewind(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x01;     // MBC1
+        // count in c000 and switch ROM banks with it
+        rom[0x0100..0x010A].copy_from_slice(&[0x34, 0x7E, 0xEA, 0x00, 0x20, 0x18, 0xF9, 0x00, 0x00, 0x00]);
+
+        let mut eng = make_engine(rom.clone());
+        eng.registers.h = 0xC0;
+        eng.registers.l = 0x00;
+        eng.rewind = Some(Rewind::make_rewind(1, 10));
+        eng.run_frames(3);
+        eng.run_limited(100);
+        eng.key_down(KeyNames::A);
+        let state = eng.save_state();
+        let (count, bank) = (eng.memory.get(0xC000), eng.memory.rom_bank());
+
+        eng.key_up(KeyNames::A);
+        eng.run_frames(2);
+        let after = (eng.registers.pc, eng.memory.get(0xC000), eng.gpu.frames, eng.clock.div_clock);
+        eng.load_state(&state).unwrap();
+        assert_eq!((count, bank), (eng.memory.get(0xC000), eng.memory.rom_bank()));
+        // A was let go of since the snapshot
+        assert_eq!(0, eng.buttons.pressed());
+        eng.run_frames(2);
+        assert_eq!(after, (eng.registers.pc, eng.memory.get(0xC000), eng.gpu.frames, eng.clock.div_clock));
+
+        // a snapshot at the start of frames 0 to 4, the one for 5 is due at the next instruction
+        assert_eq!(5, eng.rewind.as_ref().unwrap().len());
+        assert!(eng.rewind_step());
+        assert_eq!(4, eng.gpu.frames);
+        assert!(eng.rewind_step());
+        assert_eq!(3, eng.gpu.frames);
+
+        let mut other = make_engine(vec![0; 0x8000]);
+        assert!(other.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_interrupt_priority_and_timing(){
+        let rom = vec![0; 0xFFFF];
+
+        let mut eng = make_engine(rom);
+        eng.interrupts.ime = true;
+        eng.memory.set(0xFFFF, 0x1F);
+        eng.memory.set(0xFF0F, 0x0C); // timer and serial
+
+        let steps = eng.run_limited(1);
+
+        assert_eq!(20, steps);
+        assert_eq!(0x0050, eng.registers.pc);
+        assert_eq!(0x08, eng.memory.get(0xFF0F));
+        assert_eq!(0xFFFC, eng.registers.sp);
+        assert_eq!(0x0100, eng.memory.pop_stack(&mut eng.registers));
+        assert!(!eng.interrupts.ime);
+    }
+
+    #[test]
+    fn test_ei_delay(){
+        let mut rom = vec![0; 0xFFFF];
+
+        rom[0x0100] = 0xFB; // ei
+        rom[0x0101] = 0x00;
+        rom[0x0102] = 0x00;
+
+        let mut eng = make_engine(rom);
+        eng.memory.set(0xFFFF, 0x01);
+        eng.memory.set(0xFF0F, 0x01);
+
+        eng.run_limited(2);
+        assert_eq!(0x0102, eng.registers.pc);
+
+        eng.run_limited(1);
+        assert_eq!(0x0040, eng.registers.pc);
+        assert_eq!(0x0102, eng.memory.pop_stack(&mut eng.registers));
+    }
+
+    #[test]
+    fn test_halt_bug(){
+        let mut rom = vec![0; 0xFFFF];
+
+        rom[0x0100] = 0x76; // halt
+        rom[0x0101] = 0x3C; // inc a
+        rom[0x0102] = 0x00;
+
+        let mut eng = make_engine(rom);
+        eng.registers.set_register(&RegisterNames::A, 0);
+        eng.memory.set(0xFFFF, 0x01);
+        eng.memory.set(0xFF0F, 0x01);
+
+        eng.run_limited(3);
+
+        // inc a runs twice since PC wasn't incremented after the halt
+        assert_eq!(2, eng.registers.a);
+        assert_eq!(0x0102, eng.registers.pc);
+    }
+
+    #[test]
+    fn test_halt_without_ime_resumes(){
+        let mut rom = vec![0; 0xFFFF];
+
+        rom[0x0100] = 0x76; // halt
+        rom[0x0101] = 0x3C; // inc a
+
+        let mut eng = make_engine(rom);
+        eng.registers.set_register(&RegisterNames::A, 0);
+        eng.memory.set(0xFFFF, 0x04);
+
+        eng.run_limited(10);
+        assert_eq!(0x0101, eng.registers.pc);
+        assert_eq!(0, eng.registers.a);
+
+        eng.memory.set(0xFF0F, 0x04);
+        eng.run_limited(1);
+
+        // woken up but not serviced
+        assert_eq!(0x0102, eng.registers.pc);
+        assert_eq!(1, eng.registers.a);
+        assert_eq!(0x04, eng.memory.get(0xFF0F));
+    }
+
+    #[test]
+    fn test_ie_push_cancels_interrupt(){
+        let rom = vec![0; 0xFFFF];
+
+        let mut eng = make_engine(rom);
+        eng.interrupts.ime = true;
+        eng.registers.sp = 0x0000;
+        eng.memory.set(0xFFFF, 0x04);
+        eng.memory.set(0xFF0F, 0x04);
+
+        eng.run_limited(1);
+
+        // pushing PC's high byte (0x01) into IE disabled the timer interrupt
+        assert_eq!(0x0000, eng.registers.pc);
+        assert_eq!(0x01, eng.memory.get(0xFFFF));
+        assert_eq!(0x04, eng.memory.get(0xFF0F));
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_cpu(){
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD].iter() {
+            let mut rom = vec![0; 0xFFFF];
+
+            rom[0x0100] = *opcode;
+
+            let mut eng = make_engine(rom);
+            eng.interrupts.ime = true;
+            eng.memory.set(0xFFFF, 0x1F);
+
+            eng.run_limited(1);
+            eng.memory.set(0xFF0F, 0x01);
+            eng.run_limited(10);
+
+            assert_eq!(0x0100, eng.registers.pc);
+            assert_eq!(vec![EngineEvent::CpuLocked { pc: 0x0100, opcode: *opcode }], eng.take_events());
+        }
+    }
+
+    #[test]
+    fn test_cycles_and_length_from_table(){
+        use crate::engine::opcodes::{OPCODES, CB_OPCODES, Op};
+
+        // everything that doesn't jump around or change the CPU state
+        for prefixed in [false, true].iter() {
+            for opcode in 0..=255 {
+                let instr = if *prefixed {&CB_OPCODES[opcode]} else {&OPCODES[opcode]};
+                match instr.op {
+                    Op::Jr | Op::Jp | Op::Call | Op::Ret | Op::Reti | Op::Rst
+                        | Op::Halt | Op::Stop | Op::Illegal | Op::Prefix | Op::Ei => continue,
+                    _ => {}
+                }
+
+                let mut rom = vec![0; 0xFFFF];
+                if *prefixed {
+                    rom[0x0100] = 0xCB;
+                    rom[0x0101] = opcode as u8;
+                } else {
+                    rom[0x0100] = opcode as u8;
+                }
+
+                let mut eng = make_engine(rom);
+                eng.registers.set_register(&RegisterNames::HL, 0xC000);
+
+                let steps = eng.run_limited(1);
+
+                assert_eq!(instr.cycles as u64, steps, "cycles of {:02x}", opcode);
+                if instr.op != Op::Pop && instr.op != Op::Push {
+                    assert_eq!(0x0100 + instr.length as u16, eng.registers.pc, "length of {:02x}", opcode);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_branch_cycles_from_table(){
+        use crate::engine::opcodes::{OPCODES, Op};
+
+        for opcode in 0..=255 {
+            let instr = &OPCODES[opcode];
+            match instr.op {
+                Op::Jr | Op::Jp | Op::Call | Op::Ret | Op::Reti | Op::Rst => {},
+                _ => continue
+            }
+
+            for flags in [0x00, 0xF0].iter() {
+                let mut rom = vec![0; 0xFFFF];
+                rom[0x0100] = opcode as u8;
+
+                let mut eng = make_engine(rom);
+                eng.registers.set_register(&RegisterNames::F, *flags);
+                let taken = eng.condition(instr.dst);
+
+                let steps = eng.run_limited(1);
+
+                let expected = if taken {instr.branch_cycles} else {instr.cycles};
+                assert_eq!(expected as u64, steps, "cycles of {:02x} with flags {:02x}", opcode, flags);
+            }
+        }
+    }
 }
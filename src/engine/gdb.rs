@@ -0,0 +1,423 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::engine::debugger::{WatchKind, Watchpoint};
+use crate::engine::engine::{Engine, EngineEvent};
+
+/// How often (in instructions) a running game checks for gdb's interrupt (Ctrl-C) byte
+const INTERRUPT_POLL: u64 = 4096;
+
+/// gdb's z80 register layout: af bc de hl sp pc ix iy af' bc' de' hl' ir, 16 bits each.
+/// The SM83 only has the first six, the rest read as zero.
+const REGISTER_COUNT: usize = 13;
+
+/// Remote serial protocol server so gdb (`set architecture z80`, `target remote :port`)
+/// can debug the running game. Lives in `Engine::gdb` and is checked before every instruction.
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<Watchpoint>,
+    /// stop again before the next instruction
+    stepping: bool,
+    /// gdb connected and will ask why we're stopped, so don't send a stop reply yet
+    first_stop: bool,
+    watch_hit: Option<(WatchKind, u16)>,
+    instructions: u64,
+    /// gdb went away or detached, the engine drops us
+    pub detached: bool
+}
+
+impl GdbStub {
+    /// Waits for gdb to connect on localhost
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        return Ok(GdbStub::from_stream(stream));
+    }
+
+    pub fn from_stream(stream: TcpStream) -> GdbStub {
+        stream.set_nodelay(true).ok();
+        return GdbStub {
+            stream: stream,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            stepping: false,
+            first_stop: true,
+            watch_hit: None,
+            instructions: 0,
+            detached: false
+        };
+    }
+
+    /// Called for every CPU read and write
+    pub fn check_access(&mut self, addr: u16, write: bool) {
+        for watch in self.watchpoints.iter() {
+            let kind_matches = match watch.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true
+            };
+            if kind_matches && addr >= watch.start && addr <= watch.end {
+                self.watch_hit = Some((watch.kind, addr));
+            }
+        }
+    }
+
+    /// Stop reply if we should stop before the instruction at PC
+    fn stop_reason(&mut self, eng: &Engine) -> Option<String> {
+        if self.first_stop {
+            self.first_stop = false;
+            return Some(String::new());
+        }
+        if let Some((kind, addr)) = self.watch_hit.take() {
+            let name = match kind {
+                WatchKind::Read => "rwatch",
+                WatchKind::Write => "watch",
+                WatchKind::Access => "awatch"
+            };
+            return Some(format!("T05{}:{:04x};", name, addr));
+        }
+        if self.stepping || self.breakpoints.contains(&eng.registers.pc) {
+            self.stepping = false;
+            return Some(String::from("S05"));
+        }
+
+        self.instructions += 1;
+        if self.instructions % INTERRUPT_POLL == 0 && self.interrupt_requested() {
+            return Some(String::from("S02"));
+        }
+        return None;
+    }
+
+    /// gdb sends a bare 0x03 when the user hits Ctrl-C
+    fn interrupt_requested(&mut self) -> bool {
+        let mut buf = [0; 1];
+        self.stream.set_nonblocking(true).ok();
+        let res = matches!(self.stream.read(&mut buf), Ok(1) if buf[0] == 0x03);
+        self.stream.set_nonblocking(false).ok();
+        return res;
+    }
+
+    pub fn before_instruction(&mut self, eng: &mut Engine) {
+        if let Some(reply) = self.stop_reason(eng) {
+            if !reply.is_empty() {
+                self.send(&reply);
+            }
+            self.serve(eng);
+        }
+    }
+
+    /// Handles packets until gdb resumes the game
+    fn serve(&mut self, eng: &mut Engine) {
+        while !self.detached {
+            let packet = match self.read_packet() {
+                Some(packet) => packet,
+                None => {
+                    self.detached = true;
+                    return;
+                }
+            };
+
+            match packet.chars().next() {
+                Some('c') => return,
+                Some('s') => {
+                    self.stepping = true;
+                    return;
+                },
+                Some('k') => {
+                    eng.events.push(EngineEvent::Quit);
+                    self.detached = true;
+                    return;
+                },
+                Some('D') => {
+                    self.send("OK");
+                    self.detached = true;
+                    return;
+                },
+                _ => {
+                    let reply = self.handle(eng, &packet);
+                    self.send(&reply);
+                }
+            }
+        }
+    }
+
+    /// Reply to every packet that doesn't resume the game, empty for unsupported ones
+    fn handle(&mut self, eng: &mut Engine, packet: &str) -> String {
+        if packet.is_empty() {
+            return String::new();
+        }
+        let (command, args) = packet.split_at(1);
+        return match command {
+            "?" => String::from("S05"),
+            "g" => {
+                let regs = registers(eng);
+                regs.iter().map(|r| format!("{:02x}{:02x}", r & 0xFF, r >> 8)).collect()
+            },
+            "G" => {
+                let bytes = decode_hex(args);
+                for i in 0..6 {
+                    if bytes.len() >= i * 2 + 2 {
+                        set_register(eng, i, bytes[i * 2] as u16 + ((bytes[i * 2 + 1] as u16) << 8));
+                    }
+                }
+                String::from("OK")
+            },
+            "p" => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(n) if n < REGISTER_COUNT => {
+                        let r = registers(eng)[n];
+                        format!("{:02x}{:02x}", r & 0xFF, r >> 8)
+                    },
+                    _ => String::from("E01")
+                }
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = usize::from_str_radix(parts.next().unwrap_or(""), 16).unwrap_or(usize::MAX);
+                let bytes = decode_hex(parts.next().unwrap_or(""));
+                if n >= REGISTER_COUNT || bytes.len() < 2 {
+                    return String::from("E01");
+                }
+                set_register(eng, n, bytes[0] as u16 + ((bytes[1] as u16) << 8));
+                String::from("OK")
+            },
+            "m" => {
+                match parse_addr_len(args) {
+                    Some((addr, len)) => (0..len).map(|i| format!("{:02x}", eng.memory.get(addr.wrapping_add(i)))).collect(),
+                    None => String::from("E01")
+                }
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                match parse_addr_len(parts.next().unwrap_or("")) {
+                    Some((addr, _)) => {
+                        for (i, byte) in decode_hex(parts.next().unwrap_or("")).iter().enumerate() {
+                            eng.memory.set(addr.wrapping_add(i as u16), *byte);
+                        }
+                        String::from("OK")
+                    },
+                    None => String::from("E01")
+                }
+            },
+            "Z" | "z" => self.update_breakpoint(command == "Z", args),
+            "q" if args.starts_with("Supported") => String::from("PacketSize=1000"),
+            "q" if args == "Attached" => String::from("1"),
+            "q" if args == "C" => String::from("QC1"),
+            "q" if args.starts_with("fThreadInfo") => String::from("m1"),
+            "q" if args.starts_with("sThreadInfo") => String::from("l"),
+            "H" => String::from("OK"),
+            _ => String::new()
+        };
+    }
+
+    /// `Z<type>,<addr>,<kind>` adds and `z...` removes a breakpoint or watchpoint
+    fn update_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let parts: Vec<&str> = args.split(',').collect();
+        if parts.len() < 3 {
+            return String::from("E01");
+        }
+        let addr = match u16::from_str_radix(parts[1], 16) {
+            Ok(addr) => addr,
+            Err(_) => return String::from("E01")
+        };
+        let len = u16::from_str_radix(parts[2], 16).unwrap_or(1).max(1);
+
+        let kind = match parts[0] {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.push(addr);
+                } else {
+                    self.breakpoints.retain(|bp| *bp != addr);
+                }
+                return String::from("OK");
+            },
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new()
+        };
+        let watch = Watchpoint { start: addr, end: addr.wrapping_add(len - 1), kind: kind };
+        if insert {
+            self.watchpoints.push(watch);
+        } else {
+            self.watchpoints.retain(|w| *w != watch);
+        }
+        return String::from("OK");
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buf = [0; 1];
+        return match self.stream.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None
+        };
+    }
+
+    /// Next `$data#checksum` packet, acknowledged. None once gdb disconnects.
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            // skip acks and stray interrupt bytes until a packet starts
+            while self.read_byte()? != b'$' {}
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b'}' => data.push(self.read_byte()? ^ 0x20),
+                    byte => data.push(byte)
+                }
+            }
+            let checksum_text = [self.read_byte()?, self.read_byte()?];
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum_text).unwrap_or(""), 16).ok();
+
+            if expected == Some(checksum(&data)) {
+                self.stream.write_all(b"+").ok()?;
+                return Some(String::from_utf8_lossy(&data).to_string());
+            }
+            self.stream.write_all(b"-").ok()?;
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        if self.stream.write_all(packet.as_bytes()).is_err() {
+            self.detached = true;
+            return;
+        }
+        // wait for the ack, resending on '-'
+        loop {
+            match self.read_byte() {
+                Some(b'+') => return,
+                Some(b'-') => {
+                    if self.stream.write_all(packet.as_bytes()).is_err() {
+                        self.detached = true;
+                        return;
+                    }
+                },
+                Some(_) => {},
+                None => {
+                    self.detached = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    return data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+}
+
+fn decode_hex(text: &str) -> Vec<u8> {
+    return (0..text.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok())
+        .collect();
+}
+
+/// `addr,len`
+fn parse_addr_len(text: &str) -> Option<(u16, u16)> {
+    let mut parts = text.split(',');
+    let addr = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let len = u16::from_str_radix(parts.next()?, 16).ok()?;
+    return Some((addr, len));
+}
+
+fn registers(eng: &Engine) -> [u16; REGISTER_COUNT] {
+    let r = &eng.registers;
+    let mut res = [0; REGISTER_COUNT];
+    res[0] = ((r.a as u16) << 8) + r.f as u16;
+    res[1] = ((r.b as u16) << 8) + r.c as u16;
+    res[2] = ((r.d as u16) << 8) + r.e as u16;
+    res[3] = ((r.h as u16) << 8) + r.l as u16;
+    res[4] = r.sp;
+    res[5] = r.pc;
+    return res;
+}
+
+fn set_register(eng: &mut Engine, n: usize, value: u16) {
+    let r = &mut eng.registers;
+    let (high, low) = ((value >> 8) as u8, value as u8);
+    match n {
+        0 => { r.a = high; r.f = low & 0xF0; },
+        1 => { r.b = high; r.c = low; },
+        2 => { r.d = high; r.e = low; },
+        3 => { r.h = high; r.l = low; },
+        4 => r.sp = value,
+        5 => r.pc = value,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use crate::engine::make_engine;
+    use crate::engine::gdb::{GdbStub, checksum};
+
+    /// Sends a packet and returns the reply, the way gdb would
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        stream.write_all(format!("${}#{:02x}", data, checksum(data.as_bytes())).as_bytes()).unwrap();
+        return reply(stream);
+    }
+
+    fn reply(stream: &mut TcpStream) -> String {
+        let mut res = vec![];
+        let mut buf = [0; 1];
+        loop {
+            stream.read_exact(&mut buf).unwrap();
+            match buf[0] {
+                b'+' if res.is_empty() => continue,
+                b'$' => res.clear(),
+                b'#' => break,
+                byte => res.push(byte)
+            }
+        }
+        stream.read_exact(&mut [0; 2]).unwrap();
+        stream.write_all(b"+").unwrap();
+        return String::from_utf8(res).unwrap();
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = thread::spawn(move || {
+            let mut gdb = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let mut replies = vec![];
+            replies.push(request(&mut gdb, "?"));
+            replies.push(request(&mut gdb, "g"));
+            replies.push(request(&mut gdb, "m100,3"));
+            replies.push(request(&mut gdb, "Mc000,2:abcd"));
+            replies.push(request(&mut gdb, "Z0,105,1"));
+            gdb.write_all(b"$c#63").unwrap();
+            replies.push(reply(&mut gdb));
+            replies.push(request(&mut gdb, "p0"));
+            gdb.write_all(b"$k#6b").unwrap();
+            return replies;
+        });
+
+        let mut rom = vec![0; 0x8000];
+        // ld a, $12; ld b, $34; nop; call $0200
+        rom[0x100..0x108].copy_from_slice(&[0x3E, 0x12, 0x06, 0x34, 0x00, 0xCD, 0x00, 0x02]);
+        let mut eng = make_engine(rom);
+        eng.gdb = Some(GdbStub::from_stream(listener.accept().unwrap().0));
+        eng.run_limited(100);
+
+        let replies = client.join().unwrap();
+        assert_eq!("S05", replies[0]);
+        assert_eq!("b0011300d8004d01feff0001", &replies[1][0..24]);
+        assert_eq!("3e1206", replies[2]);
+        assert_eq!("OK", replies[3]);
+        assert_eq!("OK", replies[4]);
+        assert_eq!("S05", replies[5]);
+        assert_eq!("b012", replies[6]);
+        // killed at the breakpoint, the call never ran
+        assert_eq!(0x105, eng.registers.pc);
+        assert_eq!(0xCD, eng.memory.get(0xC001));
+        assert!(eng.gdb.is_none());
+    }
+}
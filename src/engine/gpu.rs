@@ -2,6 +2,7 @@ use std::fmt;
 use std::cmp;
 
 use crate::engine::memory::Memory;
+use crate::engine::state::{StateWriter, StateReader};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 
@@ -11,7 +12,40 @@ pub struct GPU {
     pub line: u8,
     pub mode: GpuState,
     pub lcd: Vec<Vec<u8>>,
-    pub time_to_draw: bool
+    pub time_to_draw: bool,
+    /// frames finished since power on, counted at the start of VBlank
+    pub frames: u64,
+    /// what the four shades in `lcd` are drawn as, lightest first
+    pub palette: Palette
+}
+
+pub type Palette = [Color; 4];
+
+const fn rgb(value: u32) -> Color {
+    return Color { r: (value >> 16) as u8, g: (value >> 8) as u8, b: value as u8, a: 0xFF };
+}
+
+/// Palettes `parse_palette` knows by name
+pub const PALETTES: [(&str, Palette); 3] = [
+    ("gray", [rgb(0xFFFFFF), rgb(0xADADAD), rgb(0x525252), rgb(0x000000)]),
+    // the original DMG's green screen
+    ("green", [rgb(0x9BBC0F), rgb(0x8BAC0F), rgb(0x306230), rgb(0x0F380F)]),
+    ("pocket", [rgb(0xC4CFA1), rgb(0x8B956D), rgb(0x4D533C), rgb(0x1F1F1F)])
+];
+
+/// A palette from `PALETTES` by name, or four hex colours lightest first like
+/// `e0f8d0,88c070,346856,081820`
+pub fn parse_palette(text: &str) -> Result<Palette, String> {
+    if let Some((_, palette)) = PALETTES.iter().find(|(name, _)| name.eq_ignore_ascii_case(text.trim())) {
+        return Ok(*palette);
+    }
+    let colors = text.split(',')
+        .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).ok().filter(|c| *c <= 0xFFFFFF).map(rgb))
+        .collect::<Option<Vec<Color>>>()
+        .filter(|colors| colors.len() == 4)
+        .ok_or(format!("{} isn't one of {} or four hex colours", text,
+                       PALETTES.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ")))?;
+    return Ok([colors[0], colors[1], colors[2], colors[3]]);
 }
 
 impl GPU {
@@ -21,10 +55,46 @@ impl GPU {
             line: 0,
             mode: GpuState::HBlank,
             lcd: vec![vec![0; 160]; 144],
-            time_to_draw: true
+            time_to_draw: true,
+            frames: 0,
+            palette: PALETTES[0].1
         };
     }
 
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.f32(self.time);
+        out.u8(self.line);
+        out.u8(match self.mode {
+            GpuState::ScanOAM => 0,
+            GpuState::ScanVRAM => 1,
+            GpuState::HBlank => 2,
+            GpuState::VBlank => 3
+        });
+        for row in self.lcd.iter() {
+            out.bytes(row);
+        }
+        out.u64(self.frames);
+    }
+
+    /// Also asks for the restored screen to be drawn
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.time = input.f32()?;
+        self.line = input.u8()?;
+        self.mode = match input.u8()? {
+            0 => GpuState::ScanOAM,
+            1 => GpuState::ScanVRAM,
+            2 => GpuState::HBlank,
+            3 => GpuState::VBlank,
+            other => return Err(format!("Bad GPU mode {} in snapshot", other))
+        };
+        for row in self.lcd.iter_mut() {
+            input.bytes_into(row)?;
+        }
+        self.frames = input.u64()?;
+        self.time_to_draw = true;
+        return Ok(());
+    }
+
     pub fn draw<C: sdl2::render::RenderTarget>(&mut self,
         canvas : &mut sdl2::render::Canvas<C>,
         width: u32,
@@ -49,9 +119,15 @@ impl GPU {
 
         for y in 0..144 {
             for x in 0..160 {
-                let mut col = self.lcd[y][x];
-
-                canvas.set_draw_color(Color::RGB(col, col, col));
+                let col = self.lcd[y][x];
+
+                canvas.set_draw_color(match col {
+                    255 => self.palette[0],
+                    173 => self.palette[1],
+                    82 => self.palette[2],
+                    0 => self.palette[3],
+                    _ => Color::RGB(col, col, col)
+                });
                 canvas.fill_rect(Rect::new(
                     (x as f64 * scale).round() as i32,
                     (y as f64 * scale).round() as i32,
@@ -79,6 +155,7 @@ impl GPU {
                         self.mode = GpuState::VBlank;
                         self.line = 0;
                         self.time_to_draw = true;
+                        self.frames += 1;
                     } else {
                         self.mode = GpuState::ScanOAM;
                         self.draw_line(memory, self.line);
@@ -372,3 +449,19 @@ pub enum GpuState {
    HBlank,
    VBlank
 }
+
+#[cfg(test)]
+mod tests {
+    use sdl2::pixels::Color;
+    use crate::engine::gpu::{parse_palette, PALETTES};
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!(Ok(PALETTES[1].1), parse_palette("Green"));
+        let palette = parse_palette("e0f8d0, 88c070,#346856,081820").unwrap();
+        assert_eq!(Color::RGB(0xE0, 0xF8, 0xD0), palette[0]);
+        assert_eq!(Color::RGB(0x08, 0x18, 0x20), palette[3]);
+        assert!(parse_palette("e0f8d0,88c070,346856").is_err());
+        assert!(parse_palette("purple").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::engine::disasm;
+
+/// Cartridge types `memory::make_memory` can run
+const SUPPORTED: [u8; 6] = [0x00, 0x01, 0x02, 0x03, 0x13, 0x1B];
+
+/// The cartridge header at 0x100-0x14F
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHeader {
+    pub title: String,
+    /// 0x80 works on both, 0xC0 is Game Boy Color only
+    pub cgb_flag: u8,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    /// 0x148, 32KiB << n
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    /// old licensee code, 0x33 means the two ASCII characters at 0x144 are used instead
+    pub licensee: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    /// what the checksums should be for this file
+    pub computed_header_checksum: u8,
+    pub computed_global_checksum: u16
+}
+
+impl RomHeader {
+    pub fn parse(rom: &[u8]) -> Result<RomHeader, String> {
+        if rom.len() < 0x150 {
+            return Err(format!("{} bytes is too short for a Game Boy ROM", rom.len()));
+        }
+        let licensee = match rom[0x14B] {
+            0x33 => rom[0x144..0x146].iter().map(|b| *b as char).collect(),
+            old => format!("{:02X}", old)
+        };
+        let computed_header_checksum = rom[0x134..0x14D].iter().fold(0u8, |sum, b| sum.wrapping_sub(*b).wrapping_sub(1));
+        let computed_global_checksum = rom.iter().enumerate()
+            .filter(|(i, _)| *i != 0x14E && *i != 0x14F)
+            .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16));
+        return Ok(RomHeader {
+            title: disasm::title(rom),
+            cgb_flag: rom[0x143],
+            sgb: rom[0x146] == 0x03,
+            cartridge_type: rom[0x147],
+            rom_size_code: rom[0x148],
+            ram_size_code: rom[0x149],
+            licensee: licensee,
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: ((rom[0x14E] as u16) << 8) | rom[0x14F] as u16,
+            computed_header_checksum: computed_header_checksum,
+            computed_global_checksum: computed_global_checksum
+        });
+    }
+
+    /// Whether the emulator has a memory controller for the cartridge type
+    pub fn is_supported(&self) -> bool {
+        return SUPPORTED.contains(&self.cartridge_type);
+    }
+
+    pub fn cartridge_name(&self) -> &'static str {
+        return match self.cartridge_type {
+            0x00 => "ROM only",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+battery",
+            0x05 => "MBC2",
+            0x06 => "MBC2+battery",
+            0x0F => "MBC3+timer+battery",
+            0x10 => "MBC3+timer+RAM+battery",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+battery",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+battery",
+            0x1C => "MBC5+rumble",
+            0x1D => "MBC5+rumble+RAM",
+            0x1E => "MBC5+rumble+RAM+battery",
+            0xFC => "Pocket Camera",
+            0xFF => "HuC1+RAM+battery",
+            _ => "unknown"
+        };
+    }
+
+    /// ROM size in bytes the header claims, None for a code it doesn't define
+    pub fn rom_size(&self) -> Option<usize> {
+        return if self.rom_size_code <= 8 { Some(0x8000 << self.rom_size_code) } else { None };
+    }
+
+    /// Cartridge RAM in bytes
+    pub fn ram_size(&self) -> Option<usize> {
+        return match self.ram_size_code {
+            0x00 => Some(0),
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::header::RomHeader;
+
+    #[test]
+    fn test_parse_header() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x139].copy_from_slice(b"HELLO");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        rom[0x14B] = 0x33;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        let header = RomHeader::parse(&rom).unwrap();
+        assert_eq!("HELLO", header.title);
+        assert_eq!("MBC1+RAM+battery", header.cartridge_name());
+        assert_eq!("01", header.licensee);
+        assert_eq!(Some(0x8000), header.rom_size());
+        assert_eq!(Some(0x2000), header.ram_size());
+        assert!(header.is_supported());
+        assert_ne!(header.header_checksum, header.computed_header_checksum);
+
+        rom[0x14D] = header.computed_header_checksum;
+        let header = RomHeader::parse(&rom).unwrap();
+        assert_eq!(header.header_checksum, header.computed_header_checksum);
+        assert!(RomHeader::parse(&rom[..0x100]).is_err());
+    }
+}
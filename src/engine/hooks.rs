@@ -0,0 +1,130 @@
+use std::ops::RangeInclusive;
+
+/// Kinds of access a hook can watch, or together to watch several
+pub const READ: u8 = 0x01;
+pub const WRITE: u8 = 0x02;
+/// the CPU fetching an opcode to run, this isn't also reported as a `READ`
+pub const EXECUTE: u8 = 0x04;
+
+/// What a hook wants done with the access it saw
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Intercept {
+    /// carry on as normal
+    Pass,
+    /// the CPU sees this value instead of what was read, or this gets written instead
+    Value(u8),
+    /// the write doesn't happen, reads see 0xFF
+    Drop
+}
+
+/// Called with the kind of access (`READ`, `WRITE` or `EXECUTE`), the address and the
+/// value read or about to be written
+pub type HookFn = Box<dyn FnMut(u8, u16, u8) -> Intercept>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+struct Hook {
+    id: HookId,
+    kinds: u8,
+    range: RangeInclusive<u16>,
+    callback: HookFn
+}
+
+/// Callbacks on CPU reads, writes and opcode fetches by address range.
+///
+/// The engine only looks here when something is registered, and `pages` keeps the lookup
+/// to one array index for the addresses nothing is watching.
+pub struct MemoryHooks {
+    hooks: Vec<Hook>,
+    /// kinds of hook covering each 256 byte page
+    pages: [u8; 256],
+    next_id: usize
+}
+
+impl MemoryHooks {
+    pub fn make_memory_hooks() -> MemoryHooks {
+        return MemoryHooks {
+            hooks: vec![],
+            pages: [0; 256],
+            next_id: 0
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.hooks.is_empty();
+    }
+
+    /// Calls `callback` for every `kinds` access in `range`, hooks run in the order they were added
+    pub fn add(&mut self, kinds: u8, range: RangeInclusive<u16>, callback: HookFn) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook { id: id, kinds: kinds, range: range, callback: callback });
+        self.update_pages();
+        return id;
+    }
+
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.update_pages();
+        return self.hooks.len() != before;
+    }
+
+    fn update_pages(&mut self) {
+        self.pages = [0; 256];
+        for hook in self.hooks.iter() {
+            for page in (*hook.range.start() >> 8)..=(*hook.range.end() >> 8) {
+                self.pages[page as usize] |= hook.kinds;
+            }
+        }
+    }
+
+    /// Runs the hooks watching a `kind` access to `addr`, the first one that doesn't `Pass` decides
+    pub fn check(&mut self, kind: u8, addr: u16, value: u8) -> Intercept {
+        if self.pages[(addr >> 8) as usize] & kind == 0 {
+            return Intercept::Pass;
+        }
+        for hook in self.hooks.iter_mut() {
+            if hook.kinds & kind != 0 && hook.range.contains(&addr) {
+                let res = (hook.callback)(kind, addr, value);
+                if res != Intercept::Pass {
+                    return res;
+                }
+            }
+        }
+        return Intercept::Pass;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::engine::hooks::{MemoryHooks, Intercept, READ, WRITE, EXECUTE};
+
+    #[test]
+    fn test_ranges_and_kinds() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut hooks = MemoryHooks::make_memory_hooks();
+        let log = seen.clone();
+        let id = hooks.add(READ | WRITE, 0xC000..=0xC0FF, Box::new(move |kind, addr, value| {
+            log.borrow_mut().push((kind, addr, value));
+            return Intercept::Pass;
+        }));
+        hooks.add(WRITE, 0x2000..=0x3FFF, Box::new(|_, _, _| Intercept::Drop));
+        hooks.add(READ, 0xC010..=0xC010, Box::new(|_, _, _| Intercept::Value(0x99)));
+
+        assert_eq!(Intercept::Pass, hooks.check(READ, 0xC001, 1));
+        assert_eq!(Intercept::Pass, hooks.check(EXECUTE, 0xC002, 2));
+        assert_eq!(Intercept::Pass, hooks.check(WRITE, 0xC100, 3));
+        assert_eq!(Intercept::Value(0x99), hooks.check(READ, 0xC010, 4));
+        assert_eq!(Intercept::Drop, hooks.check(WRITE, 0x2000, 5));
+        assert_eq!(vec![(READ, 0xC001, 1), (READ, 0xC010, 4)], *seen.borrow());
+
+        assert!(hooks.remove(id));
+        assert!(!hooks.remove(id));
+        assert_eq!(Intercept::Value(0x99), hooks.check(READ, 0xC010, 6));
+        assert_eq!(2, seen.borrow().len());
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::keyboard::Keycode;
+
+use crate::engine::engine::KeyNames;
+use crate::engine::config::Action;
+use crate::engine::controller::PadInput;
+
+/// Autofire A and B press rate when the config doesn't say, in presses per second
+pub const DEFAULT_AUTOFIRE_RATE: u32 = 15;
+
+/// Buttons held for each of a run of frames, played back by a hotkey
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    /// `ButtonState::pressed()` for each frame
+    pub frames: Vec<u8>
+}
+
+/// Autofire and input macros, pressed on top of the keys held at the start of each frame.
+///
+/// `ButtonState` asks for the extra buttons once a frame and a recording movie keeps the
+/// result, so a movie plays them back without needing the same macros or autofire keys.
+#[derive(Debug)]
+pub struct InputLayer {
+    /// frames since each of autofire A and B's keys went down, None while up
+    autofire: [Option<u64>; 2],
+    /// frames for one press and release of autofire A and B
+    pub autofire_period: [u64; 2],
+    pub macros: Vec<Macro>,
+    /// where `macros` came from, a newly recorded one is saved there
+    pub macro_file: Option<PathBuf>,
+    /// index of the macro playing and the frame it's up to
+    playing: Option<(usize, usize)>,
+    /// buttons held each frame since recording started
+    recording: Option<Vec<u8>>
+}
+
+impl InputLayer {
+    pub fn make_input_layer() -> InputLayer {
+        let period = autofire_period(DEFAULT_AUTOFIRE_RATE);
+        return InputLayer {
+            autofire: [None, None],
+            autofire_period: [period, period],
+            macros: vec![],
+            macro_file: None,
+            playing: None,
+            recording: None
+        };
+    }
+
+    /// Whether `frame` would press anything, or there's a recording to add to
+    pub fn is_active(&self) -> bool {
+        return self.autofire.iter().any(|held| held.is_some()) || self.playing.is_some() || self.recording.is_some();
+    }
+
+    /// Autofire for A or B, while held the button goes down and up by itself
+    pub fn set_autofire(&mut self, button: KeyNames, held: bool) {
+        let slot = match button {
+            KeyNames::A => &mut self.autofire[0],
+            KeyNames::B => &mut self.autofire[1],
+            _ => return
+        };
+        if held != slot.is_some() {
+            *slot = if held { Some(0) } else { None };
+        }
+    }
+
+    /// Buttons to press this frame on top of `keys`, the ones held on the keyboard or a controller
+    pub fn frame(&mut self, keys: u8) -> u8 {
+        let mut res = 0;
+        for (i, mask) in [0x10, 0x20].iter().enumerate() {
+            if let Some(count) = self.autofire[i].as_mut() {
+                let period = self.autofire_period[i].max(2);
+                if *count % period < (period + 1) / 2 {
+                    res |= mask;
+                }
+                *count += 1;
+            }
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(keys | res);
+        }
+        if let Some((index, position)) = self.playing {
+            res |= self.macros[index].frames[position];
+            self.playing = if position + 1 < self.macros[index].frames.len() { Some((index, position + 1)) } else { None };
+        }
+        return res;
+    }
+
+    /// Starts playing macro `index` from its first frame, false if there's no such macro
+    pub fn play(&mut self, index: usize) -> bool {
+        match self.macros.get(index) {
+            Some(m) if !m.frames.is_empty() => {
+                self.playing = Some((index, 0));
+                return true;
+            },
+            _ => return false
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        return self.recording.is_some();
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recording = Some(vec![]);
+    }
+
+    /// Adds what was recorded as a new macro and saves `macro_file`, returns its index unless
+    /// nothing was recorded
+    pub fn stop_recording(&mut self) -> Result<Option<usize>, String> {
+        let frames = match self.recording.take() {
+            Some(frames) if !frames.is_empty() => frames,
+            _ => return Ok(None)
+        };
+        self.macros.push(Macro { name: format!("macro{}", self.macros.len() + 1), frames: frames });
+        if let Some(path) = &self.macro_file {
+            save_macros(path, &self.macros)?;
+        }
+        return Ok(Some(self.macros.len() - 1));
+    }
+}
+
+/// A key, or an input on the controller with this instance id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputSource {
+    Key(Keycode),
+    Pad(u32, PadInput)
+}
+
+/// The actions held by each key and controller input. An action is down while any of them
+/// holds it, so letting go of the D-pad doesn't release Left still held on the keyboard.
+pub struct HeldActions {
+    held: HashSet<(InputSource, Action)>
+}
+
+impl HeldActions {
+    pub fn make_held_actions() -> HeldActions {
+        return HeldActions { held: HashSet::new() };
+    }
+
+    /// `source` went down or up, returns whether `action` did, None if it didn't change
+    pub fn set(&mut self, source: InputSource, action: Action, down: bool) -> Option<bool> {
+        let was_held = self.is_held(action);
+        if down {
+            self.held.insert((source, action));
+        } else {
+            self.held.remove(&(source, action));
+        }
+        let held = self.is_held(action);
+        return if held != was_held { Some(held) } else { None };
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        return self.held.iter().any(|(_, a)| *a == action);
+    }
+}
+
+/// Frames for one press and release at `rate` presses per second
+pub fn autofire_period(rate: u32) -> u64 {
+    return ((60.0 / rate.max(1) as f64).round() as u64).max(2);
+}
+
+/// Macros from a file with one per line, like
+///
+/// ```text
+/// # name = buttons held each frame
+/// grind = 00*30 10*2 00*10 80
+/// ```
+///
+/// where each entry is a `ButtonState::pressed()` byte in hex, `*n` repeating it n times.
+pub fn parse_macros(text: &str) -> Result<Vec<Macro>, String> {
+    let mut res = vec![];
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", line_number + 1, message);
+        let mut parts = line.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if !name.trim().is_empty() => (name.trim(), value),
+            _ => return Err(error("expected name = frames"))
+        };
+        let mut frames = vec![];
+        for entry in value.split_whitespace() {
+            let mut parts = entry.splitn(2, '*');
+            let pressed = u8::from_str_radix(parts.next().unwrap_or(""), 16).map_err(|_| error(&format!("bad buttons {}", entry)))?;
+            let count = match parts.next() {
+                Some(count) => count.parse::<usize>().map_err(|_| error(&format!("bad count {}", entry)))?,
+                None => 1
+            };
+            frames.extend(std::iter::repeat(pressed).take(count));
+        }
+        res.push(Macro { name: name.to_string(), frames: frames });
+    }
+    return Ok(res);
+}
+
+/// Text for `parse_macros`
+pub fn macros_to_text(macros: &[Macro]) -> String {
+    let mut res = String::new();
+    for m in macros {
+        res += &m.name;
+        res += " =";
+        let mut i = 0;
+        while i < m.frames.len() {
+            let run = m.frames[i..].iter().take_while(|pressed| **pressed == m.frames[i]).count();
+            res += &if run == 1 { format!(" {:02X}", m.frames[i]) } else { format!(" {:02X}*{}", m.frames[i], run) };
+            i += run;
+        }
+        res += "\n";
+    }
+    return res;
+}
+
+pub fn load_macros(path: &Path) -> Result<Vec<Macro>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    return parse_macros(&text).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+pub fn save_macros(path: &Path, macros: &[Macro]) -> Result<(), String> {
+    return fs::write(path, macros_to_text(macros)).map_err(|e| format!("Couldn't write {}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::controller::Button;
+    use sdl2::keyboard::Keycode;
+    use crate::engine::input::{InputLayer, Macro, HeldActions, InputSource, autofire_period, parse_macros, macros_to_text};
+    use crate::engine::engine::KeyNames;
+    use crate::engine::config::Action;
+    use crate::engine::controller::PadInput;
+
+    #[test]
+    fn test_autofire() {
+        assert_eq!(4, autofire_period(15));
+        assert_eq!(2, autofire_period(60));
+        let mut layer = InputLayer::make_input_layer();
+        assert!(!layer.is_active());
+        layer.set_autofire(KeyNames::A, true);
+        let frames: Vec<u8> = (0..6).map(|_| layer.frame(0)).collect();
+        assert_eq!(vec![0x10, 0x10, 0, 0, 0x10, 0x10], frames);
+        layer.set_autofire(KeyNames::A, false);
+        assert_eq!(0, layer.frame(0));
+        assert!(!layer.is_active());
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut layer = InputLayer::make_input_layer();
+        layer.start_recording();
+        layer.frame(0x01);
+        layer.frame(0x01);
+        layer.frame(0x80);
+        assert_eq!(Ok(Some(0)), layer.stop_recording());
+        assert_eq!(Macro { name: "macro1".to_string(), frames: vec![0x01, 0x01, 0x80] }, layer.macros[0]);
+
+        assert!(layer.play(0));
+        assert!(!layer.play(1));
+        assert_eq!(vec![0x01, 0x01, 0x80, 0], (0..4).map(|_| layer.frame(0)).collect::<Vec<u8>>());
+
+        let text = macros_to_text(&layer.macros);
+        assert_eq!("macro1 = 01*2 80\n", text);
+        assert_eq!(layer.macros, parse_macros(&text).unwrap());
+        assert_eq!(vec![0, 0, 0x10], parse_macros("# grind\ngrind = 00*2 10\n").unwrap()[0].frames);
+        assert!(parse_macros("grind = 100").is_err());
+        assert!(parse_macros("= 10").is_err());
+    }
+
+    #[test]
+    fn test_held_actions() {
+        let left = Action::Button(KeyNames::LEFT);
+        let key = InputSource::Key(Keycode::Left);
+        let dpad = InputSource::Pad(0, PadInput::Button(Button::DPadLeft));
+        let mut held = HeldActions::make_held_actions();
+        assert_eq!(Some(true), held.set(key, left, true));
+        assert_eq!(None, held.set(dpad, left, true));
+        // the key still holds it
+        assert_eq!(None, held.set(dpad, left, false));
+        assert!(held.is_held(left));
+        assert_eq!(None, held.set(dpad, left, false));
+        assert_eq!(Some(false), held.set(key, left, false));
+        assert!(!held.is_held(left));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::engine::memory::Memory;
+use crate::engine::state::{StateWriter, StateReader};
+
+pub const VBLANK: u8 = 0;
+pub const LCD_STAT: u8 = 1;
+pub const TIMER: u8 = 2;
+pub const SERIAL: u8 = 3;
+pub const JOYPAD: u8 = 4;
+
+const IF_ADDR: u16 = 0xFF0F;
+const IE_ADDR: u16 = 0xFFFF;
+
+/// What the CPU is doing between instructions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuState {
+    Running,
+    /// HALT, wakes up as soon as IE & IF is non zero
+    Halted,
+    /// STOP, wakes up on a joypad press
+    Stopped,
+    /// Executed an illegal opcode, nothing but a reset gets out of this
+    Locked
+}
+
+/// Interrupt master enable, the EI delay and the HALT / STOP states.
+///
+/// IE (0xFFFF) and IF (0xFF0F) stay in memory since the GPU, timer and
+/// joypad raise their requests through `Memory::setInterruptFlag`.
+#[derive(Debug)]
+pub struct Interrupts {
+    pub ime: bool,
+    /// EI was executed, IME turns on after the next instruction
+    pub ime_pending: bool,
+    pub state: CpuState,
+    /// HALT with IME=0 and an interrupt already pending fails to increment PC on the next fetch
+    pub halt_bug: bool
+}
+
+impl Interrupts {
+    pub fn make_interrupts() -> Interrupts {
+        return Interrupts {
+            ime: false,
+            ime_pending: false,
+            state: CpuState::Running,
+            halt_bug: false
+        };
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.ime);
+        out.bool(self.ime_pending);
+        out.u8(match self.state {
+            CpuState::Running => 0,
+            CpuState::Halted => 1,
+            CpuState::Stopped => 2,
+            CpuState::Locked => 3
+        });
+        out.bool(self.halt_bug);
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.ime = input.bool()?;
+        self.ime_pending = input.bool()?;
+        self.state = match input.u8()? {
+            0 => CpuState::Running,
+            1 => CpuState::Halted,
+            2 => CpuState::Stopped,
+            3 => CpuState::Locked,
+            other => return Err(format!("Bad CPU state {} in snapshot", other))
+        };
+        self.halt_bug = input.bool()?;
+        return Ok(());
+    }
+
+    /// Interrupts that are both requested and enabled
+    pub fn pending(memory: &dyn Memory) -> u8 {
+        return memory.get(IF_ADDR) & memory.get(IE_ADDR) & 0x1F;
+    }
+
+    /// Highest priority (lowest bit) interrupt in `pending`
+    pub fn highest_priority(pending: u8) -> Option<u8> {
+        if pending & 0x1F == 0 {
+            return None;
+        }
+        return Some(pending.trailing_zeros() as u8);
+    }
+
+    pub fn vector(interrupt: u8) -> u16 {
+        return 0x0040 + 8 * interrupt as u16;
+    }
+
+    pub fn acknowledge(memory: &mut dyn Memory, interrupt: u8) {
+        let flags = memory.get(IF_ADDR);
+        memory.set(IF_ADDR, flags & !(1 << interrupt));
+    }
+
+    /// EI
+    pub fn enable_delayed(&mut self) {
+        if !self.ime {
+            self.ime_pending = true;
+        }
+    }
+
+    /// RETI
+    pub fn enable(&mut self) {
+        self.ime = true;
+        self.ime_pending = false;
+    }
+
+    /// DI, also cancels an EI that hasn't taken effect yet
+    pub fn disable(&mut self) {
+        self.ime = false;
+        self.ime_pending = false;
+    }
+
+    /// Called once an instruction has finished, `was_pending` is whether EI was
+    /// pending before that instruction started.
+    pub fn finish_instruction(&mut self, was_pending: bool) {
+        if was_pending && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+    }
+
+    pub fn halt(&mut self, memory: &dyn Memory) {
+        if !self.ime && Interrupts::pending(memory) != 0 {
+            self.halt_bug = true;
+        } else {
+            self.state = CpuState::Halted;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = CpuState::Stopped;
+    }
+
+    /// Returns true (once) if the next opcode fetch should not increment PC
+    pub fn take_halt_bug(&mut self) -> bool {
+        let res = self.halt_bug;
+        self.halt_bug = false;
+        return res;
+    }
+}
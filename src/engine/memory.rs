@@ -1,6 +1,10 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::engine::registers::Registers;
 use crate::engine::registers::RegisterNames;
 use crate::engine::engine::KeyNames;
+use crate::engine::state::{StateWriter, StateReader};
 
 /// Common interface for various types of memory mapped RAM supported in different GB cards
 pub trait Memory {
@@ -17,11 +21,21 @@ pub trait Memory {
     /// handle saving for writable cards 
     fn save(&self) -> Vec<u8> ;
 
+    /// ROM bank currently mapped at 0x4000-0x7FFF
+    fn rom_bank(&self) -> u16 {
+        return 1;
+    }
+
+    /// Writes the RAM, banks and mapper registers for a snapshot, not the ROM
+    fn save_state(&self, _out: &mut StateWriter) {}
+
+    fn load_state(&mut self, _input: &mut StateReader) -> Result<(), String> {
+        return Ok(());
+    }
+
     fn setInterruptFlag(&mut self, flag: u8) {
         let interrupts = self.get(0xFF0F);
-        if (interrupts & (1 << (flag))) == 0 {
-            self.set(0xFF0F,  interrupts + (1 << (flag)));
-        }
+        self.set(0xFF0F, interrupts | (1 << flag));
     }
 
     fn setLong(&mut self, loc: u16, val: u16) {
@@ -38,19 +52,19 @@ pub trait Memory {
 
         let sp = reg.get_register(&RegisterNames::SP);
 
-        self.set(sp - 2, low_byte);
-        self.set(sp - 1, high_byte);
+        self.set(sp.wrapping_sub(1), high_byte);
+        self.set(sp.wrapping_sub(2), low_byte);
 
-        reg.set_register(&RegisterNames::SP, sp - 2);
+        reg.set_register(&RegisterNames::SP, sp.wrapping_sub(2));
     }
 
     fn pop_stack(&self, reg: &mut Registers) -> u16 {
         let sp = reg.get_register(&RegisterNames::SP);
 
-        let high_byte = self.get(sp + 1) as u16;
-        let low_byte = self.get(sp + 0) as u16;
+        let high_byte = self.get(sp.wrapping_add(1)) as u16;
+        let low_byte = self.get(sp) as u16;
 
-        reg.set_register(&RegisterNames::SP, sp + 2);
+        reg.set_register(&RegisterNames::SP, sp.wrapping_add(2));
 
         return high_byte * 0x100 + low_byte;
     }
@@ -108,6 +122,14 @@ impl Memory for ROMOnlyMemory {
     fn save(&self) -> Vec<u8> {
         return vec![];
     }
+
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        return input.bytes_into(&mut self.ram);
+    }
 }
 
 #[derive(Debug)]
@@ -207,6 +229,33 @@ impl Memory for MBC1Memory {
 
         return res;
     }
+
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+        for bank in self.ram_banks.iter() {
+            out.bytes(bank);
+        }
+        out.u32(self.bank_n);
+        out.u32(self.ram_bank_n);
+        out.bool(self.memory_model_is_4_32);
+        out.bool(self.ram_bank_ops_disabled);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.ram)?;
+        for bank in self.ram_banks.iter_mut() {
+            input.bytes_into(bank)?;
+        }
+        self.bank_n = input.u32()?;
+        self.ram_bank_n = input.u32()?;
+        self.memory_model_is_4_32 = input.bool()?;
+        self.ram_bank_ops_disabled = input.bool()?;
+        return Ok(());
+    }
+
+    fn rom_bank(&self) -> u16 {
+        return self.bank_n as u16;
+    }
 }
 
 
@@ -312,6 +361,33 @@ impl Memory for MBC3Memory {
 
         return res;
     }
+
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+        for bank in self.ram_banks.iter() {
+            out.bytes(bank);
+        }
+        out.u32(self.bank_n);
+        out.u32(self.ram_bank_n);
+        out.bool(self.memory_model_is_4_32);
+        out.bool(self.ram_bank_ops_disabled);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.ram)?;
+        for bank in self.ram_banks.iter_mut() {
+            input.bytes_into(bank)?;
+        }
+        self.bank_n = input.u32()?;
+        self.ram_bank_n = input.u32()?;
+        self.memory_model_is_4_32 = input.bool()?;
+        self.ram_bank_ops_disabled = input.bool()?;
+        return Ok(());
+    }
+
+    fn rom_bank(&self) -> u16 {
+        return self.bank_n as u16;
+    }
 }
 
 
@@ -407,4 +483,87 @@ impl Memory for MBC5Memory {
 
         return res;
     }
+
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+        for bank in self.ram_banks.iter() {
+            out.bytes(bank);
+        }
+        out.u32(self.rom_bank_n);
+        out.bool(self.rom_bank_hi);
+        out.u32(self.ram_bank_n);
+        out.bool(self.memory_model_is_4_32);
+        out.bool(self.ram_bank_ops_disabled);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.ram)?;
+        for bank in self.ram_banks.iter_mut() {
+            input.bytes_into(bank)?;
+        }
+        self.rom_bank_n = input.u32()?;
+        self.rom_bank_hi = input.bool()?;
+        self.ram_bank_n = input.u32()?;
+        self.memory_model_is_4_32 = input.bool()?;
+        self.ram_bank_ops_disabled = input.bool()?;
+        return Ok(());
+    }
+
+    fn rom_bank(&self) -> u16 {
+        return self.rom_bank_n as u16;
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool
+}
+
+/// 64KB of plain RAM with no banking or IO registers that records every access, for CPU tests
+#[derive(Debug)]
+pub struct FlatMemory {
+    ram: Vec<u8>,
+    pub accesses: Rc<RefCell<Vec<BusAccess>>>
+}
+
+impl FlatMemory {
+    pub fn make_memory() -> FlatMemory {
+        FlatMemory {
+            ram: vec![0; 0xFFFF + 1],
+            accesses: Rc::new(RefCell::new(vec![]))
+        }
+    }
+}
+
+impl Memory for FlatMemory {
+    fn set(&mut self, loc: u16, val: u8) {
+        self.accesses.borrow_mut().push(BusAccess { addr: loc, value: val, write: true });
+        self.ram[loc as usize] = val;
+    }
+
+    fn get(&self, loc: u16) -> u8 {
+        let val = self.ram[loc as usize];
+        self.accesses.borrow_mut().push(BusAccess { addr: loc, value: val, write: false });
+        return val;
+    }
+
+    fn load(&mut self, ram: Vec<u8>) {
+        let len = std::cmp::min(ram.len(), self.ram.len());
+        self.ram[..len].copy_from_slice(&ram[..len]);
+    }
+
+    fn save(&self) -> Vec<u8> {
+        return vec![];
+    }
+
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        return input.bytes_into(&mut self.ram);
+    }
 }
@@ -1,7 +1,28 @@
-mod registers;
-mod gpu;
-mod clock;
-mod memory;
+pub mod registers;
+pub mod gpu;
+pub mod clock;
+pub mod memory;
+pub mod interrupts;
+pub mod opcodes;
+pub mod trace;
+pub mod debugger;
+pub mod gdb;
+pub mod symbols;
+pub mod disasm;
+pub mod profiler;
+pub mod cdl;
+pub mod hooks;
+pub mod cheats;
+pub mod search;
+pub mod movie;
+pub mod state;
+pub mod rewind;
+pub mod speed;
+pub mod controller;
+pub mod input;
+pub mod config;
+pub mod header;
+pub mod testrom;
 pub mod engine;
 
 pub fn make_engine(rom: Vec::<u8>) -> engine::Engine {
@@ -20,18 +41,40 @@ pub fn make_engine(rom: Vec::<u8>) -> engine::Engine {
     //unsafe {memory.ram.set_len(0xFFFF+1);}
 
     memory.set(0xFF40, 0x91); // set LCDC
+    memory.set(0xFF00, 0xCF); // joypad with both rows selected, as ButtonState starts
 
+    //gpu.tick(&mut memory, 800);
 
-    let mut gpu = gpu::GPU::make_gpu();
+    return make_engine_with_memory(memory);
+}
 
-    //gpu.tick(&mut memory, 800);
+/// Engine around an already built memory, e.g. `memory::FlatMemory` for CPU tests
+pub fn make_engine_with_memory(memory: Box<dyn memory::Memory>) -> engine::Engine {
+    let gpu = gpu::GPU::make_gpu();
 
     return engine::Engine{
         memory: memory,
         registers: registers::Registers::make_registers(),
-        enable_interrupt: engine::InterruptState::Disabled,
+        interrupts: interrupts::Interrupts::make_interrupts(),
         gpu: gpu,
         clock: clock::Clock::make_clock(),
-        buttons: engine::ButtonState::create()
+        buttons: engine::ButtonState::create(),
+        events: vec![],
+        step_cycles: 0,
+        cpu_only: false,
+        tracer: None,
+        debugger: None,
+        gdb: None,
+        profiler: None,
+        cdl: None,
+        hooks: hooks::MemoryHooks::make_memory_hooks(),
+        cheats: cheats::Cheats::make_cheats(),
+        movie: None,
+        rewind: None,
+        speed: speed::Speed::make_speed(),
+        config: config::Config::make_config(),
+        symbols: symbols::Symbols::make_symbols(),
+        boot_rom: vec![],
+        boot_rom_mapped: false
     };
 }
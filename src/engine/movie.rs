@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RBMV";
+const VERSION: u8 = 1;
+
+/// What the machine looked like when the movie's first frame started
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anchor {
+    /// fresh from `make_engine`, with empty cartridge RAM
+    PowerOn,
+    /// power on with this battery backed cartridge RAM, i.e. a `.sav` file
+    SaveRam(Vec<u8>),
+    /// an `Engine::save_state` snapshot, for a movie that starts mid game
+    State(Vec<u8>)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    /// played to the end, the buttons are all released
+    Finished
+}
+
+/// Joypad input for every frame from a known starting point, enough to replay a session
+/// exactly.
+///
+/// Each frame's byte is `ButtonState::pressed()`, applied before the first instruction of
+/// that frame. Input only changes there, so while recording the keys pressed mid frame are
+/// held back until the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// CRC-32 of the whole ROM file
+    pub rom_crc: u32,
+    pub anchor: Anchor,
+    pub frames: Vec<u8>,
+    pub mode: MovieMode,
+    /// buttons down right now while recording, the next frame gets these
+    pub(crate) held: u8,
+    /// `GPU::frames` when the next movie frame starts
+    pub(crate) next_frame: u64,
+    /// frames played so far
+    pub(crate) position: usize
+}
+
+impl Movie {
+    /// Starts a recording for the ROM with `crc32()` `rom_crc`, cartridge RAM that isn't all
+    /// zero is kept as the anchor
+    pub fn make_recording(rom_crc: u32, save_ram: Vec<u8>) -> Movie {
+        let anchor = if save_ram.iter().all(|b| *b == 0) { Anchor::PowerOn } else { Anchor::SaveRam(save_ram) };
+        return Movie {
+            rom_crc: rom_crc,
+            anchor: anchor,
+            frames: vec![],
+            mode: MovieMode::Recording,
+            held: 0,
+            next_frame: 0,
+            position: 0
+        };
+    }
+
+    /// Starts a recording from `Engine::save_state` snapshot `state`
+    pub fn make_state_recording(rom_crc: u32, state: Vec<u8>) -> Movie {
+        let mut movie = Movie::make_recording(rom_crc, vec![]);
+        movie.anchor = Anchor::State(state);
+        return movie;
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let data = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        return Movie::from_bytes(&data).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        return fs::write(path, self.to_bytes()).map_err(|e| format!("Couldn't write {}: {}", path.display(), e));
+    }
+
+    /// Errors unless the movie was made with this ROM
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), String> {
+        let crc = crc32(rom);
+        if crc != self.rom_crc {
+            return Err(format!("Movie was recorded with a ROM with CRC {:08X}, this one is {:08X}", self.rom_crc, crc));
+        }
+        return Ok(());
+    }
+
+    /// "RBMV", version, ROM CRC, anchor type and data, then one byte per frame
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = MAGIC.to_vec();
+        res.push(VERSION);
+        res.extend_from_slice(&self.rom_crc.to_le_bytes());
+        match &self.anchor {
+            Anchor::PowerOn => res.push(0),
+            Anchor::SaveRam(ram) => {
+                res.push(1);
+                res.extend_from_slice(&(ram.len() as u32).to_le_bytes());
+                res.extend_from_slice(ram);
+            },
+            Anchor::State(state) => {
+                res.push(2);
+                res.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                res.extend_from_slice(state);
+            }
+        }
+        res.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        res.extend_from_slice(&self.frames);
+        return res;
+    }
+
+    /// Reads a movie to play back
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, String> {
+        let mut pos = 0;
+        let mut take = |len: usize| take(data, &mut pos, len);
+
+        if take(4)? != MAGIC {
+            return Err("Not a movie".to_string());
+        }
+        let version = take(1)?[0];
+        if version != VERSION {
+            return Err(format!("Movie version {} isn't supported", version));
+        }
+        let rom_crc = read_u32(take(4)?);
+        let anchor = match take(1)?[0] {
+            0 => Anchor::PowerOn,
+            1 => {
+                let len = read_u32(take(4)?) as usize;
+                Anchor::SaveRam(take(len)?.to_vec())
+            },
+            2 => {
+                let len = read_u32(take(4)?) as usize;
+                Anchor::State(take(len)?.to_vec())
+            },
+            other => return Err(format!("Unknown anchor type {}", other))
+        };
+        let len = read_u32(take(4)?) as usize;
+        let frames = take(len)?.to_vec();
+
+        return Ok(Movie {
+            rom_crc: rom_crc,
+            anchor: anchor,
+            frames: frames,
+            mode: MovieMode::Playing,
+            held: 0,
+            next_frame: 0,
+            position: 0
+        });
+    }
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], String> {
+    let res = data.get(*pos..*pos + len).ok_or("Movie is cut short")?;
+    *pos += len;
+    return Ok(res);
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    return u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+}
+
+/// The usual zlib/PNG CRC-32
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    return !crc;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::movie::{Movie, MovieMode, Anchor, crc32};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xCBF43926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::make_recording(crc32(&[1, 2, 3]), vec![0; 16]);
+        assert_eq!(Anchor::PowerOn, movie.anchor);
+        movie.frames = vec![0, 0x10, 0x11, 0];
+        movie.anchor = Anchor::SaveRam(vec![7; 3]);
+
+        let data = movie.to_bytes();
+        let loaded = Movie::from_bytes(&data).unwrap();
+        assert_eq!(MovieMode::Playing, loaded.mode);
+        assert_eq!(movie.frames, loaded.frames);
+        assert_eq!(movie.anchor, loaded.anchor);
+        assert!(loaded.check_rom(&[1, 2, 3]).is_ok());
+        assert!(loaded.check_rom(&[1, 2]).is_err());
+
+        let movie = Movie::make_state_recording(movie.rom_crc, vec![1, 2, 3, 4]);
+        assert_eq!(Anchor::State(vec![1, 2, 3, 4]), Movie::from_bytes(&movie.to_bytes()).unwrap().anchor);
+
+        assert!(Movie::from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(Movie::from_bytes(b"RBMX").is_err());
+    }
+}
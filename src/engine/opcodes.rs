@@ -0,0 +1,660 @@
+use crate::engine::memory::Memory;
+use crate::engine::symbols::Symbols;
+
+/// What an instruction does, the operands say what it does it to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Nop, Stop, Halt, Di, Ei, Prefix, Illegal,
+    Ld, LdHlSp, Push, Pop,
+    Inc, Dec, Add, Adc, Sub, Sbc, And, Xor, Or, Cp,
+    Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf,
+    Jr, Jp, Call, Ret, Reti, Rst,
+
+    // CB prefixed
+    Rlc, Rrc, Rl, Rr, Sla, Sra, Swap, Srl, Bit, Res, Set
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    None,
+    A, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP,
+    /// [bc], [de], [hl], [hl+], [hl-]
+    IndBC, IndDE, IndHL, IndHLInc, IndHLDec,
+    /// 8 bit immediate
+    D8,
+    /// 16 bit immediate
+    D16,
+    /// signed 8 bit immediate
+    R8,
+    /// 16 bit immediate used as an address to jump to
+    A16,
+    /// [a16]
+    IndA16,
+    /// [$FF00 + a8]
+    IndA8,
+    /// [$FF00 + c]
+    IndC,
+    /// sp + r8
+    SPR8,
+    CondNZ, CondZ, CondNC, CondC,
+    Bit(u8),
+    Vector(u16)
+}
+
+impl Operand {
+    /// How many immediate bytes follow the opcode for this operand
+    pub fn immediate_bytes(&self) -> u8 {
+        return match self {
+            Operand::D8 | Operand::R8 | Operand::IndA8 | Operand::SPR8 => 1,
+            Operand::D16 | Operand::A16 | Operand::IndA16 => 2,
+            _ => 0
+        };
+    }
+
+    pub fn is_wide(&self) -> bool {
+        return match self {
+            Operand::AF | Operand::BC | Operand::DE | Operand::HL | Operand::SP | Operand::D16 => true,
+            _ => false
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub op: Op,
+    pub mnemonic: &'static str,
+    pub dst: Operand,
+    pub src: Operand,
+    /// total bytes, including the opcode (and the 0xCB prefix)
+    pub length: u8,
+    /// clock cycles when no branch is taken
+    pub cycles: u8,
+    /// clock cycles when a conditional branch is taken, same as `cycles` for everything else
+    pub branch_cycles: u8
+}
+
+macro_rules! i {
+    ($op:ident, $mnemonic:expr, $dst:ident, $src:ident, $length:expr, $cycles:expr) => {
+        i!($op, $mnemonic, $dst, $src, $length, $cycles, $cycles)
+    };
+    ($op:ident, $mnemonic:expr, $dst:ident, $src:ident, $length:expr, $cycles:expr, $branch_cycles:expr) => {
+        Instruction {
+            op: Op::$op,
+            mnemonic: $mnemonic,
+            dst: Operand::$dst,
+            src: Operand::$src,
+            length: $length,
+            cycles: $cycles,
+            branch_cycles: $branch_cycles
+        }
+    };
+}
+
+macro_rules! rst {
+    ($vector:expr) => {
+        Instruction {
+            op: Op::Rst,
+            mnemonic: "rst",
+            dst: Operand::Vector($vector),
+            src: Operand::None,
+            length: 1,
+            cycles: 16,
+            branch_cycles: 16
+        }
+    };
+}
+
+const ILLEGAL: Instruction = i!(Illegal, "db", None, None, 1, 4);
+
+// see https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
+pub static OPCODES: [Instruction; 256] = [
+    // 0x00
+    i!(Nop, "nop", None, None, 1, 4),
+    i!(Ld, "ld", BC, D16, 3, 12),
+    i!(Ld, "ld", IndBC, A, 1, 8),
+    i!(Inc, "inc", BC, None, 1, 8),
+    i!(Inc, "inc", B, None, 1, 4),
+    i!(Dec, "dec", B, None, 1, 4),
+    i!(Ld, "ld", B, D8, 2, 8),
+    i!(Rlca, "rlca", None, None, 1, 4),
+    i!(Ld, "ld", IndA16, SP, 3, 20),
+    i!(Add, "add", HL, BC, 1, 8),
+    i!(Ld, "ld", A, IndBC, 1, 8),
+    i!(Dec, "dec", BC, None, 1, 8),
+    i!(Inc, "inc", C, None, 1, 4),
+    i!(Dec, "dec", C, None, 1, 4),
+    i!(Ld, "ld", C, D8, 2, 8),
+    i!(Rrca, "rrca", None, None, 1, 4),
+
+    // 0x10
+    i!(Stop, "stop", None, None, 2, 4),
+    i!(Ld, "ld", DE, D16, 3, 12),
+    i!(Ld, "ld", IndDE, A, 1, 8),
+    i!(Inc, "inc", DE, None, 1, 8),
+    i!(Inc, "inc", D, None, 1, 4),
+    i!(Dec, "dec", D, None, 1, 4),
+    i!(Ld, "ld", D, D8, 2, 8),
+    i!(Rla, "rla", None, None, 1, 4),
+    i!(Jr, "jr", R8, None, 2, 12),
+    i!(Add, "add", HL, DE, 1, 8),
+    i!(Ld, "ld", A, IndDE, 1, 8),
+    i!(Dec, "dec", DE, None, 1, 8),
+    i!(Inc, "inc", E, None, 1, 4),
+    i!(Dec, "dec", E, None, 1, 4),
+    i!(Ld, "ld", E, D8, 2, 8),
+    i!(Rra, "rra", None, None, 1, 4),
+
+    // 0x20
+    i!(Jr, "jr", CondNZ, R8, 2, 8, 12),
+    i!(Ld, "ld", HL, D16, 3, 12),
+    i!(Ld, "ld", IndHLInc, A, 1, 8),
+    i!(Inc, "inc", HL, None, 1, 8),
+    i!(Inc, "inc", H, None, 1, 4),
+    i!(Dec, "dec", H, None, 1, 4),
+    i!(Ld, "ld", H, D8, 2, 8),
+    i!(Daa, "daa", None, None, 1, 4),
+    i!(Jr, "jr", CondZ, R8, 2, 8, 12),
+    i!(Add, "add", HL, HL, 1, 8),
+    i!(Ld, "ld", A, IndHLInc, 1, 8),
+    i!(Dec, "dec", HL, None, 1, 8),
+    i!(Inc, "inc", L, None, 1, 4),
+    i!(Dec, "dec", L, None, 1, 4),
+    i!(Ld, "ld", L, D8, 2, 8),
+    i!(Cpl, "cpl", None, None, 1, 4),
+
+    // 0x30
+    i!(Jr, "jr", CondNC, R8, 2, 8, 12),
+    i!(Ld, "ld", SP, D16, 3, 12),
+    i!(Ld, "ld", IndHLDec, A, 1, 8),
+    i!(Inc, "inc", SP, None, 1, 8),
+    i!(Inc, "inc", IndHL, None, 1, 12),
+    i!(Dec, "dec", IndHL, None, 1, 12),
+    i!(Ld, "ld", IndHL, D8, 2, 12),
+    i!(Scf, "scf", None, None, 1, 4),
+    i!(Jr, "jr", CondC, R8, 2, 8, 12),
+    i!(Add, "add", HL, SP, 1, 8),
+    i!(Ld, "ld", A, IndHLDec, 1, 8),
+    i!(Dec, "dec", SP, None, 1, 8),
+    i!(Inc, "inc", A, None, 1, 4),
+    i!(Dec, "dec", A, None, 1, 4),
+    i!(Ld, "ld", A, D8, 2, 8),
+    i!(Ccf, "ccf", None, None, 1, 4),
+
+    // 0x40
+    i!(Ld, "ld", B, B, 1, 4),
+    i!(Ld, "ld", B, C, 1, 4),
+    i!(Ld, "ld", B, D, 1, 4),
+    i!(Ld, "ld", B, E, 1, 4),
+    i!(Ld, "ld", B, H, 1, 4),
+    i!(Ld, "ld", B, L, 1, 4),
+    i!(Ld, "ld", B, IndHL, 1, 8),
+    i!(Ld, "ld", B, A, 1, 4),
+    i!(Ld, "ld", C, B, 1, 4),
+    i!(Ld, "ld", C, C, 1, 4),
+    i!(Ld, "ld", C, D, 1, 4),
+    i!(Ld, "ld", C, E, 1, 4),
+    i!(Ld, "ld", C, H, 1, 4),
+    i!(Ld, "ld", C, L, 1, 4),
+    i!(Ld, "ld", C, IndHL, 1, 8),
+    i!(Ld, "ld", C, A, 1, 4),
+
+    // 0x50
+    i!(Ld, "ld", D, B, 1, 4),
+    i!(Ld, "ld", D, C, 1, 4),
+    i!(Ld, "ld", D, D, 1, 4),
+    i!(Ld, "ld", D, E, 1, 4),
+    i!(Ld, "ld", D, H, 1, 4),
+    i!(Ld, "ld", D, L, 1, 4),
+    i!(Ld, "ld", D, IndHL, 1, 8),
+    i!(Ld, "ld", D, A, 1, 4),
+    i!(Ld, "ld", E, B, 1, 4),
+    i!(Ld, "ld", E, C, 1, 4),
+    i!(Ld, "ld", E, D, 1, 4),
+    i!(Ld, "ld", E, E, 1, 4),
+    i!(Ld, "ld", E, H, 1, 4),
+    i!(Ld, "ld", E, L, 1, 4),
+    i!(Ld, "ld", E, IndHL, 1, 8),
+    i!(Ld, "ld", E, A, 1, 4),
+
+    // 0x60
+    i!(Ld, "ld", H, B, 1, 4),
+    i!(Ld, "ld", H, C, 1, 4),
+    i!(Ld, "ld", H, D, 1, 4),
+    i!(Ld, "ld", H, E, 1, 4),
+    i!(Ld, "ld", H, H, 1, 4),
+    i!(Ld, "ld", H, L, 1, 4),
+    i!(Ld, "ld", H, IndHL, 1, 8),
+    i!(Ld, "ld", H, A, 1, 4),
+    i!(Ld, "ld", L, B, 1, 4),
+    i!(Ld, "ld", L, C, 1, 4),
+    i!(Ld, "ld", L, D, 1, 4),
+    i!(Ld, "ld", L, E, 1, 4),
+    i!(Ld, "ld", L, H, 1, 4),
+    i!(Ld, "ld", L, L, 1, 4),
+    i!(Ld, "ld", L, IndHL, 1, 8),
+    i!(Ld, "ld", L, A, 1, 4),
+
+    // 0x70
+    i!(Ld, "ld", IndHL, B, 1, 8),
+    i!(Ld, "ld", IndHL, C, 1, 8),
+    i!(Ld, "ld", IndHL, D, 1, 8),
+    i!(Ld, "ld", IndHL, E, 1, 8),
+    i!(Ld, "ld", IndHL, H, 1, 8),
+    i!(Ld, "ld", IndHL, L, 1, 8),
+    i!(Halt, "halt", None, None, 1, 4),
+    i!(Ld, "ld", IndHL, A, 1, 8),
+    i!(Ld, "ld", A, B, 1, 4),
+    i!(Ld, "ld", A, C, 1, 4),
+    i!(Ld, "ld", A, D, 1, 4),
+    i!(Ld, "ld", A, E, 1, 4),
+    i!(Ld, "ld", A, H, 1, 4),
+    i!(Ld, "ld", A, L, 1, 4),
+    i!(Ld, "ld", A, IndHL, 1, 8),
+    i!(Ld, "ld", A, A, 1, 4),
+
+    // 0x80
+    i!(Add, "add", A, B, 1, 4),
+    i!(Add, "add", A, C, 1, 4),
+    i!(Add, "add", A, D, 1, 4),
+    i!(Add, "add", A, E, 1, 4),
+    i!(Add, "add", A, H, 1, 4),
+    i!(Add, "add", A, L, 1, 4),
+    i!(Add, "add", A, IndHL, 1, 8),
+    i!(Add, "add", A, A, 1, 4),
+    i!(Adc, "adc", A, B, 1, 4),
+    i!(Adc, "adc", A, C, 1, 4),
+    i!(Adc, "adc", A, D, 1, 4),
+    i!(Adc, "adc", A, E, 1, 4),
+    i!(Adc, "adc", A, H, 1, 4),
+    i!(Adc, "adc", A, L, 1, 4),
+    i!(Adc, "adc", A, IndHL, 1, 8),
+    i!(Adc, "adc", A, A, 1, 4),
+
+    // 0x90
+    i!(Sub, "sub", A, B, 1, 4),
+    i!(Sub, "sub", A, C, 1, 4),
+    i!(Sub, "sub", A, D, 1, 4),
+    i!(Sub, "sub", A, E, 1, 4),
+    i!(Sub, "sub", A, H, 1, 4),
+    i!(Sub, "sub", A, L, 1, 4),
+    i!(Sub, "sub", A, IndHL, 1, 8),
+    i!(Sub, "sub", A, A, 1, 4),
+    i!(Sbc, "sbc", A, B, 1, 4),
+    i!(Sbc, "sbc", A, C, 1, 4),
+    i!(Sbc, "sbc", A, D, 1, 4),
+    i!(Sbc, "sbc", A, E, 1, 4),
+    i!(Sbc, "sbc", A, H, 1, 4),
+    i!(Sbc, "sbc", A, L, 1, 4),
+    i!(Sbc, "sbc", A, IndHL, 1, 8),
+    i!(Sbc, "sbc", A, A, 1, 4),
+
+    // 0xA0
+    i!(And, "and", A, B, 1, 4),
+    i!(And, "and", A, C, 1, 4),
+    i!(And, "and", A, D, 1, 4),
+    i!(And, "and", A, E, 1, 4),
+    i!(And, "and", A, H, 1, 4),
+    i!(And, "and", A, L, 1, 4),
+    i!(And, "and", A, IndHL, 1, 8),
+    i!(And, "and", A, A, 1, 4),
+    i!(Xor, "xor", A, B, 1, 4),
+    i!(Xor, "xor", A, C, 1, 4),
+    i!(Xor, "xor", A, D, 1, 4),
+    i!(Xor, "xor", A, E, 1, 4),
+    i!(Xor, "xor", A, H, 1, 4),
+    i!(Xor, "xor", A, L, 1, 4),
+    i!(Xor, "xor", A, IndHL, 1, 8),
+    i!(Xor, "xor", A, A, 1, 4),
+
+    // 0xB0
+    i!(Or, "or", A, B, 1, 4),
+    i!(Or, "or", A, C, 1, 4),
+    i!(Or, "or", A, D, 1, 4),
+    i!(Or, "or", A, E, 1, 4),
+    i!(Or, "or", A, H, 1, 4),
+    i!(Or, "or", A, L, 1, 4),
+    i!(Or, "or", A, IndHL, 1, 8),
+    i!(Or, "or", A, A, 1, 4),
+    i!(Cp, "cp", A, B, 1, 4),
+    i!(Cp, "cp", A, C, 1, 4),
+    i!(Cp, "cp", A, D, 1, 4),
+    i!(Cp, "cp", A, E, 1, 4),
+    i!(Cp, "cp", A, H, 1, 4),
+    i!(Cp, "cp", A, L, 1, 4),
+    i!(Cp, "cp", A, IndHL, 1, 8),
+    i!(Cp, "cp", A, A, 1, 4),
+
+    // 0xC0
+    i!(Ret, "ret", CondNZ, None, 1, 8, 20),
+    i!(Pop, "pop", BC, None, 1, 12),
+    i!(Jp, "jp", CondNZ, A16, 3, 12, 16),
+    i!(Jp, "jp", A16, None, 3, 16),
+    i!(Call, "call", CondNZ, A16, 3, 12, 24),
+    i!(Push, "push", BC, None, 1, 16),
+    i!(Add, "add", A, D8, 2, 8),
+    rst!(0x00),
+    i!(Ret, "ret", CondZ, None, 1, 8, 20),
+    i!(Ret, "ret", None, None, 1, 16),
+    i!(Jp, "jp", CondZ, A16, 3, 12, 16),
+    i!(Prefix, "prefix", None, None, 1, 4),
+    i!(Call, "call", CondZ, A16, 3, 12, 24),
+    i!(Call, "call", A16, None, 3, 24),
+    i!(Adc, "adc", A, D8, 2, 8),
+    rst!(0x08),
+
+    // 0xD0
+    i!(Ret, "ret", CondNC, None, 1, 8, 20),
+    i!(Pop, "pop", DE, None, 1, 12),
+    i!(Jp, "jp", CondNC, A16, 3, 12, 16),
+    ILLEGAL,
+    i!(Call, "call", CondNC, A16, 3, 12, 24),
+    i!(Push, "push", DE, None, 1, 16),
+    i!(Sub, "sub", A, D8, 2, 8),
+    rst!(0x10),
+    i!(Ret, "ret", CondC, None, 1, 8, 20),
+    i!(Reti, "reti", None, None, 1, 16),
+    i!(Jp, "jp", CondC, A16, 3, 12, 16),
+    ILLEGAL,
+    i!(Call, "call", CondC, A16, 3, 12, 24),
+    ILLEGAL,
+    i!(Sbc, "sbc", A, D8, 2, 8),
+    rst!(0x18),
+
+    // 0xE0
+    i!(Ld, "ldh", IndA8, A, 2, 12),
+    i!(Pop, "pop", HL, None, 1, 12),
+    i!(Ld, "ldh", IndC, A, 1, 8),
+    ILLEGAL,
+    ILLEGAL,
+    i!(Push, "push", HL, None, 1, 16),
+    i!(And, "and", A, D8, 2, 8),
+    rst!(0x20),
+    i!(Add, "add", SP, R8, 2, 16),
+    i!(Jp, "jp", HL, None, 1, 4),
+    i!(Ld, "ld", IndA16, A, 3, 16),
+    ILLEGAL,
+    ILLEGAL,
+    ILLEGAL,
+    i!(Xor, "xor", A, D8, 2, 8),
+    rst!(0x28),
+
+    // 0xF0
+    i!(Ld, "ldh", A, IndA8, 2, 12),
+    i!(Pop, "pop", AF, None, 1, 12),
+    i!(Ld, "ldh", A, IndC, 1, 8),
+    i!(Di, "di", None, None, 1, 4),
+    ILLEGAL,
+    i!(Push, "push", AF, None, 1, 16),
+    i!(Or, "or", A, D8, 2, 8),
+    rst!(0x30),
+    i!(LdHlSp, "ld", HL, SPR8, 2, 12),
+    i!(Ld, "ld", SP, HL, 1, 8),
+    i!(Ld, "ld", A, IndA16, 3, 16),
+    i!(Ei, "ei", None, None, 1, 4),
+    ILLEGAL,
+    ILLEGAL,
+    i!(Cp, "cp", A, D8, 2, 8),
+    rst!(0x38)
+];
+
+/// The second byte of 0xCB prefixed instructions, laid out as op (bits 7-3) and register (bits 2-0)
+pub static CB_OPCODES: [Instruction; 256] = make_cb_table();
+
+const fn make_cb_table() -> [Instruction; 256] {
+    let mut table = [ILLEGAL; 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        table[opcode] = make_cb_instruction(opcode as u8);
+        opcode += 1;
+    }
+    return table;
+}
+
+const fn make_cb_instruction(opcode: u8) -> Instruction {
+    let target = match opcode & 0x07 {
+        0 => Operand::B,
+        1 => Operand::C,
+        2 => Operand::D,
+        3 => Operand::E,
+        4 => Operand::H,
+        5 => Operand::L,
+        6 => Operand::IndHL,
+        _ => Operand::A
+    };
+    let on_hl = opcode & 0x07 == 6;
+    let bit = (opcode >> 3) & 0x07;
+
+    let (op, mnemonic) = match opcode >> 3 {
+        0 => (Op::Rlc, "rlc"),
+        1 => (Op::Rrc, "rrc"),
+        2 => (Op::Rl, "rl"),
+        3 => (Op::Rr, "rr"),
+        4 => (Op::Sla, "sla"),
+        5 => (Op::Sra, "sra"),
+        6 => (Op::Swap, "swap"),
+        7 => (Op::Srl, "srl"),
+        0x08..=0x0F => (Op::Bit, "bit"),
+        0x10..=0x17 => (Op::Res, "res"),
+        _ => (Op::Set, "set")
+    };
+
+    let cycles = match (op, on_hl) {
+        (_, false) => 8,
+        (Op::Bit, true) => 12,
+        (_, true) => 16
+    };
+
+    let (dst, src) = match op {
+        Op::Bit | Op::Res | Op::Set => (Operand::Bit(bit), target),
+        _ => (target, Operand::None)
+    };
+
+    return Instruction {
+        op: op,
+        mnemonic: mnemonic,
+        dst: dst,
+        src: src,
+        length: 2,
+        cycles: cycles,
+        branch_cycles: cycles
+    };
+}
+
+/// Look up the instruction at `addr`, following the 0xCB prefix
+pub fn decode(memory: &dyn Memory, addr: u16) -> &'static Instruction {
+    let instr = &OPCODES[memory.get(addr) as usize];
+
+    if instr.op == Op::Prefix {
+        return &CB_OPCODES[memory.get(addr.wrapping_add(1)) as usize];
+    }
+    return instr;
+}
+
+/// Immediate value following the opcode, little endian for 16 bit operands
+pub fn immediate(memory: &dyn Memory, addr: u16, instr: &Instruction) -> u16 {
+    let bytes = instr.dst.immediate_bytes() + instr.src.immediate_bytes();
+
+    return match bytes {
+        1 => memory.get(addr.wrapping_add(1)) as u16,
+        2 => ((memory.get(addr.wrapping_add(2)) as u16) << 8) + memory.get(addr.wrapping_add(1)) as u16,
+        _ => 0
+    };
+}
+
+/// RGBDS syntax for the instruction at `addr`, along with its length
+pub fn disassemble(memory: &dyn Memory, addr: u16) -> (String, u16) {
+    return disassemble_with_symbols(memory, addr, &Symbols::make_symbols(), 1);
+}
+
+/// `disassemble` with jump targets and addresses replaced by their labels,
+/// `rom_bank` is the bank mapped at 0x4000-0x7FFF
+pub fn disassemble_with_symbols(memory: &dyn Memory, addr: u16, symbols: &Symbols, rom_bank: u16) -> (String, u16) {
+    let instr = decode(memory, addr);
+
+    if instr.op == Op::Illegal {
+        return (format!("db ${:02x}", memory.get(addr)), 1);
+    }
+
+    let imm = immediate(memory, addr, instr);
+    let next_pc = addr.wrapping_add(instr.length as u16);
+
+    let mut operands = vec![];
+    for operand in [instr.dst, instr.src].iter() {
+        if *operand == Operand::None {
+            continue;
+        }
+        // rgbds leaves the implied a off of these
+        if *operand == Operand::A && instr.src != Operand::None
+            && (instr.op == Op::Sub || instr.op == Op::And || instr.op == Op::Xor || instr.op == Op::Or || instr.op == Op::Cp) {
+            continue;
+        }
+        if *operand == Operand::R8 && instr.op == Op::Add {
+            operands.push(format!("{}", imm as u8 as i8));
+            continue;
+        }
+        operands.push(format_operand(operand, imm, next_pc, symbols, rom_bank));
+    }
+
+    if operands.is_empty() {
+        return (instr.mnemonic.to_string(), instr.length as u16);
+    }
+    return (format!("{} {}", instr.mnemonic, operands.join(", ")), instr.length as u16);
+}
+
+fn format_operand(operand: &Operand, imm: u16, next_pc: u16, symbols: &Symbols, rom_bank: u16) -> String {
+    let address = |addr: u16| match symbols.label(addr, rom_bank) {
+        Some(label) => label.to_string(),
+        None => format!("${:04x}", addr)
+    };
+    return match operand {
+        Operand::None => String::new(),
+        Operand::A => "a".to_string(),
+        Operand::B => "b".to_string(),
+        Operand::C => "c".to_string(),
+        Operand::D => "d".to_string(),
+        Operand::E => "e".to_string(),
+        Operand::H => "h".to_string(),
+        Operand::L => "l".to_string(),
+        Operand::AF => "af".to_string(),
+        Operand::BC => "bc".to_string(),
+        Operand::DE => "de".to_string(),
+        Operand::HL => "hl".to_string(),
+        Operand::SP => "sp".to_string(),
+        Operand::IndBC => "[bc]".to_string(),
+        Operand::IndDE => "[de]".to_string(),
+        Operand::IndHL => "[hl]".to_string(),
+        Operand::IndHLInc => "[hl+]".to_string(),
+        Operand::IndHLDec => "[hl-]".to_string(),
+        Operand::D8 => format!("${:02x}", imm),
+        Operand::D16 | Operand::A16 => address(imm),
+        Operand::R8 => {
+            // jr shows where it lands
+            let offset = imm as u8 as i8;
+            address(next_pc.wrapping_add(offset as u16))
+        },
+        Operand::IndA16 => format!("[{}]", address(imm)),
+        Operand::IndA8 => format!("[{}]", address(0xFF00 + imm)),
+        Operand::IndC => "[c]".to_string(),
+        Operand::SPR8 => {
+            let offset = imm as u8 as i8;
+            if offset < 0 {
+                format!("sp - {}", -(offset as i16))
+            } else {
+                format!("sp + {}", offset)
+            }
+        },
+        Operand::CondNZ => "nz".to_string(),
+        Operand::CondZ => "z".to_string(),
+        Operand::CondNC => "nc".to_string(),
+        Operand::CondC => "c".to_string(),
+        Operand::Bit(bit) => format!("{}", bit),
+        Operand::Vector(vector) => format!("${:02x}", vector)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::memory;
+    use crate::engine::opcodes::{OPCODES, CB_OPCODES, Op, Operand, disassemble, disassemble_with_symbols};
+    use crate::engine::symbols::Symbols;
+
+    #[test]
+    fn test_lengths_match_operands(){
+        for (opcode, instr) in OPCODES.iter().enumerate() {
+            let expected = match instr.op {
+                Op::Stop => 2,
+                _ => 1 + instr.dst.immediate_bytes() + instr.src.immediate_bytes()
+            };
+            assert_eq!(expected, instr.length, "length of {:02x}", opcode);
+        }
+
+        for instr in CB_OPCODES.iter() {
+            assert_eq!(2, instr.length);
+        }
+    }
+
+    #[test]
+    fn test_cycles(){
+        let mut illegal = 0;
+        for (opcode, instr) in OPCODES.iter().chain(CB_OPCODES.iter()).enumerate() {
+            assert_eq!(0, instr.cycles % 4, "cycles of {:02x}", opcode);
+            assert_eq!(0, instr.branch_cycles % 4, "branch cycles of {:02x}", opcode);
+
+            let conditional = match instr.dst {
+                Operand::CondNZ | Operand::CondZ | Operand::CondNC | Operand::CondC => true,
+                _ => false
+            };
+            assert_eq!(conditional, instr.branch_cycles > instr.cycles, "branch cycles of {:02x}", opcode);
+
+            if instr.op == Op::Illegal {
+                illegal += 1;
+            }
+        }
+        assert_eq!(11, illegal);
+
+        assert_eq!(8, CB_OPCODES[0x47].cycles); // bit 0, a
+        assert_eq!(12, CB_OPCODES[0x46].cycles); // bit 0, [hl]
+        assert_eq!(16, CB_OPCODES[0x86].cycles); // res 0, [hl]
+    }
+
+    #[test]
+    fn test_disassemble(){
+        let mut rom = vec![0; 0x8000];
+        let code = [
+            0x3E, 0x42,         // ld a, $42
+            0xEA, 0x00, 0xC0,   // ld [$c000], a
+            0x18, 0xFE,         // jr $0105
+            0xE0, 0x44,         // ldh [$ff44], a
+            0xCB, 0x7E,         // bit 7, [hl]
+            0x96,               // sub [hl]
+            0xE8, 0xFE,         // add sp, -2
+            0xF8, 0x05,         // ld hl, sp + 5
+            0xD3                // illegal
+        ];
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let memory = memory::make_memory(rom);
+
+        let expected = ["ld a, $42", "ld [$c000], a", "jr $0105", "ldh [$ff44], a",
+                        "bit 7, [hl]", "sub [hl]", "add sp, -2", "ld hl, sp + 5", "db $d3"];
+
+        let mut addr = 0x0100;
+        for line in expected.iter() {
+            let (text, length) = disassemble(&*memory, addr);
+            assert_eq!(*line, text);
+            addr += length;
+        }
+    }
+
+    #[test]
+    fn test_disassemble_with_symbols(){
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0108].copy_from_slice(&[0xCD, 0x50, 0x01, 0xFA, 0x00, 0xC0, 0x18, 0xF8]);
+        let memory = memory::make_memory(rom);
+        let symbols = Symbols::parse("00:0150 Start\n00:c000 wCount\n");
+
+        assert_eq!("call Start", disassemble_with_symbols(&*memory, 0x100, &symbols, 1).0);
+        assert_eq!("ld a, [wCount]", disassemble_with_symbols(&*memory, 0x103, &symbols, 1).0);
+        assert_eq!("jr $0100", disassemble_with_symbols(&*memory, 0x106, &symbols, 1).0);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::engine::debugger::format_addr;
+use crate::engine::symbols::Symbols;
+
+/// ROM bank (None outside of ROM) and address
+pub type Location = (Option<u16>, u16);
+
+/// Clock cycles in one frame
+const CYCLES_PER_FRAME: u64 = 70224;
+/// Lines in each table of the report
+const REPORT_LINES: usize = 100;
+/// Calls that never return (stack resets, `pop` of the return address) would grow the stack forever
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub instructions: u64,
+    pub cycles: u64
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FunctionCounts {
+    pub calls: u64,
+    /// cycles spent in the subroutine itself
+    pub self_cycles: u64,
+    /// cycles spent in the subroutine and everything it called
+    pub total_cycles: u64
+}
+
+struct Frame {
+    function: Location,
+    /// SP right after the return address was pushed
+    sp: u16,
+    /// `Profiler::cycles` when the call happened
+    entered: u64
+}
+
+/// Counts instructions and cycles per (bank, PC) and per subroutine, following CALL, RST and
+/// interrupts into subroutines and RET / RETI back out of them.
+#[derive(Default)]
+pub struct Profiler {
+    pub per_pc: HashMap<Location, Counts>,
+    pub functions: HashMap<Location, FunctionCounts>,
+    /// cycles for each call stack, outermost first, for flamegraphs
+    pub stacks: HashMap<Vec<Location>, u64>,
+    pub instructions: u64,
+    pub cycles: u64,
+    /// cycles spent in HALT and STOP, also counted in `cycles`
+    pub halted_cycles: u64,
+    stack: Vec<Frame>,
+    /// the functions in `stack`, kept separately so it can be looked up in `stacks` without allocating
+    path: Vec<Location>
+}
+
+impl Profiler {
+    pub fn make_profiler() -> Profiler {
+        return Profiler::default();
+    }
+
+    /// The instruction at `location` ran and took `cycles`, call before `call` / `ret` for it
+    pub fn instruction(&mut self, location: Location, cycles: u32) {
+        let counts = self.per_pc.entry(location).or_default();
+        counts.instructions += 1;
+        counts.cycles += cycles as u64;
+        self.instructions += 1;
+        self.add_cycles(cycles as u64);
+    }
+
+    /// A CALL or RST went to `function`, `sp` is where the return address was pushed
+    pub fn call(&mut self, function: Location, sp: u16) {
+        if self.stack.len() == MAX_DEPTH {
+            self.stack.remove(0);
+            self.path.remove(0);
+        }
+        self.functions.entry(function).or_default().calls += 1;
+        self.stack.push(Frame { function: function, sp: sp, entered: self.cycles });
+        self.path.push(function);
+    }
+
+    /// An interrupt dispatch to `handler`, which took `cycles`
+    pub fn interrupt(&mut self, handler: Location, sp: u16, cycles: u32) {
+        self.call(handler, sp);
+        self.add_cycles(cycles as u64);
+    }
+
+    /// The CPU spent `cycles` waiting in HALT or STOP
+    pub fn halted(&mut self, cycles: u32) {
+        self.halted_cycles += cycles as u64;
+        self.add_cycles(cycles as u64);
+    }
+
+    /// RET or RETI with the return address at `sp`, also drops any frames whose return was skipped
+    pub fn ret(&mut self, sp: u16) {
+        while self.stack.last().map_or(false, |frame| frame.sp <= sp) {
+            let frame = self.stack.pop().unwrap();
+            self.path.pop();
+            self.leave(&frame);
+        }
+    }
+
+    fn leave(&mut self, frame: &Frame) {
+        // recursive calls are already counted by the outer call
+        if self.stack.iter().any(|outer| outer.function == frame.function) {
+            return;
+        }
+        self.functions.entry(frame.function).or_default().total_cycles += self.cycles - frame.entered;
+    }
+
+    fn add_cycles(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        if let Some(frame) = self.stack.last() {
+            self.functions.entry(frame.function).or_default().self_cycles += cycles;
+        }
+
+        match self.stacks.get_mut(&self.path[..]) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.path.clone(), cycles);
+            }
+        }
+    }
+
+    /// Per function counts, including the time spent so far in calls that haven't returned
+    pub fn function_counts(&self) -> HashMap<Location, FunctionCounts> {
+        let mut res = self.functions.clone();
+        for (depth, frame) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].iter().any(|outer| outer.function == frame.function) {
+                res.entry(frame.function).or_default().total_cycles += self.cycles - frame.entered;
+            }
+        }
+        return res;
+    }
+
+    fn name(location: Location, symbols: &Symbols) -> String {
+        let (bank, addr) = location;
+        let label = symbols.describe(addr, bank.map_or(1, |bank| std::cmp::max(1, bank)));
+        return match label {
+            Some(label) => label,
+            None => format_addr(bank, addr)
+        };
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        return 100.0 * cycles as f64 / std::cmp::max(1, self.cycles) as f64;
+    }
+
+    /// Subroutines and instructions sorted by the cycles spent in them
+    pub fn write_report(&self, out: &mut dyn Write, symbols: &Symbols) -> io::Result<()> {
+        writeln!(out, "{} instructions, {} cycles ({:.1} frames), {:.1}% halted\n", self.instructions, self.cycles,
+                 self.cycles as f64 / CYCLES_PER_FRAME as f64, self.percent(self.halted_cycles))?;
+
+        let mut functions: Vec<(Location, FunctionCounts)> = self.function_counts().into_iter().collect();
+        functions.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(&b.0)));
+        writeln!(out, "Subroutines")?;
+        writeln!(out, "{:>12} {:>6} {:>12} {:>6} {:>8}  name", "total", "%", "self", "%", "calls")?;
+        for (location, counts) in functions.iter().take(REPORT_LINES) {
+            writeln!(out, "{:>12} {:>5.1}% {:>12} {:>5.1}% {:>8}  {}",
+                     counts.total_cycles, self.percent(counts.total_cycles), counts.self_cycles,
+                     self.percent(counts.self_cycles), counts.calls, Profiler::name(*location, symbols))?;
+        }
+
+        let mut per_pc: Vec<(&Location, &Counts)> = self.per_pc.iter().collect();
+        per_pc.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        writeln!(out, "\nInstructions")?;
+        writeln!(out, "{:>12} {:>6} {:>12}  location", "cycles", "%", "count")?;
+        for (location, counts) in per_pc.iter().take(REPORT_LINES) {
+            let (bank, addr) = **location;
+            let label = match symbols.describe(addr, bank.map_or(1, |bank| std::cmp::max(1, bank))) {
+                Some(label) => format!(" ({})", label),
+                None => String::new()
+            };
+            writeln!(out, "{:>12} {:>5.1}% {:>12}  {}{}", counts.cycles, self.percent(counts.cycles),
+                     counts.instructions, format_addr(bank, addr), label)?;
+        }
+        return Ok(());
+    }
+
+    /// One `main;caller;callee cycles` line per call stack, the input flamegraph.pl and inferno take
+    pub fn write_folded(&self, out: &mut dyn Write, symbols: &Symbols) -> io::Result<()> {
+        let mut lines: Vec<String> = self.stacks.iter().map(|(path, cycles)| {
+            let mut names = vec!["main".to_string()];
+            names.extend(path.iter().map(|location| Profiler::name(*location, symbols)));
+            format!("{} {}", names.join(";"), cycles)
+        }).collect();
+        lines.sort();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::profiler::Profiler;
+    use crate::engine::symbols::Symbols;
+
+    #[test]
+    fn test_calls_and_returns() {
+        let mut profiler = Profiler::make_profiler();
+        profiler.instruction((Some(0), 0x150), 24);     // call Update
+        profiler.call((Some(0), 0x200), 0xFFFC);
+        profiler.instruction((Some(0), 0x200), 8);
+        profiler.instruction((Some(0), 0x201), 24);     // call Draw
+        profiler.call((Some(1), 0x4000), 0xFFFA);
+        profiler.instruction((Some(1), 0x4000), 4);
+        profiler.interrupt((Some(0), 0x40), 0xFFF8, 20);
+        profiler.instruction((Some(0), 0x40), 16);      // reti
+        profiler.ret(0xFFF8);
+        profiler.instruction((Some(1), 0x4001), 16);    // ret
+        profiler.ret(0xFFFA);
+        profiler.instruction((Some(0), 0x204), 16);     // ret
+        profiler.ret(0xFFFC);
+        profiler.instruction((Some(0), 0x153), 4);
+
+        assert_eq!(132, profiler.cycles);
+        assert_eq!(8, profiler.instructions);
+
+        let functions = profiler.function_counts();
+        let update = functions[&(Some(0), 0x200)];
+        assert_eq!((1, 48, 104), (update.calls, update.self_cycles, update.total_cycles));
+        let draw = functions[&(Some(1), 0x4000)];
+        assert_eq!((1, 20, 56), (draw.calls, draw.self_cycles, draw.total_cycles));
+        let vblank = functions[&(Some(0), 0x40)];
+        assert_eq!((1, 36, 36), (vblank.calls, vblank.self_cycles, vblank.total_cycles));
+
+        let symbols = Symbols::parse("00:0200 Update\n01:4000 Draw\n");
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded, &symbols).unwrap();
+        assert_eq!("main 28\nmain;Update 48\nmain;Update;Draw 20\nmain;Update;Draw;00:0040 36\n",
+                   String::from_utf8(folded).unwrap());
+    }
+
+    #[test]
+    fn test_skipped_return() {
+        let mut profiler = Profiler::make_profiler();
+        profiler.call((Some(0), 0x200), 0xFFFC);
+        profiler.call((Some(0), 0x300), 0xFFFA);
+        // 0x300 popped its return address and jumped, then 0x200 returns
+        profiler.instruction((Some(0), 0x300), 4);
+        profiler.ret(0xFFFC);
+        profiler.instruction((Some(0), 0x153), 4);
+        assert_eq!(4, profiler.stacks[&vec![]]);
+        assert_eq!(4, profiler.function_counts()[&(Some(0), 0x300)].total_cycles);
+        assert_eq!(4, profiler.function_counts()[&(Some(0), 0x200)].total_cycles);
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use crate::engine::state::{StateWriter, StateReader};
 
 #[derive(Debug)]
 pub struct Registers {
@@ -22,18 +23,36 @@ impl Registers {
             pc: 0x100,
             sp: 0xFFFE,
 
+            // what the DMG boot rom leaves behind, games and gameboy-doctor's logs start from it
             a: 0x01,
             b: 0x00,
-            c: 0x14,
+            c: 0x13,
             d: 0x00,
-            e: 0x00,
-            f: 0x00,
+            e: 0xD8,
+            f: 0xB0,
 
-            h: 0xc0,
-            l: 0x60
+            h: 0x01,
+            l: 0x4D
         };
     }
 
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.u16(self.pc);
+        out.u16(self.sp);
+        for reg in [self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l].iter() {
+            out.u8(*reg);
+        }
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.pc = input.u16()?;
+        self.sp = input.u16()?;
+        for reg in [&mut self.a, &mut self.b, &mut self.c, &mut self.d, &mut self.e, &mut self.f, &mut self.h, &mut self.l].iter_mut() {
+            **reg = input.u8()?;
+        }
+        return Ok(());
+    }
+
     pub fn make_flags(zero: bool, subtract: bool, half_cary: bool, cary: bool) -> u16 {
         let mut res = 0;
         if zero {
@@ -182,6 +201,17 @@ pub enum RegisterNames {
 mod tests {
     use crate::engine::registers::Registers;
     use crate::engine::registers::RegisterNames;
+
+    #[test]
+    fn test_power_on_state() {
+        let mut reg = Registers::make_registers();
+        assert_eq!(0x100, reg.pc);
+        assert_eq!(0xFFFE, reg.sp);
+        assert_eq!([0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D], [reg.a, reg.f, reg.b, reg.c, reg.d, reg.e, reg.h, reg.l]);
+        assert!(reg.is_zero_flag());
+        assert!(reg.is_cary_flag());
+    }
+
     #[test]
     fn test_flags() {
         let mut reg = Registers::make_registers();
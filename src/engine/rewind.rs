@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+
+/// Equal bytes it takes to end a changed run, shorter gaps are cheaper to copy than to skip
+const MIN_SKIP: usize = 4;
+
+/// Recent snapshots to step back through.
+///
+/// Only the newest snapshot is kept whole, each older one is stored as the changes that turn
+/// the snapshot after it back into it. Most of the machine doesn't change from one frame to
+/// the next, so that's usually a few hundred bytes instead of the full state.
+pub struct Rewind {
+    /// `GPU::frames` and the full snapshot
+    latest: Option<(u64, Vec<u8>)>,
+    /// older snapshots, oldest first
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    /// most snapshots kept, the oldest are dropped
+    pub capacity: usize,
+    /// frames between snapshots
+    pub interval: u64,
+    /// `GPU::frames` when the next snapshot is due
+    pub(crate) next_frame: u64
+}
+
+impl Rewind {
+    pub fn make_rewind(interval: u64, capacity: usize) -> Rewind {
+        return Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity: capacity,
+            interval: interval,
+            next_frame: 0
+        };
+    }
+
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((latest_frame, latest)) = self.latest.take() {
+            self.deltas.push_back((latest_frame, delta(&state, &latest)));
+            while self.deltas.len() + 1 > self.capacity && !self.deltas.is_empty() {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some((frame, state));
+    }
+
+    /// Takes the newest snapshot off the buffer, with the frame it was taken at
+    pub fn pop(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (frame, state) = self.latest.take()?;
+        if let Some((older_frame, changes)) = self.deltas.pop_back() {
+            self.latest = Some((older_frame, apply_delta(&state, &changes)));
+        }
+        return Some((frame, state));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.deltas.len() + self.latest.iter().count();
+    }
+
+    /// Bytes held in snapshots and deltas
+    pub fn size(&self) -> usize {
+        return self.latest.as_ref().map_or(0, |(_, state)| state.len())
+            + self.deltas.iter().map(|(_, changes)| changes.len()).sum::<usize>();
+    }
+}
+
+/// Changes to turn `from` into `to`: `to`'s length, then runs of bytes to skip and bytes to
+/// copy, all lengths as LEB128
+pub fn delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut res = vec![];
+    write_length(&mut res, to.len());
+    let same = |i: usize| from.get(i) == Some(&to[i]);
+
+    let mut i = 0;
+    while i < to.len() {
+        let start = i;
+        while i < to.len() && same(i) {
+            i += 1;
+        }
+        if i == to.len() {
+            break;
+        }
+        let skip = i - start;
+        let changed = i;
+        let mut equal_run = 0;
+        while i < to.len() && equal_run < MIN_SKIP {
+            equal_run = if same(i) { equal_run + 1 } else { 0 };
+            i += 1;
+        }
+        i -= equal_run;
+        write_length(&mut res, skip);
+        write_length(&mut res, i - changed);
+        res.extend_from_slice(&to[changed..i]);
+    }
+    return res;
+}
+
+/// `to` from the `from` that was given to `delta`
+pub fn apply_delta(from: &[u8], changes: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_length(changes, &mut pos);
+    let mut res = from.to_vec();
+    res.resize(len, 0);
+
+    let mut i = 0;
+    while pos < changes.len() {
+        i += read_length(changes, &mut pos);
+        let run = read_length(changes, &mut pos);
+        res[i..i + run].copy_from_slice(&changes[pos..pos + run]);
+        pos += run;
+        i += run;
+    }
+    return res;
+}
+
+fn write_length(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_length(data: &[u8], pos: &mut usize) -> usize {
+    let mut res = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        res |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return res;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::rewind::{Rewind, delta, apply_delta};
+
+    #[test]
+    fn test_delta() {
+        let from: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut to = from.clone();
+        to[3] = 0;
+        to[5] = 0;
+        to[900] = 1;
+        let changes = delta(&from, &to);
+        assert!(changes.len() < 16);
+        assert_eq!(to, apply_delta(&from, &changes));
+
+        assert_eq!(vec![1, 2], apply_delta(&from, &delta(&from, &[1, 2])));
+        assert_eq!(from, apply_delta(&[1, 2], &delta(&[1, 2], &from)));
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut rewind = Rewind::make_rewind(1, 3);
+        let snapshot = |frame: u64| {
+            let mut state = vec![0; 100];
+            state[50] = frame as u8;
+            return state;
+        };
+        for frame in 0..5 {
+            rewind.push(frame, snapshot(frame));
+        }
+        assert_eq!(3, rewind.len());
+        assert!(rewind.size() < 120);
+
+        assert_eq!(Some((4, snapshot(4))), rewind.pop());
+        assert_eq!(Some((3, snapshot(3))), rewind.pop());
+        assert_eq!(Some((2, snapshot(2))), rewind.pop());
+        assert_eq!(None, rewind.pop());
+    }
+}
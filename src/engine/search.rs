@@ -0,0 +1,141 @@
+use crate::engine::memory::Memory;
+use crate::engine::cheats::CheatCode;
+
+/// Cartridge RAM, WRAM and HRAM, where game variables live
+pub const REGIONS: [(u16, u16); 3] = [(0xA000, 0xBFFF), (0xC000, 0xDFFF), (0xFF80, 0xFFFE)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Byte,
+    /// little endian, like the CPU's 16 bit loads
+    Word
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// same as last time
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u16)
+}
+
+impl Filter {
+    /// `eq`, `changed`, `inc`, `dec` or a hex value
+    pub fn parse(text: &str) -> Result<Filter, String> {
+        return match text {
+            "eq" | "equal" | "same" => Ok(Filter::Equal),
+            "ne" | "changed" => Ok(Filter::Changed),
+            "inc" | "increased" => Ok(Filter::Increased),
+            "dec" | "decreased" => Ok(Filter::Decreased),
+            _ => u16::from_str_radix(text.trim_start_matches('$').trim_start_matches("0x"), 16)
+                .map(Filter::Value)
+                .map_err(|_| format!("{} isn't eq, changed, inc, dec or a value", text))
+        };
+    }
+
+    fn keeps(&self, previous: u16, current: u16) -> bool {
+        return match self {
+            Filter::Equal => current == previous,
+            Filter::Changed => current != previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::Value(value) => current == *value
+        };
+    }
+}
+
+/// Narrows down where a game keeps a value: snapshot all of RAM, play a bit, keep the
+/// addresses whose value changed the way the game's did, repeat.
+pub struct RamSearch {
+    pub width: Width,
+    /// addresses still in the running and their values at the last filter
+    pub candidates: Vec<(u16, u16)>
+}
+
+impl RamSearch {
+    /// Starts a search with every address in `REGIONS` as a candidate
+    pub fn make_ram_search(memory: &dyn Memory, width: Width) -> RamSearch {
+        let mut candidates = vec![];
+        for (start, end) in REGIONS.iter() {
+            let last = if width == Width::Word { end - 1 } else { *end };
+            for addr in *start..=last {
+                candidates.push((addr, 0));
+            }
+        }
+        let mut res = RamSearch { width: width, candidates: candidates };
+        for candidate in res.candidates.iter_mut() {
+            candidate.1 = RamSearch::read(memory, width, candidate.0);
+        }
+        return res;
+    }
+
+    fn read(memory: &dyn Memory, width: Width, addr: u16) -> u16 {
+        return match width {
+            Width::Byte => memory.get(addr) as u16,
+            Width::Word => memory.get(addr) as u16 | ((memory.get(addr + 1) as u16) << 8)
+        };
+    }
+
+    /// Drops the candidates `filter` doesn't keep, returns how many are left
+    pub fn filter(&mut self, memory: &dyn Memory, filter: Filter) -> usize {
+        let width = self.width;
+        let mut kept = Vec::with_capacity(self.candidates.len());
+        for (addr, previous) in self.candidates.iter() {
+            let current = RamSearch::read(memory, width, *addr);
+            if filter.keeps(*previous, current) {
+                kept.push((*addr, current));
+            }
+        }
+        self.candidates = kept;
+        return self.candidates.len();
+    }
+
+    /// GameShark codes that hold `addr` at `value`, two for a 16 bit value
+    pub fn freeze(&self, addr: u16, value: u16) -> Vec<CheatCode> {
+        let mut res = vec![CheatCode::GameShark { addr: addr, value: value as u8 }];
+        if self.width == Width::Word {
+            res.push(CheatCode::GameShark { addr: addr.wrapping_add(1), value: (value >> 8) as u8 });
+        }
+        return res;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::memory;
+    use crate::engine::search::{RamSearch, Filter, Width};
+    use crate::engine::cheats::CheatCode;
+
+    #[test]
+    fn test_byte_search() {
+        let mut memory = memory::make_memory(vec![0; 0x8000]);
+        memory.set(0xC123, 3);  // lives
+        memory.set(0xFF90, 3);  // something else
+        let mut search = RamSearch::make_ram_search(&*memory, Width::Byte);
+        assert_eq!(0x2000 + 0x2000 + 0x7F, search.candidates.len());
+
+        memory.set(0xC123, 2);
+        assert_eq!(1, search.filter(&*memory, Filter::Decreased));
+        assert_eq!(vec![(0xC123, 2)], search.candidates);
+
+        assert_eq!(1, search.filter(&*memory, Filter::Equal));
+        assert_eq!(0, search.filter(&*memory, Filter::Value(3)));
+    }
+
+    #[test]
+    fn test_word_search() {
+        let mut memory = memory::make_memory(vec![0; 0x8000]);
+        memory.set(0xD000, 0xFF);   // score 0x00FF
+        let mut search = RamSearch::make_ram_search(&*memory, Width::Word);
+        memory.set(0xD000, 0x00);
+        memory.set(0xD001, 0x01);   // score 0x0100
+        search.filter(&*memory, Filter::Increased);
+        assert_eq!(vec![(0xD000, 0x0100), (0xD001, 0x0001)], search.candidates);
+        assert_eq!(1, search.filter(&*memory, Filter::parse("100").unwrap()));
+
+        assert_eq!(vec![CheatCode::GameShark { addr: 0xD000, value: 0x0F }, CheatCode::GameShark { addr: 0xD001, value: 0x27 }],
+                   search.freeze(0xD000, 9999));
+    }
+}
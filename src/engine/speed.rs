@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+/// How long the real hardware takes for a frame, 70224 clocks at 4.194304 MHz
+pub const FRAME_TIME: Duration = Duration::from_nanos(16_742_706);
+
+/// How fast `Engine::run` goes: paused, one frame at a time, real speed, sped up by the turbo
+/// multiplier or as fast as the host can with only some frames drawn.
+#[derive(Debug)]
+pub struct Speed {
+    pub paused: bool,
+    /// run until the next frame is done, then pause again
+    pub advance: bool,
+    /// the fast forward key is held
+    pub fast_forward: bool,
+    pub turbo: bool,
+    /// times normal speed while turbo is on
+    pub turbo_multiplier: f64,
+    /// never wait, like holding fast forward all the time
+    pub unthrottled: bool,
+    /// draw one frame in this many while fast forwarding or unthrottled
+    pub draw_every: u64,
+    /// when the frame that just finished was due to end
+    frame_due: Option<Instant>
+}
+
+impl Speed {
+    pub fn make_speed() -> Speed {
+        return Speed {
+            paused: false,
+            advance: false,
+            fast_forward: false,
+            turbo: false,
+            turbo_multiplier: 2.0,
+            unthrottled: false,
+            draw_every: 4,
+            frame_due: None
+        };
+    }
+
+    /// Times normal speed, None when running as fast as possible
+    pub fn multiplier(&self) -> Option<f64> {
+        if self.fast_forward || self.unthrottled {
+            return None;
+        }
+        return Some(if self.turbo { self.turbo_multiplier } else { 1.0 });
+    }
+
+    /// Paused and not advancing a frame, the CPU shouldn't run
+    pub fn is_stopped(&self) -> bool {
+        return self.paused && !self.advance;
+    }
+
+    /// Whether frame number `frame` gets shown
+    pub fn should_draw(&self, frame: u64) -> bool {
+        return self.multiplier().is_some() || frame % self.draw_every.max(1) == 0;
+    }
+
+    /// Call when a frame is finished, returns how long to wait to keep to the speed
+    pub fn end_frame(&mut self, now: Instant) -> Duration {
+        self.advance = false;
+        let frame_time = match self.multiplier() {
+            Some(multiplier) => FRAME_TIME.div_f64(multiplier),
+            None => {
+                self.frame_due = None;
+                return Duration::from_secs(0);
+            }
+        };
+        let due = match self.frame_due {
+            // more than a few frames behind, don't try to catch up
+            Some(due) if now < due + frame_time * 4 => due + frame_time,
+            _ => now + frame_time
+        };
+        self.frame_due = Some(due);
+        return due.saturating_duration_since(now);
+    }
+
+    /// Forgets the frame timing, for after a pause
+    pub fn reset_timing(&mut self) {
+        self.frame_due = None;
+    }
+
+    /// For the window title, empty at normal speed
+    pub fn describe(&self) -> String {
+        if self.paused {
+            return "Paused".to_string();
+        }
+        return match self.multiplier() {
+            None => "Fast forward".to_string(),
+            Some(multiplier) if multiplier != 1.0 => format!("Turbo {}x", multiplier),
+            _ => String::new()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::engine::speed::{Speed, FRAME_TIME};
+
+    #[test]
+    fn test_frame_pacing() {
+        let mut speed = Speed::make_speed();
+        let start = Instant::now();
+        assert_eq!(FRAME_TIME, speed.end_frame(start));
+        // the frame took 5ms to emulate
+        assert_eq!(FRAME_TIME - Duration::from_millis(5), speed.end_frame(start + FRAME_TIME + Duration::from_millis(5)));
+        // fell far behind, start again from now
+        assert_eq!(FRAME_TIME, speed.end_frame(start + Duration::from_secs(1)));
+
+        speed.turbo = true;
+        speed.reset_timing();
+        assert_eq!(FRAME_TIME / 2, speed.end_frame(start));
+        assert_eq!("Turbo 2x", speed.describe());
+
+        speed.fast_forward = true;
+        assert_eq!(Duration::from_secs(0), speed.end_frame(start));
+        assert!(speed.should_draw(8));
+        assert!(!speed.should_draw(9));
+    }
+
+    #[test]
+    fn test_frame_advance() {
+        let mut speed = Speed::make_speed();
+        speed.paused = true;
+        assert!(speed.is_stopped());
+        speed.advance = true;
+        assert!(!speed.is_stopped());
+        speed.end_frame(Instant::now());
+        assert!(speed.is_stopped());
+        assert_eq!("Paused", speed.describe());
+    }
+}
@@ -0,0 +1,129 @@
+/// Builds a snapshot of the whole machine, everything but the ROM, as one byte string.
+///
+/// Each part of the engine writes its fields in a fixed order with `save_state` and reads
+/// them back in the same order with `load_state`, so a snapshot only loads into an engine
+/// built from the same ROM.
+pub struct StateWriter {
+    pub data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn make_state_writer() -> StateWriter {
+        return StateWriter { data: vec![] };
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    /// Length prefixed
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn make_state_reader(data: &'a [u8]) -> StateReader<'a> {
+        return StateReader { data: data, pos: 0 };
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let res = self.data.get(self.pos..self.pos + len).ok_or("Snapshot is cut short")?;
+        self.pos += len;
+        return Ok(res);
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        return Ok(self.u8()? != 0);
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        return Ok(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        return Ok(low | (high << 32));
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        return Ok(f32::from_bits(self.u32()?));
+    }
+
+    /// Fills `out` from `StateWriter::bytes`, which has to have written the same length
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let len = self.u32()? as usize;
+        if len != out.len() {
+            return Err(format!("Snapshot has {} bytes where {} were expected", len, out.len()));
+        }
+        out.copy_from_slice(self.take(len)?);
+        return Ok(());
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.pos == self.data.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::state::{StateWriter, StateReader};
+
+    #[test]
+    fn test_round_trip() {
+        let mut out = StateWriter::make_state_writer();
+        out.u8(1);
+        out.bool(true);
+        out.u16(0x1234);
+        out.u64(0x1_0000_0002);
+        out.f32(1.5);
+        out.bytes(&[9, 8, 7]);
+
+        let mut input = StateReader::make_state_reader(&out.data);
+        assert_eq!(Ok(1), input.u8());
+        assert_eq!(Ok(true), input.bool());
+        assert_eq!(Ok(0x1234), input.u16());
+        assert_eq!(Ok(0x1_0000_0002), input.u64());
+        assert_eq!(Ok(1.5), input.f32());
+        let mut bytes = [0; 2];
+        assert!(input.bytes_into(&mut bytes).is_err());
+        assert!(!input.is_done());
+    }
+}
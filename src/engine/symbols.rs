@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Labels from an RGBDS / no$gmb `.sym` file, lines of `bank:address label`
+#[derive(Debug, Default)]
+pub struct Symbols {
+    by_name: HashMap<String, (u16, u16)>,
+    by_addr: BTreeMap<(u16, u16), String>
+}
+
+/// Start of the memory area `addr` is in, labels don't reach past these
+fn region_start(addr: u16) -> u16 {
+    return match addr {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xCFFF => 0xC000,
+        0xD000..=0xDFFF => 0xD000,
+        0xE000..=0xFF7F => 0xE000,
+        _ => 0xFF80
+    };
+}
+
+impl Symbols {
+    pub fn make_symbols() -> Symbols {
+        return Symbols::default();
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Symbols> {
+        return Ok(Symbols::parse(&fs::read_to_string(path)?));
+    }
+
+    pub fn parse(text: &str) -> Symbols {
+        let mut res = Symbols::make_symbols();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let mut parts = line.split_whitespace();
+            let (location, name) = match (parts.next(), parts.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => continue
+            };
+            let mut location = location.split(':');
+            let bank = location.next().and_then(|b| u16::from_str_radix(b, 16).ok());
+            let addr = location.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+            if let (Some(bank), Some(addr)) = (bank, addr) {
+                res.add(bank, addr, name);
+            }
+        }
+        return res;
+    }
+
+    pub fn add(&mut self, bank: u16, addr: u16, name: &str) {
+        self.by_name.insert(name.to_string(), (bank, addr));
+        // keep the first label when several share an address
+        self.by_addr.entry((bank, addr)).or_insert_with(|| name.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.by_name.is_empty();
+    }
+
+    /// Bank and address of a label
+    pub fn lookup(&self, name: &str) -> Option<(u16, u16)> {
+        return self.by_name.get(name).cloned();
+    }
+
+    /// Every label as (bank, address, name), in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u16, &str)> {
+        return self.by_addr.iter().map(|((bank, addr), name)| (*bank, *addr, name.as_str()));
+    }
+
+    /// Bank to look labels up in, `rom_bank` is what's mapped at 0x4000-0x7FFF.
+    /// None outside of ROM, RAM labels match whatever bank the file gives them.
+    fn bank_for(addr: u16, rom_bank: u16) -> Option<u16> {
+        return match addr {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => Some(rom_bank),
+            _ => None
+        };
+    }
+
+    /// Label exactly at `addr`
+    pub fn label(&self, addr: u16, rom_bank: u16) -> Option<&str> {
+        return match Symbols::bank_for(addr, rom_bank) {
+            Some(bank) => self.by_addr.get(&(bank, addr)).map(|s| s.as_str()),
+            None => self.by_addr.iter().find(|((_, a), _)| *a == addr).map(|(_, s)| s.as_str())
+        };
+    }
+
+    /// `Label` or `Label+offset` for the closest label at or before `addr` in the same memory area
+    pub fn describe(&self, addr: u16, rom_bank: u16) -> Option<String> {
+        let banks: Vec<u16> = match Symbols::bank_for(addr, rom_bank) {
+            Some(bank) => vec![bank],
+            None => vec![0, 1]
+        };
+        let start = region_start(addr);
+
+        let mut best: Option<(u16, &String)> = None;
+        for bank in banks {
+            if let Some(((_, label_addr), name)) = self.by_addr.range((bank, start)..=(bank, addr)).next_back() {
+                if best.map_or(true, |(a, _)| *label_addr > a) {
+                    best = Some((*label_addr, name));
+                }
+            }
+        }
+        return best.map(|(label_addr, name)| {
+            if label_addr == addr {
+                name.clone()
+            } else {
+                format!("{}+{}", name, addr - label_addr)
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::symbols::Symbols;
+
+    const SYM: &str = "; File generated by rgblink\n\
+        00:0150 Start\n\
+        00:0160 Main.loop\n\
+        02:4000 LevelData\n\
+        03:4000 Music\n\
+        00:c000 wPlayerX\n";
+
+    #[test]
+    fn test_lookup() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(Some((0, 0x160)), symbols.lookup("Main.loop"));
+        assert_eq!(Some((3, 0x4000)), symbols.lookup("Music"));
+        assert_eq!(None, symbols.lookup("Nope"));
+    }
+
+    #[test]
+    fn test_describe() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(Some("Main.loop"), symbols.label(0x160, 1));
+        assert_eq!(Some("Main.loop+5".to_string()), symbols.describe(0x165, 1));
+        assert_eq!(Some("Music+16".to_string()), symbols.describe(0x4010, 3));
+        assert_eq!(Some("LevelData".to_string()), symbols.describe(0x4000, 2));
+        assert_eq!(None, symbols.describe(0x4000, 1));
+        assert_eq!(Some("wPlayerX+1".to_string()), symbols.describe(0xC001, 1));
+        assert_eq!(None, symbols.describe(0x100, 1));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::engine::engine::Engine;
+use crate::engine::interrupts::CpuState;
+
+const LD_B_B: u8 = 0x40;
+/// B/C/D/E/H/L at `LD B,B` when a mooneye test passes, or fails
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+/// At 0xA001 once a blargg test has started writing its result to cartridge RAM
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+/// At 0xA000 while that test is still running
+const BLARGG_RUNNING: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestResult {
+    Passed,
+    /// with why, or what the ROM printed
+    Failed(String),
+    /// the ROM didn't finish in time
+    Timeout
+}
+
+/// Watches a test ROM for its result: mooneye's Fibonacci registers at `LD B,B`, or blargg's
+/// "Passed" / "Failed" on the serial port or result code in cartridge RAM.
+///
+/// There's no serial port to plug into, so the watcher plays the other end of the cable:
+/// each byte the ROM sends is taken and the transfer marked done.
+pub struct TestWatcher {
+    /// everything printed on the serial port
+    pub serial: String
+}
+
+impl TestWatcher {
+    pub fn make_test_watcher() -> TestWatcher {
+        return TestWatcher { serial: String::new() };
+    }
+
+    /// Looks for a result before the next instruction, None while the ROM is still going
+    pub fn check(&mut self, eng: &mut Engine) -> Option<TestResult> {
+        if eng.interrupts.state == CpuState::Locked {
+            return Some(TestResult::Failed("CPU locked up".to_string()));
+        }
+
+        if eng.memory.get(0xFF02) == 0x81 {
+            self.serial.push(eng.memory.get(0xFF01) as char);
+            eng.memory.set(0xFF02, 0x01);
+            if self.serial.contains("Passed") {
+                return Some(TestResult::Passed);
+            }
+            if self.serial.contains("Failed") {
+                return Some(TestResult::Failed(self.serial.trim().to_string()));
+            }
+        }
+
+        if eng.interrupts.state == CpuState::Running && eng.memory.get(eng.registers.pc) == LD_B_B {
+            let r = &eng.registers;
+            let regs = [r.b, r.c, r.d, r.e, r.h, r.l];
+            // other tests run LD B,B too, only these two mean anything
+            if regs == MOONEYE_PASS {
+                return Some(TestResult::Passed);
+            }
+            if regs == MOONEYE_FAIL {
+                return Some(TestResult::Failed("mooneye failure registers".to_string()));
+            }
+        }
+
+        let signature = [eng.memory.get(0xA001), eng.memory.get(0xA002), eng.memory.get(0xA003)];
+        let status = eng.memory.get(0xA000);
+        if signature == BLARGG_SIGNATURE && status != BLARGG_RUNNING {
+            let text: String = (0xA004..0xC000u16).map(|addr| eng.memory.get(addr)).take_while(|b| *b != 0)
+                .map(|b| b as char).collect();
+            return Some(if status == 0 { TestResult::Passed } else {
+                TestResult::Failed(format!("result code {}: {}", status, text.trim()))
+            });
+        }
+        return None;
+    }
+
+    /// Runs `eng` until the ROM reports a result or `frames` frames go by
+    pub fn run(&mut self, eng: &mut Engine, frames: u64) -> TestResult {
+        let target = eng.gpu.frames + frames;
+        while eng.gpu.frames < target {
+            if let Some(result) = self.check(eng) {
+                return result;
+            }
+            eng.run_limited(1);
+        }
+        return TestResult::Timeout;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::make_engine;
+    use crate::engine::testrom::{TestWatcher, TestResult};
+
+    #[test]
+    fn test_mooneye_registers() {
+        let mut rom = vec![0; 0x8000];
+        // ld b,3; ld c,5; ld d,8; ld e,13; ld h,21; ld l,34; ld b,b
+        rom[0x0100..0x010D].copy_from_slice(&[0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40]);
+        let mut eng = make_engine(rom.clone());
+        assert_eq!(TestResult::Passed, TestWatcher::make_test_watcher().run(&mut eng, 10));
+
+        rom[0x0101] = 4;
+        let mut eng = make_engine(rom);
+        assert_eq!(TestResult::Timeout, TestWatcher::make_test_watcher().run(&mut eng, 10));
+    }
+
+    #[test]
+    fn test_serial_output() {
+        let mut rom = vec![0; 0x8000];
+        let mut code = vec![];
+        for c in b"Failed".iter() {
+            // ld a,c; ldh (01),a; ld a,81; ldh (02),a
+            code.extend_from_slice(&[0x3E, *c, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+        }
+        code.extend_from_slice(&[0x18, 0xFE]);
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let mut eng = make_engine(rom);
+        let mut watcher = TestWatcher::make_test_watcher();
+        assert_eq!(TestResult::Failed("Failed".to_string()), watcher.run(&mut eng, 10));
+        assert_eq!("Failed", watcher.serial);
+    }
+}
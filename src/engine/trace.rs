@@ -0,0 +1,73 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::engine::memory::Memory;
+use crate::engine::registers::Registers;
+use crate::engine::symbols::Symbols;
+
+/// Writes one line per executed instruction in gameboy-doctor's format:
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+/// followed by ` ; Label+offset` for PC when symbols are loaded.
+pub struct Tracer {
+    out: BufWriter<fs::File>,
+    /// LY (0xFF44) reads as 0x90 for the CPU, which the reference logs from gameboy-doctor expect
+    pub stub_ly: bool
+}
+
+impl Tracer {
+    pub fn make_tracer(path: &Path, stub_ly: bool) -> std::io::Result<Tracer> {
+        return Ok(Tracer {
+            out: BufWriter::new(fs::File::create(path)?),
+            stub_ly: stub_ly
+        });
+    }
+
+    /// Logs the state right before the instruction at PC runs
+    pub fn log(&mut self, registers: &Registers, memory: &dyn Memory, symbols: &Symbols) {
+        let pc = registers.pc;
+        // a full disk isn't worth stopping the game for
+        let _ = write!(self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.a, registers.f, registers.b, registers.c, registers.d, registers.e, registers.h, registers.l,
+            registers.sp, pc,
+            memory.get(pc), memory.get(pc.wrapping_add(1)), memory.get(pc.wrapping_add(2)), memory.get(pc.wrapping_add(3)));
+        if let Some(label) = symbols.describe(pc, memory.rom_bank()) {
+            let _ = write!(self.out, " ; {}", label);
+        }
+        let _ = writeln!(self.out);
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::engine::memory::{FlatMemory, Memory};
+    use crate::engine::registers::Registers;
+    use crate::engine::trace::Tracer;
+    use crate::engine::symbols::Symbols;
+
+    #[test]
+    fn test_doctor_format() {
+        let path = std::env::temp_dir().join(format!("rustboy_trace_{}.log", std::process::id()));
+        let mut memory = FlatMemory::make_memory();
+        memory.set(0x100, 0x00);
+        memory.set(0x101, 0xC3);
+        memory.set(0x102, 0x13);
+        memory.set(0x103, 0x02);
+
+        let mut tracer = Tracer::make_tracer(&path, false).unwrap();
+        tracer.log(&Registers::make_registers(), &memory, &Symbols::make_symbols());
+        tracer.log(&Registers::make_registers(), &memory, &Symbols::parse("00:00FE Entry"));
+        tracer.flush();
+
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!("A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02\n\
+                    A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02 ; Entry+2\n", log);
+    }
+}
@@ -2,60 +2,673 @@ use std::env;
 use std::io;
 use std::io::prelude::*;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
 
-extern crate sdl2;
+use rustboy::engine;
+use rustboy::engine::engine::{Engine, EngineEvent};
+use rustboy::engine::trace::Tracer;
+use rustboy::engine::debugger::Debugger;
+use rustboy::engine::gdb::GdbStub;
+use rustboy::engine::symbols::Symbols;
+use rustboy::engine::disasm;
+use rustboy::engine::profiler::Profiler;
+use rustboy::engine::cdl;
+use rustboy::engine::cdl::CodeDataLog;
+use rustboy::engine::cheats::Cheats;
+use rustboy::engine::input;
+use rustboy::engine::movie;
+use rustboy::engine::movie::Movie;
+use rustboy::engine::rewind::Rewind;
+use rustboy::engine::config;
+use rustboy::engine::config::Config;
+use rustboy::engine::gpu;
+use rustboy::engine::gpu::Palette;
+use rustboy::engine::header::RomHeader;
+use rustboy::engine::testrom::{TestWatcher, TestResult};
 
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::time::Duration;
+/// Ran, or a test ROM passed
+const EXIT_OK: i32 = 0;
+/// something went wrong, or a test ROM failed
+const EXIT_FAILURE: i32 = 1;
+/// the command line didn't make sense
+const EXIT_USAGE: i32 = 2;
+/// a test ROM didn't finish within `--frames`
+const EXIT_TIMEOUT: i32 = 3;
 
-mod engine;
+/// Frames `headless` runs without `--frames`, about 50 seconds
+const DEFAULT_HEADLESS_FRAMES: u64 = 3000;
+/// Frames `test` gives a ROM without `--frames`, two minutes
+const DEFAULT_TEST_FRAMES: u64 = 7200;
+
+const USAGE: &str = "\
+Usage: rustboy [COMMAND] ROM [OPTIONS]
+
+Commands:
+  run                     Play in a window, the default
+  headless                Run without a window, taking screenshots
+  test                    Run a blargg or mooneye test ROM and report the result
+  info                    Show the cartridge header
+  disasm                  Disassemble the ROM to rgbds style assembly
+
+Running:
+  --frames N              Frames to run headless (3000), or before a test times out (7200)
+  --screenshot-every N    Headless screenshot every N frames, as well as after the last
+  --screenshot-at N,M...  Headless screenshots after these frames
+  --out-dir DIR           Where screenshots go (screenshots)
+  --save-dir DIR          Keep the .sav there instead of next to the ROM
+  --palette PALETTE       gray, green, pocket or four hex colours like e0f8d0,88c070,346856,081820
+  --scale N               Window size in Game Boy pixels, 1 to 20 (5)
+  --boot-rom FILE         Start from a 256 byte DMG boot ROM
+  --load-state FILE       Start from a snapshot written by --save-state
+  --save-state FILE       Write a snapshot of the whole machine at exit
+  --config FILE           Settings and key bindings instead of ~/.config/rustboy/config.ini
+  --dump                  Print registers, I/O and video memory at exit
+
+Input:
+  --record FILE           Record the joypad to a movie
+  --play FILE             Play the joypad back from a movie
+  --cheats FILE           Cheats instead of the .cht next to the ROM
+  --macros FILE           Input macros instead of the .macros next to the ROM
+  --block-opposite        Opposite directions held together read as neither
+
+Speed:
+  --turbo X               Turbo hotkey speed multiplier (2)
+  --unthrottled N         Run as fast as possible, drawing every Nth frame
+  --rewind-interval N     Frames between rewind snapshots (2)
+  --rewind-seconds N      How far back rewind goes (30)
+  --no-rewind             Turn rewind off
+
+Debugging:
+  --debug                 Start stopped in the command line debugger
+  --gdb PORT              Wait for gdb to connect on 127.0.0.1:PORT
+  --sym FILE              Labels instead of the .sym next to the ROM
+  --trace FILE            Log every instruction gameboy-doctor style
+  --trace-doctor          Make LY read 0x90 like gameboy-doctor's logs expect
+  --profile FILE          Write hot spots at exit
+  --profile-folded FILE   Write call stacks for flamegraph.pl at exit
+  --cdl                   Keep a code/data log next to the ROM
+
+Disassembly:
+  --reachable             Only code reached from the entry points
+  -o, --out FILE          Write it here instead of standard output
+
+  -h, --help              Show this
+
+Exit codes: 0 done or test passed, 1 error or test failed, 2 bad command line,
+3 test timed out.
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Headless,
+    Test,
+    Info,
+    Disasm
+}
+
+const COMMANDS: [(&str, Command); 5] = [
+    ("run", Command::Run),
+    ("headless", Command::Headless),
+    ("test", Command::Test),
+    ("info", Command::Info),
+    ("disasm", Command::Disasm)
+];
+
+struct Options {
+    command: Command,
+    rom_file: String,
+    help: bool,
+    /// frames to run headless, or before a test ROM times out
+    frames: Option<u64>,
+    /// take a headless screenshot every this many frames
+    screenshot_every: Option<u64>,
+    /// and after each of these
+    screenshot_at: Vec<u64>,
+    /// where screenshots go
+    out_dir: Option<PathBuf>,
+    /// where the .sav goes instead of next to the rom
+    save_dir: Option<PathBuf>,
+    palette: Option<Palette>,
+    scale: Option<u32>,
+    boot_rom: Option<String>,
+    /// snapshot to start from
+    load_state: Option<String>,
+    /// snapshot to write at exit
+    save_state: Option<String>,
+    /// print registers and video memory at exit
+    dump: bool,
+    /// write a gameboy-doctor log of every instruction here
+    trace_file: Option<String>,
+    /// make LY read 0x90 like gameboy-doctor expects
+    trace_doctor: bool,
+    /// start stopped in the command line debugger
+    debug: bool,
+    /// wait for gdb to connect on this port
+    gdb_port: Option<u16>,
+    /// labels to use instead of the .sym file next to the rom
+    sym_file: Option<String>,
+    /// write the profiler's hot spot report here at exit
+    profile_file: Option<String>,
+    /// write the profiler's call stacks here at exit, for flamegraph.pl
+    folded_file: Option<String>,
+    /// add to the code/data log next to the rom
+    cdl: bool,
+    /// cheats to use instead of the .cht file next to the rom
+    cheat_file: Option<String>,
+    /// input macros to use instead of the .macros file next to the rom
+    macro_file: Option<String>,
+    /// record the joypad to this movie
+    record_file: Option<String>,
+    /// play the joypad back from this movie
+    play_file: Option<String>,
+    /// frames between rewind snapshots, 0 turns rewind off
+    rewind_interval: u64,
+    /// how far back rewind goes
+    rewind_seconds: u64,
+    /// times normal speed when turbo is on
+    turbo: f64,
+    /// run as fast as possible, drawing one frame in this many
+    unthrottled: Option<u64>,
+    /// key bindings to use instead of the ones in the config directory
+    config_file: Option<String>,
+    /// opposite directions held together read as neither
+    block_opposite: bool,
+    /// disassemble only code reachable from the entry points
+    reachable: bool,
+    /// write the disassembly here instead of stdout
+    out_file: Option<String>
+}
+
+/// The next argument, which `option` needs
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    return args.next().ok_or(format!("{} needs a value", option));
+}
+
+fn number<T: FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> Result<T, String> {
+    let text = value(args, option)?;
+    return text.parse().map_err(|_| format!("{} needs a number, not {}", option, text));
+}
+
+/// A number more than 0
+fn positive(args: &mut impl Iterator<Item = String>, option: &str) -> Result<u64, String> {
+    let n = number(args, option)?;
+    return if n > 0 { Ok(n) } else { Err(format!("{} has to be more than 0", option)) };
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Run,
+        rom_file: String::new(),
+        help: false,
+        frames: None,
+        screenshot_every: None,
+        screenshot_at: vec![],
+        out_dir: None,
+        save_dir: None,
+        palette: None,
+        scale: None,
+        boot_rom: None,
+        load_state: None,
+        save_state: None,
+        dump: false,
+        trace_file: None,
+        trace_doctor: false,
+        debug: false,
+        gdb_port: None,
+        sym_file: None,
+        profile_file: None,
+        folded_file: None,
+        cdl: false,
+        cheat_file: None,
+        macro_file: None,
+        record_file: None,
+        play_file: None,
+        rewind_interval: 2,
+        rewind_seconds: 30,
+        turbo: 2.0,
+        unthrottled: None,
+        config_file: None,
+        block_opposite: false,
+        reachable: false,
+        out_file: None
+    };
+
+    let mut args = args.into_iter().peekable();
+    // no command runs the game, like it always has
+    if let Some((_, command)) = args.peek().and_then(|first| COMMANDS.iter().find(|(name, _)| name == first)) {
+        options.command = *command;
+        args.next();
+    }
+
+    let mut positional = vec![];
+    while let Some(arg) = args.next() {
+        let args = &mut args;
+        match arg.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--frames" => options.frames = Some(positive(args, &arg)?),
+            "--screenshot-every" => options.screenshot_every = Some(positive(args, &arg)?),
+            "--screenshot-at" => {
+                let list = value(args, &arg)?;
+                options.screenshot_at = list.split(',').map(|n| n.trim().parse::<u64>().map_err(|_| format!("--screenshot-at needs frame numbers, not {}", list)))
+                    .collect::<Result<Vec<u64>, String>>()?;
+            },
+            "--out-dir" => options.out_dir = Some(PathBuf::from(value(args, &arg)?)),
+            "--save-dir" => options.save_dir = Some(PathBuf::from(value(args, &arg)?)),
+            "--palette" => options.palette = Some(gpu::parse_palette(&value(args, &arg)?)?),
+            "--scale" => options.scale = Some(config::parse_scale(&value(args, &arg)?)?),
+            "--boot-rom" => options.boot_rom = Some(value(args, &arg)?),
+            "--load-state" => options.load_state = Some(value(args, &arg)?),
+            "--save-state" => options.save_state = Some(value(args, &arg)?),
+            "--dump" => options.dump = true,
+            "--trace" => options.trace_file = Some(value(args, &arg)?),
+            "--trace-doctor" => options.trace_doctor = true,
+            "--debug" => options.debug = true,
+            "--sym" => options.sym_file = Some(value(args, &arg)?),
+            "--profile" => options.profile_file = Some(value(args, &arg)?),
+            "--profile-folded" => options.folded_file = Some(value(args, &arg)?),
+            "--cdl" => options.cdl = true,
+            "--cheats" => options.cheat_file = Some(value(args, &arg)?),
+            "--macros" => options.macro_file = Some(value(args, &arg)?),
+            "--record" => options.record_file = Some(value(args, &arg)?),
+            "--play" => options.play_file = Some(value(args, &arg)?),
+            "--rewind-interval" => options.rewind_interval = number(args, &arg)?,
+            "--rewind-seconds" => options.rewind_seconds = number(args, &arg)?,
+            "--turbo" => options.turbo = Some(number(args, &arg)?).filter(|n: &f64| *n > 0.0).ok_or("--turbo has to be more than 0")?,
+            "--unthrottled" => options.unthrottled = Some(positive(args, &arg)?),
+            "--config" => options.config_file = Some(value(args, &arg)?),
+            "--no-rewind" => options.rewind_interval = 0,
+            "--block-opposite" => options.block_opposite = true,
+            "--gdb" => options.gdb_port = Some(number(args, &arg)?),
+            "--reachable" => options.reachable = true,
+            "-o" | "--out" => options.out_file = Some(value(args, &arg)?),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ => positional.push(arg)
+        }
+    }
+
+    if options.help {
+        return Ok(options);
+    }
+    if options.record_file.is_some() && options.play_file.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+    if options.cheat_file.is_some() && (options.play_file.is_some() || options.record_file.is_some()) {
+        return Err("Movies don't record cheats, --cheats can't be used with --record or --play".to_string());
+    }
+    if options.play_file.is_some() && options.load_state.is_some() {
+        return Err("--play starts from where the movie was recorded, it can't be used with --load-state".to_string());
+    }
+    match positional.len() {
+        0 => return Err("Need a ROM file".to_string()),
+        1 => options.rom_file = positional.remove(0),
+        _ => return Err(format!("Unexpected argument {}", positional[1]))
+    }
+    return Ok(options);
+}
 
 fn main() {
-    let rom_file = env::args().nth(1).expect("Need rom file!");
-    
-    let demo_mode = env::args().nth(2) == Some(String::from("TEST"));
+    let code = match parse_args(env::args().skip(1).collect()) {
+        Err(e) => {
+            eprintln!("rustboy: {}\nTry rustboy --help", e);
+            EXIT_USAGE
+        },
+        Ok(options) if options.help => {
+            print!("{}", USAGE);
+            EXIT_OK
+        },
+        Ok(options) => {
+            let res = match options.command {
+                Command::Info => info_main(&options),
+                Command::Disasm => disasm_main(&options),
+                Command::Run | Command::Headless | Command::Test => emulate(options)
+            };
+            res.unwrap_or_else(|e| {
+                eprintln!("rustboy: {}", e);
+                EXIT_FAILURE
+            })
+        }
+    };
+    process::exit(code);
+}
+
+fn read_rom(rom_file: &str) -> Result<Vec<u8>, String> {
+    return fs::read(rom_file).map_err(|e| format!("Couldn't read {}: {}", rom_file, e));
+}
+
+/// Symbols from `sym_file`, or the .sym next to the rom if there is one
+fn load_symbols(rom_file: &str, sym_file: Option<String>) -> Result<Option<Symbols>, String> {
+    // rgbds makes game.sym for game.gb
+    let sym_file = sym_file.unwrap_or(Path::new(rom_file).with_extension("sym").to_string_lossy().to_string());
+    if !Path::new(&sym_file).exists() {
+        return Ok(None);
+    }
+    eprintln!("Loading symbols from {}", sym_file);
+    return Symbols::load(Path::new(&sym_file)).map(Some).map_err(|e| format!("Couldn't read {}: {}", sym_file, e));
+}
+
+/// `rustboy info rom.gb`
+fn info_main(options: &Options) -> Result<i32, String> {
+    let rom = read_rom(&options.rom_file)?;
+    let header = RomHeader::parse(&rom)?;
+    let kib = |size: Option<usize>| size.map_or("unknown".to_string(), |size| format!("{} KiB", size / 1024));
+    let check = |ok: bool, expected: String| if ok { "ok".to_string() } else { format!("should be {}", expected) };
+
+    println!("Title:           {}", header.title);
+    println!("Cartridge:       {} (${:02X}){}", header.cartridge_name(), header.cartridge_type,
+             if header.is_supported() { "" } else { ", not supported" });
+    println!("ROM size:        {}, file is {} KiB", kib(header.rom_size()), rom.len() / 1024);
+    println!("RAM size:        {}", kib(header.ram_size()));
+    println!("Game Boy Color:  {}", match header.cgb_flag {
+        0xC0 => "only",
+        0x80 => "enhanced",
+        _ => "no"
+    });
+    println!("Super Game Boy:  {}", if header.sgb { "yes" } else { "no" });
+    println!("Licensee:        {}", header.licensee);
+    println!("Version:         {}", header.version);
+    println!("Header checksum: ${:02X} ({})", header.header_checksum,
+             check(header.header_checksum == header.computed_header_checksum, format!("${:02X}", header.computed_header_checksum)));
+    println!("Global checksum: ${:04X} ({})", header.global_checksum,
+             check(header.global_checksum == header.computed_global_checksum, format!("${:04X}", header.computed_global_checksum)));
+    println!("CRC-32:          {:08X}", movie::crc32(&rom));
+    return Ok(EXIT_OK);
+}
+
+/// `rustboy disasm rom.gb [--reachable] [--sym FILE] [-o FILE]`
+fn disasm_main(options: &Options) -> Result<i32, String> {
+    let rom = read_rom(&options.rom_file)?;
+    let symbols = load_symbols(&options.rom_file, options.sym_file.clone())?.unwrap_or_default();
+
+    let mut out: Box<dyn Write> = match &options.out_file {
+        Some(path) => Box::new(io::BufWriter::new(fs::File::create(path).map_err(|e| format!("Couldn't create {}: {}", path, e))?)),
+        None => Box::new(io::BufWriter::new(io::stdout()))
+    };
+    disasm::write_disassembly(&mut *out, &rom, &symbols, options.reachable)
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("Write failed: {}", e))?;
+    return Ok(EXIT_OK);
+}
 
+/// Where the battery backed RAM is kept, `game.gb.sav` next to the rom or in `--save-dir`
+fn save_path(options: &Options) -> PathBuf {
+    return match &options.save_dir {
+        Some(dir) => dir.join(format!("{}.sav", Path::new(&options.rom_file).file_name().unwrap_or_default().to_string_lossy())),
+        None => PathBuf::from(format!("{}.sav", options.rom_file))
+    };
+}
+
+/// `run`, `headless` and `test`
+fn emulate(options: Options) -> Result<i32, String> {
+    let rom_file = options.rom_file.clone();
     println!("Using file {}", rom_file);
 
-    let mut rom = Vec::<u8>::new();
+    let rom = read_rom(&rom_file)?;
+    let header = RomHeader::parse(&rom)?;
+    if !header.is_supported() {
+        return Err(format!("{} cartridges (${:02X}) aren't supported", header.cartridge_name(), header.cartridge_type));
+    }
 
-    let mut rom_file_ptr = fs::File::open(&rom_file).expect("Bad file name!");
+    let rom_size = rom.len();
+    let rom_crc = movie::crc32(&rom);
 
-    rom_file_ptr.read_to_end(&mut rom).expect("Couldn't read file");
+    let playback = match options.play_file.as_ref() {
+        Some(path) => {
+            let movie = Movie::load(Path::new(&path))?;
+            movie.check_rom(&rom)?;
+            println!("Playing {} frames of input from {}", movie.frames.len(), path);
+            Some(movie)
+        },
+        None => None
+    };
 
     let mut eng = engine::make_engine(rom);
 
-    let save_file_name = rom_file + ".sav";
-    if Path::new(&save_file_name).exists() {
-        println!("Loading save from {}", save_file_name);
-        let mut sav_ram = Vec::<u8>::new();
+    if let Some(path) = options.boot_rom.as_ref() {
+        println!("Starting from boot ROM {}", path);
+        eng.load_boot_rom(fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?)?;
+    }
+
+    if let Some(symbols) = load_symbols(&rom_file, options.sym_file.clone())? {
+        eng.symbols = symbols;
+    }
+
+    if let Some(trace_file) = options.trace_file.as_ref() {
+        println!("Tracing to {}", trace_file);
+        eng.tracer = Some(Tracer::make_tracer(Path::new(trace_file), options.trace_doctor)
+            .map_err(|e| format!("Couldn't create {}: {}", trace_file, e))?);
+    }
+
+    if options.debug {
+        println!("Debugger started, type help for commands");
+        eng.debugger = Some(Debugger::make_debugger());
+    }
+
+    if options.profile_file.is_some() || options.folded_file.is_some() {
+        eng.profiler = Some(Profiler::make_profiler());
+    }
+
+    let cheat_file = options.cheat_file.clone().unwrap_or(Path::new(&rom_file).with_extension("cht").to_string_lossy().to_string());
+    let movie = options.play_file.is_some() || options.record_file.is_some();
+    if movie && Path::new(&cheat_file).exists() {
+        // cheats change what the game sees without going through the joypad, so a replay
+        // would go out of sync
+        println!("Not loading cheats from {} with a movie", cheat_file);
+    } else if Path::new(&cheat_file).exists() {
+        let cheats = Cheats::load(Path::new(&cheat_file))?;
+        println!("Loaded {} cheats from {}, F1-F9 turn them on and off", cheats.cheats.len(), cheat_file);
+        eng.set_cheats(cheats);
+    }
+
+    // new recordings are added to the file, making it if needed
+    let macro_file = options.macro_file.clone().map(PathBuf::from).unwrap_or(Path::new(&rom_file).with_extension("macros"));
+    if macro_file.exists() {
+        eng.buttons.layer.macros = input::load_macros(&macro_file)?;
+        println!("Loaded {} macros from {}", eng.buttons.layer.macros.len(), macro_file.display());
+    }
+    eng.buttons.layer.macro_file = Some(macro_file);
+
+    let cdl_file = Path::new(&rom_file).with_extension("cdl");
+    if options.cdl {
+        println!("Logging code and data to {}", cdl_file.display());
+        eng.cdl = Some(CodeDataLog::load(&cdl_file, rom_size).map_err(|e| format!("Couldn't read {}: {}", cdl_file.display(), e))?);
+    }
+
+    if let Some(port) = options.gdb_port {
+        println!("Waiting for gdb on 127.0.0.1:{}", port);
+        eng.gdb = Some(GdbStub::listen(port).map_err(|e| format!("Couldn't start gdb server: {}", e))?);
+        println!("gdb connected");
+    }
+
+    let save_file = save_path(&options);
+    if save_file.exists() {
+        println!("Loading save from {}", save_file.display());
+        eng.memory.load(fs::read(&save_file).map_err(|e| format!("Couldn't read {}: {}", save_file.display(), e))?);
+    }
+
+    // replaces the .sav and everything else
+    if let Some(path) = options.load_state.as_ref() {
+        println!("Loading state from {}", path);
+        let state = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        eng.load_state(&state).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    // after the .sav and --load-state so a recording can start from them
+    if let Some(movie) = playback {
+        eng.start_movie(movie)?;
+    } else if let Some(path) = options.record_file.as_ref() {
+        println!("Recording input to {}", path);
+        let movie = match options.load_state {
+            Some(_) => Movie::make_state_recording(rom_crc, eng.save_state()),
+            None => Movie::make_recording(rom_crc, eng.memory.save())
+        };
+        eng.start_movie(movie)?;
+    }
 
-        let mut sav_ram_file_ptr = fs::File::open(&save_file_name).expect("Bad save file name!");
+    let config_file = options.config_file.clone().map(PathBuf::from).or(Config::default_path().filter(|path| path.exists()));
+    if let Some(path) = config_file {
+        println!("Loading key bindings from {}", path.display());
+        eng.config = Config::load(&path)?;
+    }
+    if let Some(scale) = options.scale {
+        eng.config.scale = scale;
+    }
+    if let Some(palette) = options.palette {
+        eng.config.palette = palette;
+    }
+    if let Some(dir) = options.out_dir.as_ref() {
+        eng.config.screenshot_dir = dir.clone();
+    }
+    eng.gpu.palette = eng.config.palette;
 
-        sav_ram_file_ptr.read_to_end(&mut sav_ram).expect("Couldn't read save file");
-        eng.memory.load(sav_ram);
+    eng.speed.turbo_multiplier = options.turbo;
+    eng.buttons.block_opposite = options.block_opposite;
+    if let Some(draw_every) = options.unthrottled {
+        eng.speed.unthrottled = true;
+        eng.speed.draw_every = draw_every;
     }
 
-    if demo_mode {
-        println!("Running headless demo mode");
-        fs::create_dir_all("screenshots/");
-        
-        for i in 0..50{
-            eng.run_limited(1000000);
-            //println!("{} of 50 done", i);
-            eng.screenshot(Path::new("screenshots/screenshot.bmp"));
+    // rewinding would throw a movie out of sync
+    if options.command == Command::Run && eng.movie.is_none() && options.rewind_interval > 0 {
+        let snapshots = options.rewind_seconds * 60 / options.rewind_interval;
+        eng.rewind = Some(Rewind::make_rewind(options.rewind_interval, std::cmp::max(snapshots, 1) as usize));
+    }
+
+    let code = match options.command {
+        Command::Headless => run_headless(&mut eng, &options)?,
+        Command::Test => run_test(&mut eng, &options),
+        _ => {
+            eng.run();
+            EXIT_OK
+        }
+    };
+
+    if options.dump {
+        print_dump(&eng);
+    }
+
+    if let Some(tracer) = eng.tracer.as_mut() {
+        tracer.flush();
+    }
+
+    if let Some(profiler) = eng.profiler.as_ref() {
+        let create = |path: &String| fs::File::create(path).map(io::BufWriter::new).map_err(|e| format!("Couldn't create {}: {}", path, e));
+        if let Some(path) = options.profile_file.as_ref() {
+            println!("Writing profile to {}", path);
+            profiler.write_report(&mut create(path)?, &eng.symbols).map_err(|e| format!("Write failed: {}", e))?;
+        }
+        if let Some(path) = options.folded_file.as_ref() {
+            println!("Writing call stacks to {}", path);
+            profiler.write_folded(&mut create(path)?, &eng.symbols).map_err(|e| format!("Write failed: {}", e))?;
         }
+    }
+
+    if let Some(log) = eng.cdl.as_ref() {
+        println!("Code/data log: {} of {} rom bytes run as code, {} read as data",
+                 log.count(cdl::OPCODE | cdl::OPERAND), rom_size, log.count(cdl::DATA | cdl::DMA));
+        log.save(&cdl_file).map_err(|e| format!("Couldn't write {}: {}", cdl_file.display(), e))?;
+    }
+
+    if let (Some(movie), Some(path)) = (eng.movie.as_ref(), options.record_file.as_ref()) {
+        movie.save(Path::new(path))?;
+        println!("Saved {} frames of input to {}", movie.frames.len(), path);
+    }
+
+    if let Some(path) = options.save_state.as_ref() {
+        fs::write(path, eng.save_state()).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+        println!("Saved state to {}", path);
+    }
+
+    let to_save = eng.memory.save();
+
+    if options.play_file.is_some() {
+        // a replay shouldn't change the player's save
+        println!("Not saving after a movie");
+    } else if options.command == Command::Test {
+        println!("Not saving after a test");
+    } else if to_save.len() == 0 {
+        println!("Nothing to save");
     } else {
-        eng.run(false);
+        println!("Saving to {}", save_file.display());
+        if let Some(dir) = options.save_dir.as_ref() {
+            fs::create_dir_all(dir).map_err(|e| format!("Couldn't make {}: {}", dir.display(), e))?;
+        }
+        fs::write(&save_file, &to_save).map_err(|e| format!("Couldn't write {}: {}", save_file.display(), e))?;
     }
+    return Ok(code);
+}
 
-    print!("\nLCD Control\n{:#010b}", eng.memory.get(0xFF40));
+/// Runs `--frames` frames without a window, with screenshots on the schedule and at the end.
+/// A movie running out or the debugger quitting stops it early.
+fn run_headless(eng: &mut Engine, options: &Options) -> Result<i32, String> {
+    let frames = options.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES);
+    let out_dir = eng.config.screenshot_dir.clone();
+    fs::create_dir_all(&out_dir).map_err(|e| format!("Couldn't make {}: {}", out_dir.display(), e))?;
+    let name = Path::new(&options.rom_file).file_stem().unwrap_or_default().to_string_lossy().to_string();
 
-    print!("\nLCD Stat\n{:#010b}", eng.memory.get(0xFF41));
+    println!("Running {} frames headless", frames);
+    for done in 1..=frames {
+        eng.run_frames(1);
+        let mut stop = false;
+        for event in eng.take_events() {
+            if event == EngineEvent::Quit {
+                stop = true;
+                continue;
+            }
+            println!("{}", event);
+            if let EngineEvent::MovieFinished { .. } = event {
+                stop = true;
+            }
+        }
+
+        let scheduled = options.screenshot_every.map_or(false, |every| done % every == 0) || options.screenshot_at.contains(&done);
+        if scheduled || stop || done == frames {
+            let path = out_dir.join(format!("{}-{}.bmp", name, done));
+            eng.screenshot(&path).map_err(|e| format!("Couldn't write {}: {}", path.display(), e))?;
+            println!("Saved {}", path.display());
+        }
+        if stop {
+            break;
+        }
+    }
+    return Ok(EXIT_OK);
+}
+
+/// Runs a test ROM until it reports a result or `--frames` go by
+fn run_test(eng: &mut Engine, options: &Options) -> i32 {
+    let frames = options.frames.unwrap_or(DEFAULT_TEST_FRAMES);
+    let mut watcher = TestWatcher::make_test_watcher();
+    let result = watcher.run(eng, frames);
+    if !watcher.serial.is_empty() {
+        println!("{}", watcher.serial.trim_end());
+    }
+    return match result {
+        TestResult::Passed => {
+            println!("Passed after {} frames", eng.gpu.frames);
+            EXIT_OK
+        },
+        TestResult::Failed(why) => {
+            // serial output says why itself
+            if watcher.serial.is_empty() {
+                println!("Failed: {}", why);
+            } else {
+                println!("Failed");
+            }
+            EXIT_FAILURE
+        },
+        TestResult::Timeout => {
+            println!("Timed out after {} frames", frames);
+            EXIT_TIMEOUT
+        }
+    };
+}
+
+/// Registers, I/O and video memory, for `--dump`
+fn print_dump(eng: &Engine) {
     print!("\nLCD Control\n{:#010b}", eng.memory.get(0xFF40));
+    print!("\nLCD Stat\n{:#010b}", eng.memory.get(0xFF41));
     print!("\nLCD Scroll Y\n{}", eng.memory.get(0xFF42));
     print!("\nLCD Scroll X\n{}", eng.memory.get(0xFF43));
     print!("\nLCD Current Y\n{}", eng.memory.get(0xFF44));
@@ -83,31 +696,14 @@ fn main() {
 
     print!("\nRegisters\n");
     print!("{:?}", eng.registers);
-
-
-    print!("\nImage:\n");
-    for i in 0..144 {
-        //println!("{:?}", eng.gpu.lcd[i]);
-    }
+    print!("\n{}: {}", eng.describe_addr(eng.registers.pc), eng.disassemble(eng.registers.pc).0);
 
     println!("\nTimer\n");
     println!("{:x?}, {:x?}", eng.memory.get(0xFF06), eng.memory.get(0xFF07));
 
-
     println!("\nInterrupts\n");
     println!("{:x?} x {:x?}", eng.memory.get(0xFF0F), eng.memory.get(0xFFFF));
-    println!("{:?}", eng.enable_interrupt);
+    println!("{:?}", eng.interrupts);
 
     println!("\nKeys\n{:#010b}", eng.memory.get(0xFF00));
-
-    let to_save = eng.memory.save();
-
-    if to_save.len() == 0 {
-        println!("Nothing to save");
-    } else {
-        println!("Saving state");
-        let mut out_file = fs::File::create(save_file_name).expect("Couldn't save!");
-        out_file.write_all(&to_save).expect("Write failed");
-        println!("Done");
-    }
 }
@@ -0,0 +1,75 @@
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+use rustboy::engine::engine::Engine;
+
+/// Grayscale image, screenshots are compared by brightness only
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub luma: Vec<u8>
+}
+
+impl Image {
+    pub fn from_engine(eng: &Engine) -> Image {
+        return Image {
+            width: 160,
+            height: 144,
+            luma: eng.gpu.lcd.iter().flat_map(|line| line.iter().cloned()).collect()
+        };
+    }
+
+    pub fn read_png(path: &Path) -> Result<Image, String> {
+        let file = fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+
+        let channels = info.color_type.samples();
+        let mut luma = Vec::with_capacity((info.width * info.height) as usize);
+        for y in 0..info.height as usize {
+            let line = &buf[y * info.line_size..];
+            for x in 0..info.width as usize {
+                let px = &line[x * channels..];
+                luma.push(match channels {
+                    1 | 2 => px[0],
+                    _ => ((px[0] as u32 * 299 + px[1] as u32 * 587 + px[2] as u32 * 114) / 1000) as u8
+                });
+            }
+        }
+        return Ok(Image { width: info.width, height: info.height, luma: luma });
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        return write_png(path, self.width, self.height, png::ColorType::Grayscale, &self.luma);
+    }
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, color: png::ColorType, data: &[u8]) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    return writer.write_image_data(data).map_err(|e| e.to_string());
+}
+
+/// Number of pixels whose brightness differs by more than `tolerance`, along with an RGB
+/// image of the expected screen faded out and those pixels in red
+pub fn diff(actual: &Image, expected: &Image, tolerance: u8) -> (usize, Vec<u8>) {
+    let mut mismatches = 0;
+    let mut res = Vec::with_capacity(expected.luma.len() * 3);
+    for (a, e) in actual.luma.iter().zip(expected.luma.iter()) {
+        if (*a as i16 - *e as i16).abs() > tolerance as i16 {
+            mismatches += 1;
+            res.extend_from_slice(&[255, 0, 0]);
+        } else {
+            let faded = 192 + e / 4;
+            res.extend_from_slice(&[faded, faded, faded]);
+        }
+    }
+    return (mismatches, res);
+}
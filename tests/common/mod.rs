@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+
+pub mod image;
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub fn load_rom(path: &Path) -> Vec<u8> {
+    let mut rom = Vec::<u8>::new();
+    fs::File::open(path).expect("Bad rom file!").read_to_end(&mut rom).expect("Couldn't read rom");
+    return rom;
+}
+
+/// Every `.gb` file under `dir`, sorted so reports come out in a stable order
+pub fn rom_files(dir: &Path) -> Vec<PathBuf> {
+    let mut res = vec![];
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return res
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            res.extend(rom_files(&path));
+        } else if path.extension().map_or(false, |ext| ext == "gb") {
+            res.push(path);
+        }
+    }
+    res.sort();
+    return res;
+}
+
+/// Directory from `var` if set, otherwise `default` relative to the crate root
+pub fn test_dir(var: &str, default: &str) -> PathBuf {
+    if let Ok(dir) = std::env::var(var) {
+        return PathBuf::from(dir);
+    }
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(default);
+}
@@ -0,0 +1,144 @@
+//! Runs the mooneye-test-suite ROMs headlessly. `TestWatcher` decides when a test is done: at
+//! `LD B,B` it passed if B/C/D/E/H/L hold the Fibonacci numbers 3/5/8/13/21/34 and failed if
+//! they are all 0x42.
+//!
+//! The built ROMs aren't checked in, so the test is ignored by default. Put them in
+//! `tests/mooneye-test-suite` or point `MOONEYE_TESTS` at them and run
+//! `cargo test --test mooneye -- --ignored`. Only the `acceptance` and `emulator-only` ROMs
+//! meant for DMG-ABC are run, `MOONEYE_ONLY=timer` limits that to paths containing the given text.
+//! `MOONEYE_TIMEOUT` is the emulated seconds a ROM gets before it counts as hung.
+//!
+//! ROMs we know we fail are listed in `mooneye_known_failures.txt`, the test only fails
+//! when a result differs from that list. `BLESS_MOONEYE=1` rewrites the list with the ROMs
+//! that failed this run. Every run writes a markdown table of results.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustboy::engine;
+use rustboy::engine::testrom::{TestWatcher, TestResult};
+
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+const DEFAULT_TIMEOUT: u64 = 10;
+
+fn run_rom(path: &Path, timeout: u64) -> (TestResult, u64) {
+    let mut eng = engine::make_engine(common::load_rom(path));
+    let mut watcher = TestWatcher::make_test_watcher();
+    let mut cycles = 0;
+
+    while cycles < timeout * CYCLES_PER_SECOND {
+        if let Some(result) = watcher.check(&mut eng) {
+            return (result, cycles);
+        }
+        cycles += eng.run_limited(1);
+    }
+    return (TestResult::Timeout, cycles);
+}
+
+/// Mooneye names model specific ROMs like `boot_regs-dmgABC` or `di_timing-GS`
+fn runs_on_dmg(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    return match stem.rfind('-') {
+        Some(i) => stem[i + 1..].contains("dmgABC") || stem[i + 1..].contains('G'),
+        None => true
+    };
+}
+
+fn known_failures_path() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mooneye_known_failures.txt");
+}
+
+fn known_failures() -> Vec<String> {
+    let list = fs::read_to_string(known_failures_path()).unwrap_or_default();
+    return list.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect();
+}
+
+fn report_path() -> PathBuf {
+    if let Ok(path) = env::var("MOONEYE_REPORT") {
+        return PathBuf::from(path);
+    }
+    return PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mooneye.md");
+}
+
+/// Replaces the known failures with `failed`, keeping the comments at the top and the entries
+/// for ROMs that weren't run
+fn bless(known: &[String], ran: &[String], failed: &[String]) {
+    let text = fs::read_to_string(known_failures_path()).unwrap_or_default();
+    let mut res: String = text.lines().take_while(|line| line.starts_with('#')).map(|line| format!("{}\n", line)).collect();
+    let mut names: Vec<&String> = known.iter().filter(|name| !ran.contains(name)).chain(failed.iter()).collect();
+    names.sort();
+    for name in names {
+        res.push_str(&format!("{}\n", name));
+    }
+    fs::write(known_failures_path(), res).expect("Couldn't write known failures");
+}
+
+#[test]
+#[ignore = "needs the mooneye-test-suite ROMs, see tests/mooneye.rs"]
+fn mooneye_acceptance() {
+    let dir = common::test_dir("MOONEYE_TESTS", "tests/mooneye-test-suite");
+    assert!(dir.is_dir(), "No mooneye ROMs in {}, put them there or point MOONEYE_TESTS at them", dir.display());
+
+    let timeout = env::var("MOONEYE_TIMEOUT").ok().and_then(|t| t.parse().ok()).unwrap_or(DEFAULT_TIMEOUT);
+    let only = env::var("MOONEYE_ONLY").unwrap_or_default();
+    let known = known_failures();
+
+    let mut table = String::from("| ROM | Result | Cycles |\n|-----|--------|--------|\n");
+    let mut unexpected = vec![];
+    let mut ran = vec![];
+    let mut failed = vec![];
+    let mut passed = 0;
+    let mut total = 0;
+
+    for suite in ["acceptance", "emulator-only"].iter() {
+        for path in common::rom_files(&dir.join(suite)) {
+            let name = path.strip_prefix(&dir).unwrap().to_string_lossy().to_string();
+            if !runs_on_dmg(&path) || !name.contains(&only) {
+                continue;
+            }
+
+            let (outcome, cycles) = run_rom(&path, timeout);
+            let result = match &outcome {
+                TestResult::Passed => String::from("pass"),
+                TestResult::Failed(why) => format!("FAIL ({})", why),
+                TestResult::Timeout => String::from("FAIL (timeout)")
+            };
+            table.push_str(&format!("| {} | {} | {} |\n", name, result, cycles));
+            println!("{:<60} {}", name, result);
+
+            total += 1;
+            ran.push(name.clone());
+            if outcome != TestResult::Passed {
+                failed.push(name.clone());
+            }
+            let is_known = known.contains(&name);
+            if outcome == TestResult::Passed {
+                passed += 1;
+                if is_known {
+                    unexpected.push(format!("{} passes now, remove it from the known failures", name));
+                }
+            } else if !is_known {
+                unexpected.push(format!("{} {}", name, result));
+            }
+        }
+    }
+
+    table.push_str(&format!("\n{} of {} passed\n", passed, total));
+    let report = report_path();
+    fs::write(&report, &table).expect("Couldn't write report");
+    println!("{} of {} passed, table in {}", passed, total, report.display());
+
+    if env::var("BLESS_MOONEYE").is_ok() {
+        bless(&known, &ran, &failed);
+        println!("Wrote {} known failures to {}", failed.len(), known_failures_path().display());
+        return;
+    }
+    assert!(unexpected.is_empty(), "Unexpected results:\n{}", unexpected.join("\n"));
+}
@@ -0,0 +1,78 @@
+//! Runs test ROMs for a fixed number of frames and compares the screen with a reference
+//! PNG stored next to the ROM (`rom.gb.png`). Pixels may differ in brightness by
+//! `PIXEL_TOLERANCE` so references from other emulators with slightly different shades work.
+//!
+//! On a mismatch the actual screen and a diff (mismatched pixels in red) are written to
+//! `target/tmp/screenshots/`. Run with `BLESS_SCREENSHOTS=1` to overwrite the references
+//! with what the emulator currently draws.
+//!
+//! ROMs that aren't checked in (dmg-acid2) are skipped when missing.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::image::{self, Image};
+use rustboy::engine;
+
+const PIXEL_TOLERANCE: u8 = 16;
+
+fn check_screenshot(name: &str, rom: &str, frames: u64) {
+    let rom_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(rom);
+    if !rom_path.exists() {
+        println!("Skipping {}, {} is missing", name, rom_path.display());
+        return;
+    }
+    let reference = PathBuf::from(format!("{}.png", rom_path.display()));
+
+    let mut eng = engine::make_engine(common::load_rom(&rom_path));
+    eng.run_frames(frames);
+    let actual = Image::from_engine(&eng);
+
+    if env::var("BLESS_SCREENSHOTS").is_ok() {
+        actual.write_png(&reference).expect("Couldn't write reference");
+        println!("Blessed {}", reference.display());
+        return;
+    }
+
+    let expected = Image::read_png(&reference)
+        .unwrap_or_else(|e| panic!("No reference for {} ({}), run with BLESS_SCREENSHOTS=1 to make one", name, e));
+    assert_eq!((expected.width, expected.height), (actual.width, actual.height), "{} has the wrong size", reference.display());
+
+    let (mismatches, diff) = image::diff(&actual, &expected, PIXEL_TOLERANCE);
+    if mismatches > 0 {
+        let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("screenshots");
+        fs::create_dir_all(&out_dir).expect("Couldn't make output dir");
+        let actual_path = out_dir.join(format!("{}.png", name));
+        let diff_path = out_dir.join(format!("{}.diff.png", name));
+        actual.write_png(&actual_path).expect("Couldn't write screenshot");
+        image::write_png(&diff_path, actual.width, actual.height, png::ColorType::Rgb, &diff).expect("Couldn't write diff");
+        panic!("{} differs from {} in {} pixels, see {} and {}",
+            name, reference.display(), mismatches, actual_path.display(), diff_path.display());
+    }
+}
+
+macro_rules! screenshot_test {
+    ($name:ident, $rom:expr, $frames:expr) => {
+        #[test]
+        fn $name() {
+            check_screenshot(stringify!($name), $rom, $frames);
+        }
+    };
+}
+
+screenshot_test!(dmg_acid2, "tests/dmg-acid2/dmg-acid2.gb", 10);
+
+screenshot_test!(blargg_01_special, "tests/blargg-gb/cpu_instrs/individual/01-special.gb", 200);
+screenshot_test!(blargg_02_interrupts, "tests/blargg-gb/cpu_instrs/individual/02-interrupts.gb", 60);
+screenshot_test!(blargg_03_op_sp_hl, "tests/blargg-gb/cpu_instrs/individual/03-op sp,hl.gb", 200);
+screenshot_test!(blargg_04_op_r_imm, "tests/blargg-gb/cpu_instrs/individual/04-op r,imm.gb", 220);
+screenshot_test!(blargg_05_op_rp, "tests/blargg-gb/cpu_instrs/individual/05-op rp.gb", 300);
+screenshot_test!(blargg_06_ld_r_r, "tests/blargg-gb/cpu_instrs/individual/06-ld r,r.gb", 60);
+screenshot_test!(blargg_07_jr_jp_call_ret_rst, "tests/blargg-gb/cpu_instrs/individual/07-jr,jp,call,ret,rst.gb", 80);
+screenshot_test!(blargg_08_misc_instrs, "tests/blargg-gb/cpu_instrs/individual/08-misc instrs.gb", 60);
+screenshot_test!(blargg_09_op_r_r, "tests/blargg-gb/cpu_instrs/individual/09-op r,r.gb", 700);
+screenshot_test!(blargg_10_bit_ops, "tests/blargg-gb/cpu_instrs/individual/10-bit ops.gb", 1000);
+screenshot_test!(blargg_11_op_a_hl, "tests/blargg-gb/cpu_instrs/individual/11-op a,(hl).gb", 1250);
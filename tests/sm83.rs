@@ -0,0 +1,211 @@
+//! Runs the community SM83 single step test vectors (one JSON file per opcode, e.g. `00.json`,
+//! `cb 00.json`) against the CPU. Each test gives the initial and final registers and RAM plus
+//! the bus activity of every M-cycle.
+//!
+//! As the vectors' README describes, each test starts with the opcode already fetched: PC is
+//! one past it, and the last M-cycle is the fetch of the next opcode, which leaves the final PC
+//! one past that.
+//!
+//! The vectors aren't checked in, so the test is ignored by default. Put them in
+//! `tests/sm83/v1` or point `SM83_TESTS` at them and run `cargo test --test sm83 -- --ignored`.
+//! `SM83_ONLY=cb` limits the run to files whose name contains the given text.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use rustboy::engine;
+use rustboy::engine::engine::Engine;
+use rustboy::engine::memory::{BusAccess, FlatMemory};
+
+// HALT and STOP check IF / IE through the bus and don't finish within one step,
+// so for these only the register and RAM state is compared
+const NO_BUS_CHECK: [&str; 2] = ["10", "76"];
+
+fn field(state: &Value, name: &str) -> Result<u16, String> {
+    return state.get(name).and_then(|v| v.as_u64()).map(|v| v as u16).ok_or(format!("missing {}", name));
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    let mut res = vec![];
+    if let Some(entries) = state.get("ram").and_then(|v| v.as_array()) {
+        for entry in entries {
+            if let Some(pair) = entry.as_array() {
+                res.push((pair[0].as_u64().unwrap_or(0) as u16, pair[1].as_u64().unwrap_or(0) as u8));
+            }
+        }
+    }
+    return res;
+}
+
+/// Reads and writes from the `cycles` array, internal cycles are `null` or have no r/w pin
+fn expected_accesses(cycles: &[Value]) -> Vec<BusAccess> {
+    let mut res = vec![];
+    for cycle in cycles {
+        let parts = match cycle.as_array() {
+            Some(parts) if parts.len() == 3 => parts,
+            _ => continue
+        };
+        let pins = parts[2].as_str().unwrap_or("");
+        let write = pins.contains('w');
+        if !write && !pins.contains('r') {
+            continue;
+        }
+        res.push(BusAccess {
+            addr: parts[0].as_u64().unwrap_or(0) as u16,
+            value: parts[1].as_u64().unwrap_or(0) as u8,
+            write: write
+        });
+    }
+    return res;
+}
+
+fn set_state(eng: &mut Engine, state: &Value) -> Result<(), String> {
+    eng.registers.a = field(state, "a")? as u8;
+    eng.registers.b = field(state, "b")? as u8;
+    eng.registers.c = field(state, "c")? as u8;
+    eng.registers.d = field(state, "d")? as u8;
+    eng.registers.e = field(state, "e")? as u8;
+    eng.registers.f = field(state, "f")? as u8;
+    eng.registers.h = field(state, "h")? as u8;
+    eng.registers.l = field(state, "l")? as u8;
+    eng.registers.sp = field(state, "sp")?;
+    eng.registers.pc = field(state, "pc")?;
+    eng.interrupts.ime = field(state, "ime").unwrap_or(0) != 0;
+    eng.interrupts.ime_pending = field(state, "ei").unwrap_or(0) != 0;
+
+    if let Ok(ie) = field(state, "ie") {
+        eng.memory.set(0xFFFF, ie as u8);
+    }
+    for (addr, value) in ram(state) {
+        eng.memory.set(addr, value);
+    }
+    return Ok(());
+}
+
+fn check_state(eng: &Engine, state: &Value) -> Vec<String> {
+    let mut diffs = vec![];
+    let regs = [
+        ("a", eng.registers.a as u16), ("b", eng.registers.b as u16),
+        ("c", eng.registers.c as u16), ("d", eng.registers.d as u16),
+        ("e", eng.registers.e as u16), ("f", eng.registers.f as u16),
+        ("h", eng.registers.h as u16), ("l", eng.registers.l as u16),
+        ("sp", eng.registers.sp), ("pc", eng.registers.pc.wrapping_add(1))
+    ];
+    for (name, ours) in regs.iter() {
+        match field(state, name) {
+            Ok(expected) if expected != *ours => diffs.push(format!("{} {:04X} != {:04X}", name, ours, expected)),
+            _ => {}
+        }
+    }
+
+    // without an "ei" field a pending EI counts as enabled
+    let ime = match state.get("ei") {
+        Some(_) => eng.interrupts.ime,
+        None => eng.interrupts.ime || eng.interrupts.ime_pending
+    };
+    if let Ok(expected) = field(state, "ime") {
+        if ime != (expected != 0) {
+            diffs.push(format!("ime {} != {}", ime, expected != 0));
+        }
+    }
+    if let Ok(expected) = field(state, "ei") {
+        if eng.interrupts.ime_pending != (expected != 0) {
+            diffs.push(format!("ei {} != {}", eng.interrupts.ime_pending, expected != 0));
+        }
+    }
+
+    for (addr, expected) in ram(state) {
+        let ours = eng.memory.get(addr);
+        if ours != expected {
+            diffs.push(format!("[{:04X}] {:02X} != {:02X}", addr, ours, expected));
+        }
+    }
+    return diffs;
+}
+
+/// Runs one test case, returns what didn't match
+fn run_test(test: &Value, opcode_name: &str) -> Result<(), String> {
+    let initial = test.get("initial").ok_or("missing initial")?;
+    let expected = test.get("final").ok_or("missing final")?;
+    let cycles = test.get("cycles").and_then(|c| c.as_array()).ok_or("missing cycles")?;
+
+    let memory = FlatMemory::make_memory();
+    let accesses = memory.accesses.clone();
+    let mut eng = engine::make_engine_with_memory(Box::new(memory));
+    eng.cpu_only = true;
+    set_state(&mut eng, initial)?;
+
+    // we fetch the opcode ourselves, so start at it and drop our fetch; their fetch of the next
+    // opcode happens in the same M-cycle as our last one, so the cycle counts still line up
+    eng.registers.pc = eng.registers.pc.wrapping_sub(1);
+
+    accesses.borrow_mut().clear();
+    let clock_cycles = eng.step_cpu();
+    let mut ours = accesses.borrow().clone();
+    let mut theirs = expected_accesses(cycles);
+    ours.remove(0);
+    theirs.pop();
+
+    let mut diffs = check_state(&eng, expected);
+    if !NO_BUS_CHECK.contains(&opcode_name) {
+        if clock_cycles as usize != cycles.len() * 4 {
+            diffs.push(format!("{} cycles != {}", clock_cycles, cycles.len() * 4));
+        }
+        if ours != theirs {
+            diffs.push(format!("bus {:?} != {:?}", ours, theirs));
+        }
+    }
+
+    if diffs.is_empty() {
+        return Ok(());
+    }
+    return Err(diffs.join(", "));
+}
+
+#[test]
+#[ignore = "needs the SM83 test vectors, see tests/sm83.rs"]
+fn sm83_single_step() {
+    let dir = common::test_dir("SM83_TESTS", "tests/sm83/v1");
+    assert!(dir.is_dir(), "No SM83 test vectors in {}, put them there or point SM83_TESTS at them", dir.display());
+
+    let only = env::var("SM83_ONLY").unwrap_or_default();
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir).expect("Couldn't read test dir")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .filter(|path| path.file_name().unwrap().to_string_lossy().contains(&only))
+        .collect();
+    files.sort();
+
+    let mut failed_opcodes = vec![];
+    let mut total = 0;
+    for path in files.iter() {
+        let opcode_name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let text = fs::read_to_string(path).expect("Couldn't read test file");
+        let tests: Value = serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+
+        let mut failures = 0;
+        let mut first_failure = String::new();
+        for test in tests.as_array().expect("Expected a list of tests") {
+            total += 1;
+            if let Err(msg) = run_test(test, &opcode_name) {
+                if failures == 0 {
+                    let name = test.get("name").and_then(|n| n.as_str()).unwrap_or("?");
+                    first_failure = format!("{}: {}", name, msg);
+                }
+                failures += 1;
+            }
+        }
+
+        if failures > 0 {
+            println!("{:>8}: {} failed, first {}", opcode_name, failures, first_failure);
+            failed_opcodes.push(opcode_name);
+        }
+    }
+
+    println!("Ran {} tests from {} files", total, files.len());
+    assert!(failed_opcodes.is_empty(), "Failing opcodes: {}", failed_opcodes.join(" "));
+}